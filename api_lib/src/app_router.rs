@@ -0,0 +1,694 @@
+use axum::{
+    middleware,
+    routing::{delete, get, post, put},
+    Router,
+};
+use user_lib::{user_api_key::ApiKeyScope, user_models::Role};
+
+use crate::{app_state::AppState, auth_guard, auth_routes, totp_routes, user_routes, word_routes};
+
+/// Every route of the service, with the guards in front of them.
+///
+/// Health checks, tracing and the fallback are left to the binary.
+pub fn app_router(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/api/words",
+            post(word_routes::create_word).route_layer(middleware::from_fn_with_state(
+                Role::Moderator,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/wotd/pins/:date",
+            put(word_routes::pin_wotd)
+                .delete(word_routes::unpin_wotd)
+                .route_layer(middleware::from_fn_with_state(
+                    Role::Moderator,
+                    auth_guard::require_role,
+                )),
+        )
+        .route(
+            "/api/wotd/queue",
+            get(word_routes::get_queue).route_layer(middleware::from_fn_with_state(
+                Role::Moderator,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/wotd/queue/:id",
+            get(word_routes::get_queue_item)
+                .delete(word_routes::delete_queue_item)
+                .route_layer(middleware::from_fn_with_state(
+                    Role::Moderator,
+                    auth_guard::require_role,
+                )),
+        )
+        .route(
+            "/api/wotd/queue/:id/position",
+            put(word_routes::move_queue_item_to).route_layer(middleware::from_fn_with_state(
+                Role::Moderator,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/users/me",
+            get(user_routes::get_me)
+                .patch(user_routes::update_me)
+                .delete(user_routes::delete_me),
+        )
+        .route(
+            "/api/users/me/password",
+            put(user_routes::change_my_password),
+        )
+        .route("/api/users/:username", get(user_routes::get_user))
+        .route(
+            "/api/users/:username/roles",
+            put(user_routes::update_user_roles).route_layer(middleware::from_fn_with_state(
+                Role::Admin,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/lockouts",
+            get(auth_routes::get_lockouts).route_layer(middleware::from_fn_with_state(
+                Role::Admin,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/lockouts/:kind/:subject",
+            delete(auth_routes::delete_lockout).route_layer(middleware::from_fn_with_state(
+                Role::Admin,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/wotd/rotation",
+            get(word_routes::get_rotation_status).route_layer(middleware::from_fn_with_state(
+                Role::Admin,
+                auth_guard::require_role,
+            )),
+        )
+        .route(
+            "/api/users/:username/keys",
+            get(user_routes::get_keys).post(user_routes::create_key),
+        )
+        .route(
+            "/api/users/:username/keys/:key_id",
+            delete(user_routes::delete_key),
+        )
+        .route("/auth/logout", get(auth_routes::user_logout))
+        .route(
+            "/auth/sessions",
+            get(auth_routes::get_sessions).delete(auth_routes::delete_sessions),
+        )
+        .route(
+            "/auth/sessions/:session_id",
+            delete(auth_routes::delete_session),
+        )
+        .route(
+            "/auth/totp",
+            post(totp_routes::enroll).delete(totp_routes::disable),
+        )
+        .route("/auth/totp/confirm", post(totp_routes::confirm))
+        .route_layer(middleware::from_fn(auth_guard::require_session)) // API keys cannot reach the routes above
+        .route(
+            "/api/wotd",
+            get(word_routes::get_wotd).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::ReadWords,
+                auth_guard::require_scope,
+            )),
+        )
+        .route(
+            "/api/wotd/update",
+            post(word_routes::update_wotd)
+                .route_layer(middleware::from_fn_with_state(
+                    Role::Moderator,
+                    auth_guard::require_role,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    ApiKeyScope::RotateWotd,
+                    auth_guard::require_scope,
+                )),
+        )
+        .route(
+            "/api/wotd/history",
+            get(word_routes::get_history).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::ReadWords,
+                auth_guard::require_scope,
+            )),
+        )
+        .route(
+            "/api/wotd/:date",
+            get(word_routes::get_wotd_by_date).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::ReadWords,
+                auth_guard::require_scope,
+            )),
+        )
+        .route(
+            "/api/wotd/suggest",
+            post(word_routes::suggest_new_wotd).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::Suggest,
+                auth_guard::require_scope,
+            )),
+        )
+        .route(
+            "/api/words",
+            get(word_routes::get_words).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::ReadWords,
+                auth_guard::require_scope,
+            )),
+        )
+        .route(
+            "/api/words/:word",
+            get(word_routes::get_word).route_layer(middleware::from_fn_with_state(
+                ApiKeyScope::ReadWords,
+                auth_guard::require_scope,
+            )),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_guard::auth,
+        )) // All routes above will require 'access_token' cookie
+        .route("/auth/login", post(auth_routes::user_login))
+        .route("/auth/login/totp", post(totp_routes::login_totp))
+        .route("/auth/refresh", post(auth_routes::refresh))
+        .route("/auth/account", post(user_routes::create_user))
+        .route("/auth/verify", get(auth_routes::verify_email))
+        .route(
+            "/auth/verify/resend",
+            post(auth_routes::resend_verification),
+        )
+        .route("/auth/password/forgot", post(auth_routes::forgot_password))
+        .route(
+            "/auth/password/reset",
+            get(auth_routes::reset_password_page).post(auth_routes::reset_password),
+        )
+        .route("/.well-known/jwks.json", get(auth_routes::jwks))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod app_router_tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{header::AUTHORIZATION, header::CONTENT_TYPE, Request, StatusCode},
+        response::Response,
+    };
+    use bson::oid::ObjectId;
+    use error_lib::api_error::ApiError;
+    use openssl::rsa::Rsa;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+    use user_lib::user_mail::{InMemoryMailSender, Mail, MailSender};
+    use user_lib::user_models::PASSWORD_MAX_CHARS;
+    use user_lib::user_totp::{generate_recovery_codes, generate_secret, UserTotpModel};
+    use wotd_lib::word_logic::rotate_wotd;
+
+    use super::*;
+    use crate::auth_token::TokenIssuer;
+
+    fn test_issuer() -> Arc<TokenIssuer> {
+        let token_issuer = TokenIssuer::new(
+            Rsa::generate(2048).unwrap(),
+            "poc_rear_test".to_string(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        Arc::new(token_issuer)
+    }
+
+    /// The whole router on in-memory storage, along with the mails it sent.
+    fn test_app() -> (Router, Arc<InMemoryMailSender>) {
+        let mail_sender = Arc::new(InMemoryMailSender::default());
+        let state = AppState::in_memory(test_issuer(), None, mail_sender.clone());
+        (app_router(state), mail_sender)
+    }
+
+    /// Like [`test_app`], on an in-memory SQLite database.
+    async fn sqlite_test_app() -> (Router, Arc<InMemoryMailSender>) {
+        sqlx::any::install_default_drivers();
+        let pool = sqlx::any::AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../migrations").run(&pool).await.unwrap();
+
+        let mail_sender = Arc::new(InMemoryMailSender::default());
+        let state = AppState::with_sql(pool, test_issuer(), None, mail_sender.clone());
+        (app_router(state), mail_sender)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        access_token: Option<&str>,
+        body: Option<Value>,
+    ) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(access_token) = access_token {
+            request = request.header(AUTHORIZATION, format!("Bearer {access_token}"));
+        }
+        let mut request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4242))));
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn send_form(app: &Router, uri: &str, form: &str) -> Response {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_string()))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 4242))));
+
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn text_body(response: Response) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn json_body(response: Response) -> Value {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn account(username: &str) -> Value {
+        json!({
+            "username": username,
+            "password": "hunter22",
+            "email": format!("{username}@example.com"),
+        })
+    }
+
+    fn suggestion(word: &str) -> Value {
+        json!({
+            "word": word,
+            "definition": "a definition",
+            "sentence": format!("A sentence with {word}."),
+        })
+    }
+
+    /// Creates and verifies an account, then logs in, returning the access token.
+    async fn sign_up(app: &Router, mails: &InMemoryMailSender, username: &str) -> String {
+        let created = send(app, "POST", "/auth/account", None, Some(account(username))).await;
+        assert_eq!(StatusCode::OK, created.status());
+
+        let mail = mails.sent().await.pop().unwrap();
+        let token = mail.body.split("token=").nth(1).unwrap().trim();
+        let verified = send(
+            app,
+            "GET",
+            &format!("/auth/verify?token={token}"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, verified.status());
+
+        let login = json!({ "username": username, "password": "hunter22" });
+        let logged_in = send(app, "POST", "/auth/login", None, Some(login)).await;
+        assert_eq!(StatusCode::OK, logged_in.status());
+        json_body(logged_in).await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn signed_up_user_reaches_protected_routes() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let me = send(&app, "GET", "/api/users/me", Some(&access_token), None).await;
+
+        assert_eq!(StatusCode::OK, me.status());
+        assert_eq!("jork", json_body(me).await["username"]);
+    }
+
+    #[tokio::test]
+    async fn signed_up_user_reaches_protected_routes_on_sqlite() {
+        let (app, mails) = sqlite_test_app().await;
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let me = send(&app, "GET", "/api/users/me", Some(&access_token), None).await;
+
+        assert_eq!(StatusCode::OK, me.status());
+        assert_eq!("jork", json_body(me).await["username"]);
+    }
+
+    #[tokio::test]
+    async fn changed_email_waits_for_verification() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+        let change = |current_password: Option<&str>| {
+            let body =
+                json!({ "email": "jork@new.example.com", "current_password": current_password });
+            send(
+                &app,
+                "PATCH",
+                "/api/users/me",
+                Some(&access_token),
+                Some(body),
+            )
+        };
+
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            change(None).await.status()
+        );
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            change(Some("wrong password")).await.status()
+        );
+        let changed = change(Some("hunter22")).await;
+        assert_eq!(StatusCode::OK, changed.status());
+        assert_eq!("jork@example.com", json_body(changed).await["email"]);
+
+        let mail = mails.sent().await.pop().unwrap();
+        assert_eq!("jork@new.example.com", mail.to);
+        let me = send(&app, "GET", "/api/users/me", Some(&access_token), None).await;
+        assert_eq!("jork@example.com", json_body(me).await["email"]);
+
+        let token = mail.body.split("token=").nth(1).unwrap().trim();
+        let uri = format!("/auth/verify?token={token}");
+        let verified = send(&app, "GET", &uri, None, None).await;
+        assert_eq!(StatusCode::OK, verified.status());
+        let me = send(&app, "GET", "/api/users/me", Some(&access_token), None).await;
+        assert_eq!("jork@new.example.com", json_body(me).await["email"]);
+    }
+
+    #[tokio::test]
+    async fn mailed_reset_link_opens_a_form_that_resets_the_password() {
+        let (app, mails) = test_app();
+        sign_up(&app, &mails, "jork").await;
+
+        let forgot = send_form(&app, "/auth/password/forgot", "username=jork").await;
+        assert_eq!(StatusCode::OK, forgot.status());
+        let mail = mails.sent().await.pop().unwrap();
+        let link = mail
+            .body
+            .lines()
+            .find(|line| line.contains("token="))
+            .unwrap();
+        let token = link.split("token=").nth(1).unwrap().trim();
+
+        let page = send(
+            &app,
+            "GET",
+            &format!("/auth/password/reset?token={token}"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, page.status());
+        let page = text_body(page).await;
+        assert!(page.contains(r#"action="reset""#));
+        assert!(page.contains(&format!(r#"value="{token}""#)));
+
+        let form = format!("token={token}&password=hunter23");
+        let reset = send_form(&app, "/auth/password/reset", &form).await;
+        assert_eq!(StatusCode::OK, reset.status());
+        let login = json!({ "username": "jork", "password": "hunter23" });
+        let logged_in = send(&app, "POST", "/auth/login", None, Some(login)).await;
+        assert_eq!(StatusCode::OK, logged_in.status());
+    }
+
+    struct FailingMailSender;
+
+    #[axum::async_trait]
+    impl MailSender for FailingMailSender {
+        async fn send(&self, _mail: Mail) -> Result<(), ApiError> {
+            Err(ApiError::internal(anyhow::anyhow!(
+                "the mail server is down"
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn forgot_password_answers_the_same_when_mail_fails() {
+        let state = AppState::in_memory(test_issuer(), None, Arc::new(FailingMailSender));
+        let app = app_router(state);
+        // The account is stored before its verification mail fails to send.
+        send(&app, "POST", "/auth/account", None, Some(account("jork"))).await;
+
+        let existing = send_form(&app, "/auth/password/forgot", "username=jork").await;
+        let missing = send_form(&app, "/auth/password/forgot", "username=nobody").await;
+
+        assert_eq!(StatusCode::OK, existing.status());
+        assert_eq!(text_body(existing).await, text_body(missing).await);
+    }
+
+    #[tokio::test]
+    async fn sign_up_succeeds_when_the_verification_mail_fails() {
+        let state = AppState::in_memory(test_issuer(), None, Arc::new(FailingMailSender));
+        let app = app_router(state);
+
+        let created = send(&app, "POST", "/auth/account", None, Some(account("jork"))).await;
+
+        assert_eq!(StatusCode::OK, created.status());
+        assert!(text_body(created).await.contains("/auth/verify/resend"));
+    }
+
+    #[tokio::test]
+    async fn forgot_password_validates_the_username() {
+        let (app, _mails) = test_app();
+
+        let response = send_form(&app, "/auth/password/forgot", "username=").await;
+
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+    }
+
+    #[tokio::test]
+    async fn second_factor_login_can_be_finished_with_json() {
+        let mail_sender = Arc::new(InMemoryMailSender::default());
+        let state = AppState::in_memory(test_issuer(), None, mail_sender.clone());
+        let app = app_router(state.clone());
+        sign_up(&app, &mail_sender, "jork").await;
+        let user = state.users.find_by_username("jork").await.unwrap().unwrap();
+        let secret = generate_secret();
+        let totp = UserTotpModel {
+            secret: secret.clone(),
+            confirmed: false,
+            last_used_step: None,
+            recovery_code_hashes: Vec::new(),
+        };
+        state.users.begin_totp(user._id, &totp).await.unwrap();
+        let (recovery_codes, hashes) = generate_recovery_codes();
+        state
+            .users
+            .confirm_totp(user._id, &secret, 0, &hashes)
+            .await
+            .unwrap();
+
+        let login = json!({ "username": "jork", "password": "hunter22" });
+        let challenged = send(&app, "POST", "/auth/login", None, Some(login)).await;
+        assert_eq!(StatusCode::ACCEPTED, challenged.status());
+        let challenge = json_body(challenged).await["challenge"].clone();
+
+        let second_factor = json!({ "challenge": challenge, "code": recovery_codes[0] });
+        let logged_in = send(&app, "POST", "/auth/login/totp", None, Some(second_factor)).await;
+        assert_eq!(StatusCode::OK, logged_in.status());
+    }
+
+    #[tokio::test]
+    async fn oversized_or_empty_fields_are_rejected() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let long_password = json!({ "password": "x".repeat(PASSWORD_MAX_CHARS as usize + 1) });
+        let deleted = send(
+            &app,
+            "DELETE",
+            "/api/users/me",
+            Some(&access_token),
+            Some(long_password),
+        )
+        .await;
+        let unnamed_key = json!({ "name": "", "scopes": ["read-words"] });
+        let created = send(
+            &app,
+            "POST",
+            "/api/users/jork/keys",
+            Some(&access_token),
+            Some(unnamed_key),
+        )
+        .await;
+
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, deleted.status());
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, created.status());
+    }
+
+    #[tokio::test]
+    async fn protected_routes_need_a_token() {
+        let (app, _mails) = test_app();
+
+        let response = send(&app, "GET", "/api/users/me", None, None).await;
+
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    }
+
+    #[tokio::test]
+    async fn taken_username_is_conflict() {
+        let (app, _mails) = test_app();
+        send(&app, "POST", "/auth/account", None, Some(account("jork"))).await;
+
+        let response = send(&app, "POST", "/auth/account", None, Some(account("jork"))).await;
+
+        assert_eq!(StatusCode::CONFLICT, response.status());
+    }
+
+    #[tokio::test]
+    async fn suggested_word_is_queued() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+        let suggestion = json!({
+            "word": "petrichor",
+            "definition": "the smell of rain on dry ground",
+            "sentence": "The petrichor came before the storm.",
+        });
+
+        let suggested = send(
+            &app,
+            "POST",
+            "/api/wotd/suggest",
+            Some(&access_token),
+            Some(suggestion.clone()),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, suggested.status());
+
+        // It only becomes the word of the day when the queue rotates.
+        let wotd = send(&app, "GET", "/api/wotd", Some(&access_token), None).await;
+        assert_eq!(StatusCode::NOT_FOUND, wotd.status());
+
+        let again = send(
+            &app,
+            "POST",
+            "/api/wotd/suggest",
+            Some(&access_token),
+            Some(suggestion),
+        )
+        .await;
+        assert_eq!(StatusCode::CONFLICT, again.status());
+    }
+
+    #[tokio::test]
+    async fn wotd_is_the_word_rotated_to_today() {
+        let mail_sender = Arc::new(InMemoryMailSender::default());
+        let state = AppState::in_memory(test_issuer(), None, mail_sender.clone());
+        let app = app_router(state.clone());
+        let access_token = sign_up(&app, &mail_sender, "jork").await;
+        for word in ["petrichor", "sonder"] {
+            let body = Some(suggestion(word));
+            send(&app, "POST", "/api/wotd/suggest", Some(&access_token), body).await;
+        }
+        let today = state.wotd_today();
+
+        // As the scheduler does when it fires.
+        rotate_wotd(state.queue.as_ref(), state.history.as_ref(), today)
+            .await
+            .unwrap();
+
+        let wotd = send(&app, "GET", "/api/wotd", Some(&access_token), None).await;
+        let uri = format!("/api/wotd/{today}");
+        let dated = send(&app, "GET", &uri, Some(&access_token), None).await;
+        assert_eq!(StatusCode::OK, wotd.status());
+        assert_eq!(StatusCode::OK, dated.status());
+        assert_eq!("petrichor", json_body(wotd).await["word"]["word"]);
+        assert_eq!("petrichor", json_body(dated).await["word"]["word"]);
+    }
+
+    #[tokio::test]
+    async fn pinned_word_is_the_wotd_on_its_day() {
+        let mail_sender = Arc::new(InMemoryMailSender::default());
+        let state = AppState::in_memory(test_issuer(), None, mail_sender.clone());
+        let app = app_router(state.clone());
+        let access_token = sign_up(&app, &mail_sender, "jork").await;
+        for word in ["petrichor", "valentine"] {
+            let body = Some(suggestion(word));
+            send(&app, "POST", "/api/wotd/suggest", Some(&access_token), body).await;
+        }
+        let today = state.wotd_today();
+        state.queue.pin("valentine", today).await.unwrap();
+
+        rotate_wotd(state.queue.as_ref(), state.history.as_ref(), today)
+            .await
+            .unwrap();
+
+        let wotd = send(&app, "GET", "/api/wotd", Some(&access_token), None).await;
+        assert_eq!("valentine", json_body(wotd).await["word"]["word"]);
+    }
+
+    #[tokio::test]
+    async fn history_is_read_by_date() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let history = send(&app, "GET", "/api/wotd/history", Some(&access_token), None).await;
+        assert_eq!(StatusCode::OK, history.status());
+        assert_eq!(0, json_body(history).await["total"]);
+
+        let today = chrono::Utc::now().date_naive();
+        let uri = format!("/api/wotd/{today}");
+        let missing = send(&app, "GET", &uri, Some(&access_token), None).await;
+        assert_eq!(StatusCode::NOT_FOUND, missing.status());
+        let invalid = send(&app, "GET", "/api/wotd/someday", Some(&access_token), None).await;
+        assert_eq!(StatusCode::BAD_REQUEST, invalid.status());
+    }
+
+    #[tokio::test]
+    async fn far_history_pages_are_empty() {
+        let (app, mails) = sqlite_test_app().await;
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let uri = "/api/wotd/history?page=10000000000000000000";
+        let history = send(&app, "GET", uri, Some(&access_token), None).await;
+
+        assert_eq!(StatusCode::OK, history.status());
+        assert_eq!(
+            0,
+            json_body(history).await["items"].as_array().unwrap().len()
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_is_for_moderators() {
+        let (app, mails) = test_app();
+        let access_token = sign_up(&app, &mails, "jork").await;
+
+        let queue = send(&app, "GET", "/api/wotd/queue", Some(&access_token), None).await;
+        assert_eq!(StatusCode::FORBIDDEN, queue.status());
+
+        let uri = format!("/api/wotd/queue/{}/position", ObjectId::new());
+        let moved = send(
+            &app,
+            "PUT",
+            &uri,
+            Some(&access_token),
+            Some(json!({ "position": 1 })),
+        )
+        .await;
+        assert_eq!(StatusCode::FORBIDDEN, moved.status());
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+use config_lib::config::{Config, StorageBackend};
+use mongodb::Client;
+use sqlx::AnyPool;
+use user_lib::{
+    user_mail::MailSender,
+    user_memory::{
+        MemoryApiKeyRepository, MemoryLoginAttemptRepository, MemoryPasswordResetRepository,
+        MemorySessionRepository, MemoryUserRepository,
+    },
+    user_mongo::{
+        MongoApiKeyRepository, MongoLoginAttemptRepository, MongoPasswordResetRepository,
+        MongoSessionRepository, MongoUserRepository,
+    },
+    user_repository::{
+        ApiKeyRepository, LoginAttemptRepository, PasswordResetRepository, SessionRepository,
+        UserRepository,
+    },
+    user_sql::{
+        SqlApiKeyRepository, SqlLoginAttemptRepository, SqlPasswordResetRepository,
+        SqlSessionRepository, SqlUserRepository,
+    },
+};
+use wotd_lib::{
+    word_memory::{
+        MemoryHistoryRepository, MemoryQueueRepository, MemoryRotationRepository,
+        MemoryWordRepository,
+    },
+    word_mongo::{
+        MongoHistoryRepository, MongoQueueRepository, MongoRotationRepository, MongoWordRepository,
+    },
+    word_repository::{HistoryRepository, QueueRepository, RotationRepository, WordRepository},
+    word_sql::{
+        SqlHistoryRepository, SqlQueueRepository, SqlRotationRepository, SqlWordRepository,
+    },
+};
+
+use crate::{auth_token::TokenIssuer, jwks_cache::JwksCache};
+
+/// Everything the handlers share, handed to them through axum's `State`.
+///
+/// Storage is only reachable through the repositories, so the same router runs against any
+/// backend that implements them.
+#[derive(Clone)]
+pub struct AppState {
+    pub users: Arc<dyn UserRepository>,
+    pub sessions: Arc<dyn SessionRepository>,
+    pub api_keys: Arc<dyn ApiKeyRepository>,
+    pub login_attempts: Arc<dyn LoginAttemptRepository>,
+    pub password_resets: Arc<dyn PasswordResetRepository>,
+    pub words: Arc<dyn WordRepository>,
+    pub queue: Arc<dyn QueueRepository>,
+    pub history: Arc<dyn HistoryRepository>,
+    pub rotations: Arc<dyn RotationRepository>,
+    pub token_issuer: Arc<TokenIssuer>,
+    /// Only set when tokens from an external authority are accepted as well.
+    pub authority_keys: Option<Arc<JwksCache>>,
+    pub mail_sender: Arc<dyn MailSender>,
+    /// What the word of the day goes by. UTC, unless `from_config` read another from
+    /// `WOTD_TIMEZONE`.
+    pub wotd_timezone: Tz,
+}
+
+impl AppState {
+    /// Connects to the storage backend picked by `STORAGE` and `DATABASE_URL`.
+    pub async fn from_config(
+        token_issuer: Arc<TokenIssuer>,
+        authority_keys: Option<Arc<JwksCache>>,
+        mail_sender: Arc<dyn MailSender>,
+    ) -> AppState {
+        // Read once, so an invalid timezone stops the service from starting instead of failing
+        // requests.
+        let wotd_timezone = Config::wotd_timezone();
+        let state = match Config::storage_backend() {
+            StorageBackend::Mongo => {
+                let client = Config::init_mongo().await;
+                AppState::with_mongo(&client, token_issuer, authority_keys, mail_sender)
+            }
+            StorageBackend::Sql => {
+                let pool = Config::init_sql().await;
+                AppState::with_sql(pool, token_issuer, authority_keys, mail_sender)
+            }
+            StorageBackend::Memory => {
+                tracing::warn!("storing everything in memory, it is gone on restart");
+                AppState::in_memory(token_issuer, authority_keys, mail_sender)
+            }
+        };
+        AppState {
+            wotd_timezone,
+            ..state
+        }
+    }
+
+    /// The date it is now in [`AppState::wotd_timezone`].
+    pub fn wotd_today(&self) -> chrono::NaiveDate {
+        chrono::Utc::now()
+            .with_timezone(&self.wotd_timezone)
+            .date_naive()
+    }
+
+    /// Stores everything in the collections `Config::init_mongo` set up.
+    pub fn with_mongo(
+        client: &Client,
+        token_issuer: Arc<TokenIssuer>,
+        authority_keys: Option<Arc<JwksCache>>,
+        mail_sender: Arc<dyn MailSender>,
+    ) -> AppState {
+        let db = client.database(Config::MONGO_DB_NAME);
+
+        AppState {
+            users: Arc::new(MongoUserRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_USERS),
+            )),
+            sessions: Arc::new(MongoSessionRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_REFRESH_TOKENS),
+                db.collection(Config::MONGO_COLL_NAME_REVOKED_TOKENS),
+            )),
+            api_keys: Arc::new(MongoApiKeyRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_API_KEYS),
+            )),
+            login_attempts: Arc::new(MongoLoginAttemptRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_LOGIN_ATTEMPTS),
+            )),
+            password_resets: Arc::new(MongoPasswordResetRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_PASSWORD_RESETS),
+            )),
+            words: Arc::new(MongoWordRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_WORDS),
+            )),
+            queue: Arc::new(MongoQueueRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_WORDS),
+                db.collection(Config::MONGO_COLL_NAME_QUEUE_WORDS),
+            )),
+            history: Arc::new(MongoHistoryRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_WOTD_HISTORY),
+            )),
+            rotations: Arc::new(MongoRotationRepository::new(
+                db.collection(Config::MONGO_COLL_NAME_ROTATION_LEASES),
+                db.collection(Config::MONGO_COLL_NAME_ROTATION_RUNS),
+            )),
+            token_issuer,
+            authority_keys,
+            mail_sender,
+            wotd_timezone: Tz::UTC,
+        }
+    }
+
+    /// Stores everything in the tables `Config::init_sql` migrated.
+    pub fn with_sql(
+        pool: AnyPool,
+        token_issuer: Arc<TokenIssuer>,
+        authority_keys: Option<Arc<JwksCache>>,
+        mail_sender: Arc<dyn MailSender>,
+    ) -> AppState {
+        AppState {
+            users: Arc::new(SqlUserRepository::new(pool.clone())),
+            sessions: Arc::new(SqlSessionRepository::new(pool.clone())),
+            api_keys: Arc::new(SqlApiKeyRepository::new(pool.clone())),
+            login_attempts: Arc::new(SqlLoginAttemptRepository::new(pool.clone())),
+            password_resets: Arc::new(SqlPasswordResetRepository::new(pool.clone())),
+            words: Arc::new(SqlWordRepository::new(pool.clone())),
+            queue: Arc::new(SqlQueueRepository::new(pool.clone())),
+            history: Arc::new(SqlHistoryRepository::new(pool.clone())),
+            rotations: Arc::new(SqlRotationRepository::new(pool)),
+            token_issuer,
+            authority_keys,
+            mail_sender,
+            wotd_timezone: Tz::UTC,
+        }
+    }
+
+    /// Keeps everything in this process, starting out empty.
+    pub fn in_memory(
+        token_issuer: Arc<TokenIssuer>,
+        authority_keys: Option<Arc<JwksCache>>,
+        mail_sender: Arc<dyn MailSender>,
+    ) -> AppState {
+        let words = Arc::new(MemoryWordRepository::default());
+        AppState {
+            users: Arc::new(MemoryUserRepository::default()),
+            sessions: Arc::new(MemorySessionRepository::default()),
+            api_keys: Arc::new(MemoryApiKeyRepository::default()),
+            login_attempts: Arc::new(MemoryLoginAttemptRepository::default()),
+            password_resets: Arc::new(MemoryPasswordResetRepository::default()),
+            words: words.clone(),
+            queue: Arc::new(MemoryQueueRepository::new(words)),
+            history: Arc::new(MemoryHistoryRepository::default()),
+            rotations: Arc::new(MemoryRotationRepository::default()),
+            token_issuer,
+            authority_keys,
+            mail_sender,
+            wotd_timezone: Tz::UTC,
+        }
+    }
+}
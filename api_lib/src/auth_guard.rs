@@ -1,90 +1,395 @@
-use std::sync::Arc;
+use error_lib::api_error::ApiError;
 
-use alcoholic_jwt::{token_kid, validate, Validation, JWKS};
+use alcoholic_jwt::{token_kid, validate, ValidJWT, Validation, JWK};
 use anyhow::Result;
-use bson::doc;
-use config_lib::{config::Config, config_env::ConfigEnvKey};
+use config_lib::config::Config;
 
 use axum::{
-    http::{Request, StatusCode},
-    middleware::Next,
-    response::Response,
-    Extension,
+    extract::State, http::HeaderMap, http::Request, middleware::Next, response::Response, Extension,
 };
-use mongodb::{Client, Collection};
-use user_lib::user_models::{DtoUser, UserModel};
+use user_lib::{
+    user_api_key::{authenticate_api_key, is_api_key, ApiKeyModel, ApiKeyScope},
+    user_models::{DtoUser, Role},
+    user_session::is_access_revoked,
+};
+
+use crate::{app_state::AppState, auth_token::TokenIssuer, jwks_cache::JwksCache};
+
+/// What the auth guard learned about the credential used for the current request.
+///
+/// Inserted as a request extension next to the `DtoUser`.
+#[derive(Clone, Debug, Default)]
+pub struct AuthContext {
+    pub jti: Option<String>,
+    /// Only tokens issued by this service belong to a session.
+    pub session_id: Option<String>,
+    pub expires_at: Option<i64>,
+    /// Set when the request was made with an API key rather than a token.
+    pub api_key_scopes: Option<Vec<ApiKeyScope>>,
+}
+
+impl AuthContext {
+    /// Tokens may do anything their user may do, API keys only what they were scoped for.
+    pub fn allows(&self, scope: ApiKeyScope) -> bool {
+        match &self.api_key_scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
+}
+
+impl From<&ValidJWT> for AuthContext {
+    fn from(valid_jwt: &ValidJWT) -> Self {
+        AuthContext {
+            jti: valid_jwt.claims["jti"].as_str().map(String::from),
+            session_id: valid_jwt.claims["sid"].as_str().map(String::from),
+            expires_at: valid_jwt.claims["exp"].as_i64(),
+            api_key_scopes: None,
+        }
+    }
+}
+
+impl From<&ApiKeyModel> for AuthContext {
+    fn from(api_key: &ApiKeyModel) -> Self {
+        AuthContext {
+            api_key_scopes: Some(api_key.scopes.clone()),
+            ..AuthContext::default()
+        }
+    }
+}
 
+/// Authenticates the request with either an access token or an API key.
+///
+/// API keys are sent as `Authorization: Bearer wotd_...`. Added with
+/// `middleware::from_fn_with_state(state.clone(), auth_guard::auth)`.
 pub async fn auth<T>(
-    Extension(client): Extension<Arc<Client>>,
+    State(state): State<AppState>,
     mut req: Request<T>,
     next: Next<T>,
-) -> Result<Response, StatusCode> {
-    let access_token = extract_access_token(&req)?;
+) -> Result<Response, ApiError> {
+    let credential = extract_access_token(req.headers())?;
 
-    if !bool::from(ConfigEnvKey::DevMode) {
-        validate_access_token(&access_token).await?;
+    let (found_user, auth_context) = if is_api_key(&credential) {
+        let api_key = authenticate_api_key(state.api_keys.as_ref(), &credential).await?;
+        tracing::debug!("authenticated with api key {}", api_key.key_hint);
+        let user = state.users.find_by_id(api_key.user_id).await?;
+        (user, AuthContext::from(&api_key))
     } else {
-        let parts: Vec<&str> = access_token.split('.').collect();
-        let user_collection: Collection<UserModel> = client
-            .database(Config::MONGO_DB_NAME)
-            .collection(Config::MONGO_COLL_NAME_USERS);
+        let valid_jwt = validate_access_token(
+            &state.token_issuer,
+            state.authority_keys.as_deref(),
+            &credential,
+        )
+        .await?;
+        let username = valid_jwt.claims["sub"]
+            .as_str()
+            .ok_or(ApiError::Unauthorized)?;
+        let auth_context = AuthContext::from(&valid_jwt);
 
-        let found_user = user_collection
-            .find_one(doc! {"username": parts.get(1)}, None)
-            .await
-            .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if is_access_revoked(
+            state.sessions.as_ref(),
+            auth_context.jti.as_deref(),
+            auth_context.session_id.as_deref(),
+        )
+        .await?
+        {
+            tracing::debug!("rejected revoked token");
+            return Err(ApiError::Unauthorized);
+        }
+        (state.users.find_by_username(username).await?, auth_context)
+    };
+
+    let found_user = found_user.ok_or(ApiError::Unauthorized)?;
+
+    let dto_user = DtoUser::from(found_user);
+    tracing::debug!("found user: {dto_user:#?}");
+    req.extensions_mut().insert(dto_user);
+    req.extensions_mut().insert(auth_context);
+
+    Ok(next.run(req).await)
+}
 
-        let dto_user = DtoUser::from(found_user);
-        tracing::info!("found user: {dto_user:#?}");
-        req.extensions_mut().insert(dto_user);
+/// Rejects requests from users without `role` with a 403.
+///
+/// Must run after [`auth`], and is meant to be added to single routes:
+/// ```ignore
+/// post(word_routes::update_wotd)
+///     .route_layer(middleware::from_fn_with_state(Role::Moderator, auth_guard::require_role))
+/// ```
+pub async fn require_role<T>(
+    State(role): State<Role>,
+    Extension(user): Extension<DtoUser>,
+    req: Request<T>,
+    next: Next<T>,
+) -> Result<Response, ApiError> {
+    if !user.has_role(role) {
+        tracing::info!(user.username, "missing required role {role:?}");
+        return Err(ApiError::Forbidden);
     }
 
     Ok(next.run(req).await)
 }
 
-pub async fn validate_access_token(access_token: &str) -> Result<bool, StatusCode> {
-    let authority = String::from(ConfigEnvKey::Authority);
-    let uri = format!("{}{}", authority.as_str(), ".well-known/jwks.json");
-    let jwks = fetch_jwks(&uri)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
-    tracing::trace!("got jwks from authority");
+/// Lets API keys with `scope` through to a route, tokens are always let through.
+///
+/// Must run after [`auth`]. The owner of the key still needs whatever role the route requires.
+pub async fn require_scope<T>(
+    State(scope): State<ApiKeyScope>,
+    Extension(auth_context): Extension<AuthContext>,
+    req: Request<T>,
+    next: Next<T>,
+) -> Result<Response, ApiError> {
+    if !auth_context.allows(scope) {
+        tracing::info!("api key is missing scope {scope:?}");
+        return Err(ApiError::Forbidden);
+    }
 
-    let kid = token_kid(access_token)
-        .map_err(|_err| StatusCode::UNAUTHORIZED)?
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    Ok(next.run(req).await)
+}
 
-    let jwk = jwks.find(&kid).ok_or(StatusCode::UNAUTHORIZED)?;
+/// Rejects API keys, for routes that manage the account itself.
+///
+/// Must run after [`auth`].
+pub async fn require_session<T>(
+    Extension(auth_context): Extension<AuthContext>,
+    req: Request<T>,
+    next: Next<T>,
+) -> Result<Response, ApiError> {
+    if auth_context.api_key_scopes.is_some() {
+        tracing::info!("rejected api key on a route that requires a login");
+        return Err(ApiError::Forbidden);
+    }
 
-    let validations = vec![Validation::Issuer(authority), Validation::SubjectPresent];
-    validate(access_token, jwk, validations).map_err(|_err| StatusCode::UNAUTHORIZED)?;
+    Ok(next.run(req).await)
+}
+
+/// Validates a token issued either by this service, or by the configured external authority.
+pub async fn validate_access_token(
+    token_issuer: &TokenIssuer,
+    authority_keys: Option<&JwksCache>,
+    access_token: &str,
+) -> Result<ValidJWT, ApiError> {
+    let kid = token_kid(access_token)
+        .map_err(|_err| ApiError::Unauthorized)?
+        .ok_or(ApiError::Unauthorized)?;
+
+    let valid_jwt = if kid == token_issuer.kid() {
+        let jwk = token_issuer
+            .jwks()
+            .find(&kid)
+            .ok_or(ApiError::Unauthorized)?;
+        let valid_jwt = validate_with_jwk(access_token, jwk, token_issuer.issuer().to_string())?;
+        // Tokens from mailed links are signed with the same key, but must not work as access tokens.
+        if valid_jwt.claims.get("purpose").is_some() {
+            return Err(ApiError::Unauthorized);
+        }
+        valid_jwt
+    } else {
+        let authority_keys = authority_keys.ok_or(ApiError::Unauthorized)?;
+        let jwk = authority_keys.find(&kid).await?;
+        validate_with_jwk(access_token, &jwk, authority_keys.authority().to_string())?
+    };
 
     tracing::trace!("validated token");
-    Ok(true)
+    Ok(valid_jwt)
+}
+
+fn validate_with_jwk(access_token: &str, jwk: &JWK, issuer: String) -> Result<ValidJWT, ApiError> {
+    let validations = vec![
+        Validation::Issuer(issuer),
+        Validation::SubjectPresent,
+        Validation::NotExpired,
+    ];
+    validate(access_token, jwk, validations).map_err(|_err| ApiError::Unauthorized)
 }
 
-async fn fetch_jwks(uri: &str) -> Result<JWKS, StatusCode> {
-    match reqwest::get(uri).await {
-        Ok(r) => r
-            .json::<JWKS>()
-            .await
-            .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR),
-        Err(_err) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// Finds the access token on a request.
+///
+/// Clients that cannot use cookies send `Authorization: Bearer <token>`. When an `Authorization`
+/// header is present it always wins over the cookie, and a malformed one is rejected outright
+/// rather than falling back to the cookie, so a client never ends up authenticated as someone it
+/// did not intend to be.
+fn extract_access_token(headers: &HeaderMap) -> Result<String, ApiError> {
+    let mut authorization = headers.get_all(http::header::AUTHORIZATION).iter();
+    if let Some(header) = authorization.next() {
+        if authorization.next().is_some() {
+            tracing::debug!("rejected request with multiple authorization headers");
+            return Err(ApiError::Unauthorized);
+        }
+        let token = extract_bearer_token(header.to_str().map_err(|_err| ApiError::Unauthorized)?)
+            .ok_or(ApiError::Unauthorized)?;
+        tracing::trace!("extracted jwt from authorization header");
+        return Ok(token.to_string());
     }
+
+    let token = extract_cookie(headers, Config::AUTH_TOKEN_STRING)
+        .filter(|token| !token.is_empty())
+        .ok_or(ApiError::Unauthorized)?;
+    tracing::trace!("extracted jwt from cookie");
+    Ok(token)
 }
 
-fn extract_access_token<T>(req: &Request<T>) -> Result<String, StatusCode> {
-    if let Some(cookie_header) = req.headers().get(http::header::COOKIE) {
-        let cookies: Vec<_> = cookie_header.to_str().unwrap().split(';').collect();
-        for cookie in cookies {
-            if cookie.contains(Config::AUTH_TOKEN_STRING) {
-                let jwt_access_token =
-                    cookie.replace(&format!("{}=", Config::AUTH_TOKEN_STRING), "");
-                tracing::trace!("extracted jwt from headers");
-                return Ok(jwt_access_token);
-            }
+/// The auth scheme is case insensitive, see RFC 7235.
+fn extract_bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() && !token.contains(' ') {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+/// Finds the value of the cookie called `name`, across every `Cookie` header on the request.
+pub fn extract_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.to_string())
+}
+
+#[cfg(test)]
+mod guard_tests {
+    use axum::http::{HeaderValue, StatusCode};
+
+    use super::*;
+
+    fn access_token(headers: &HeaderMap) -> Result<String, StatusCode> {
+        extract_access_token(headers).map_err(|err| err.status())
+    }
+
+    fn headers(pairs: &[(http::header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn access_token_from_bearer_header() {
+        let headers = headers(&[(http::header::AUTHORIZATION, "Bearer abc.def.ghi")]);
+
+        assert_eq!(Ok("abc.def.ghi".to_string()), access_token(&headers));
+    }
+
+    #[test]
+    fn access_token_scheme_is_case_insensitive() {
+        let headers = headers(&[(http::header::AUTHORIZATION, "bearer abc.def.ghi")]);
+
+        assert_eq!(Ok("abc.def.ghi".to_string()), access_token(&headers));
+    }
+
+    #[test]
+    fn access_token_from_cookie() {
+        let headers = headers(&[(http::header::COOKIE, "theme=dark; access_token=abc.def.ghi")]);
+
+        assert_eq!(Ok("abc.def.ghi".to_string()), access_token(&headers));
+    }
+
+    #[test]
+    fn access_token_header_wins_over_cookie() {
+        let headers = headers(&[
+            (http::header::COOKIE, "access_token=from.the.cookie"),
+            (http::header::AUTHORIZATION, "Bearer from.the.header"),
+        ]);
+
+        assert_eq!(Ok("from.the.header".to_string()), access_token(&headers));
+    }
+
+    #[test]
+    fn access_token_malformed_header_does_not_fall_back() {
+        for authorization in ["Bearer", "Bearer ", "Basic dXNlcjpwYXNz", "Bearer a b"] {
+            let headers = headers(&[
+                (http::header::COOKIE, "access_token=from.the.cookie"),
+                (http::header::AUTHORIZATION, authorization),
+            ]);
+
+            assert_eq!(
+                Err(StatusCode::UNAUTHORIZED),
+                access_token(&headers),
+                "{authorization}"
+            );
         }
     }
-    Err(StatusCode::UNAUTHORIZED)
+
+    #[test]
+    fn access_token_non_ascii_is_unauthorized() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::AUTHORIZATION,
+            HeaderValue::from_bytes(b"Bearer \xff").unwrap(),
+        );
+        assert_eq!(Err(StatusCode::UNAUTHORIZED), access_token(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_bytes(b"access_token=\xff").unwrap(),
+        );
+        assert_eq!(Err(StatusCode::UNAUTHORIZED), access_token(&headers));
+    }
+
+    #[test]
+    fn access_token_cookie_name_must_match_exactly() {
+        let headers = headers(&[(http::header::COOKIE, "not_access_token=abc.def.ghi")]);
+
+        assert_eq!(Err(StatusCode::UNAUTHORIZED), access_token(&headers));
+    }
+
+    #[test]
+    fn extract_cookie_by_exact_name() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_static("old_refresh_token=nope; refresh_token=abc.def"),
+        );
+
+        assert_eq!(
+            Some("abc.def".to_string()),
+            extract_cookie(&headers, "refresh_token")
+        );
+        assert_eq!(None, extract_cookie(&headers, "access_token"));
+    }
+
+    #[test]
+    fn extract_cookie_skips_non_ascii_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            http::header::COOKIE,
+            HeaderValue::from_bytes(b"refresh_token=\xff").unwrap(),
+        );
+        headers.append(
+            http::header::COOKIE,
+            HeaderValue::from_static("refresh_token=abc"),
+        );
+
+        assert_eq!(
+            Some("abc".to_string()),
+            extract_cookie(&headers, "refresh_token")
+        );
+    }
+
+    #[test]
+    fn tokens_allow_every_scope() {
+        let auth_context = AuthContext::default();
+
+        assert!(auth_context.allows(ApiKeyScope::RotateWotd));
+    }
+
+    #[test]
+    fn api_keys_only_allow_their_scopes() {
+        let auth_context = AuthContext {
+            api_key_scopes: Some(vec![ApiKeyScope::ReadWords, ApiKeyScope::Suggest]),
+            ..AuthContext::default()
+        };
+
+        assert!(auth_context.allows(ApiKeyScope::ReadWords));
+        assert!(auth_context.allows(ApiKeyScope::Suggest));
+        assert!(!auth_context.allows(ApiKeyScope::RotateWotd));
+    }
 }
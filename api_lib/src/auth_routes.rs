@@ -1,97 +1,550 @@
 use anyhow::Result;
 use config_lib::{config::Config, config_env::ConfigEnvKey};
-use std::sync::Arc;
-use user_lib::user_models::{DtoUser, DtoUserLogin, UserModel};
+use error_lib::api_error::ApiError;
+use std::net::SocketAddr;
+use user_lib::{
+    user_lockout::{
+        list_lockouts, lockout_remaining, record_failure, record_success, unlock, LockoutKind,
+    },
+    user_logic::{
+        login_user, renew_verification_nonce, reset_password as reset_user_password,
+        verify_email as verify_email_nonce,
+    },
+    user_mail::{Mail, MailSender},
+    user_models::{
+        DtoUser, DtoUserLogin, UserModel, PASSWORD_MAX_CHARS, PASSWORD_MIN_CHARS,
+        USERNAME_MAX_CHARS,
+    },
+    user_password_reset::{
+        consume_password_reset, create_password_reset, DtoPasswordForgot, DtoPasswordReset,
+    },
+    user_session::{
+        create_session, end_all_sessions, end_session, list_sessions, revoke_access_token,
+        rotate_refresh_token,
+    },
+};
 
 use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    Extension, Form,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header::SET_COOKIE, HeaderMap, StatusCode},
+    response::{AppendHeaders, Html, IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    app_state::AppState,
+    auth_guard::{extract_cookie, AuthContext},
+    auth_token::{new_token_id, TokenIssuer},
+    validation::{ValidatedBody, ValidatedForm},
+    webutil::client_ip,
 };
-use mongodb::{bson::doc, Client, Collection};
+
+/// The `purpose` of tokens in email verification links.
+const VERIFY_EMAIL_PURPOSE: &str = "verify-email";
+/// The `purpose` of tokens proving the password was right, while the second factor is pending.
+pub(crate) const LOGIN_CHALLENGE_PURPOSE: &str = "login-challenge";
 
 pub async fn user_login(
-    Extension(client): Extension<Arc<Client>>,
-    Form(user_form): Form<DtoUserLogin>,
-) -> Result<Response, StatusCode> {
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedBody(user_form): ValidatedBody<DtoUserLogin>,
+) -> Result<Response, ApiError> {
+    tracing::info!(user_form.username, "login attempt");
+
+    let ip = client_ip(&headers, peer, bool::from(ConfigEnvKey::TrustForwardedFor)).to_string();
     let username = user_form.username.clone();
-    let password = user_form.password.clone();
 
-    tracing::info!("username: {username}, password: {password}");
+    check_lockout(&state, &username, &ip).await?;
+
+    let user = match login_user(state.users.as_ref(), user_form, &Config::password_params()).await {
+        Ok(user) => user,
+        Err(ApiError::NotFound) => {
+            record_login_failure(&state, &username, &ip).await?;
+            return Err(ApiError::NotFound);
+        }
+        Err(err) => return Err(err),
+    };
+
+    // The failure count is only reset once the second factor is in as well, otherwise someone with
+    // the password could keep guessing codes forever.
+    if user.totp.as_ref().is_some_and(|totp| totp.confirmed) {
+        tracing::info!(user.username, "second factor required");
+        return build_challenge_response(&state.token_issuer, &user);
+    }
+    record_success(state.login_attempts.as_ref(), &user.username).await?;
+
+    tracing::info!(user.username, "matched user!");
+    start_session(&state, &user).await
+}
+
+/// Returns `TooManyRequests` if the username or IP is locked out.
+pub(crate) async fn check_lockout(
+    state: &AppState,
+    username: &str,
+    ip: &str,
+) -> Result<(), ApiError> {
+    let retry_after = lockout_remaining(
+        state.login_attempts.as_ref(),
+        &[(LockoutKind::Username, username), (LockoutKind::Ip, ip)],
+    )
+    .await?;
+
+    match retry_after {
+        Some(retry_after) => {
+            tracing::info!(username, ip, "rejected login while locked out");
+            Err(ApiError::TooManyRequests { retry_after })
+        }
+        None => Ok(()),
+    }
+}
+
+pub(crate) async fn record_login_failure(
+    state: &AppState,
+    username: &str,
+    ip: &str,
+) -> Result<(), ApiError> {
+    let attempts = state.login_attempts.as_ref();
+    let policy = Config::lockout_policy();
+    record_failure(attempts, LockoutKind::Username, username, &policy).await?;
+    record_failure(attempts, LockoutKind::Ip, ip, &policy).await
+}
+
+/// Logs `user` in, handing out an access and a refresh token.
+pub(crate) async fn start_session(
+    state: &AppState,
+    user: &UserModel,
+) -> Result<Response, ApiError> {
+    let (refresh_token, session) =
+        create_session(state.sessions.as_ref(), user, Config::refresh_token_ttl()).await?;
+    let (access_token, _claims) =
+        state
+            .token_issuer
+            .issue_access_token(&user.username, &session.family_id, &user.roles)?;
+    build_login_response(&state.token_issuer, access_token, refresh_token)
+}
+
+/// Returned instead of the tokens when the password was right, but a second factor is needed.
+#[derive(Serialize)]
+pub struct DtoLoginChallenge {
+    pub second_factor_required: bool,
+    /// Sent to `/auth/login/totp` along with the code.
+    pub challenge: String,
+    pub expires_in: u64,
+}
+
+fn build_challenge_response(
+    token_issuer: &TokenIssuer,
+    user: &UserModel,
+) -> Result<Response, ApiError> {
+    let ttl = std::time::Duration::from_secs(Config::LOGIN_CHALLENGE_TTL_SECS);
+    let challenge = token_issuer.issue_link_token(
+        LOGIN_CHALLENGE_PURPOSE,
+        &user.username,
+        &new_token_id()?,
+        ttl,
+    )?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(DtoLoginChallenge {
+            second_factor_required: true,
+            challenge,
+            expires_in: ttl.as_secs(),
+        }),
+    )
+        .into_response())
+}
+
+/// Exchanges the refresh token cookie for a new access token, rotating the refresh token.
+pub async fn refresh(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let presented_token =
+        extract_cookie(&headers, Config::REFRESH_TOKEN_STRING).ok_or(ApiError::Unauthorized)?;
 
-    let user_collection: Collection<UserModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_USERS);
+    let (refresh_token, session) = rotate_refresh_token(
+        state.sessions.as_ref(),
+        &presented_token,
+        Config::refresh_token_ttl(),
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
 
-    let user = user_collection
-        .find_one(doc! { "username": &username }, None)
+    let user = state
+        .users
+        .find_by_id(session.user_id)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    let (access_token, _claims) =
+        state
+            .token_issuer
+            .issue_access_token(&user.username, &session.family_id, &user.roles)?;
+    build_login_response(&state.token_issuer, access_token, refresh_token)
+}
+
+/// Sends `user` a link that activates their account, or makes their pending email their email.
+/// It goes to the pending email, if they have one.
+pub async fn send_verification_mail(
+    token_issuer: &TokenIssuer,
+    mail_sender: &dyn MailSender,
+    user: &UserModel,
+) -> Result<(), ApiError> {
+    let nonce = user
+        .verification_nonce
+        .as_deref()
+        .ok_or_else(|| ApiError::internal(anyhow::anyhow!("user has no verification nonce")))?;
+    let token = token_issuer.issue_link_token(
+        VERIFY_EMAIL_PURPOSE,
+        &user.username,
+        nonce,
+        Config::verification_link_ttl(),
+    )?;
+    let link = format!(
+        "{}/auth/verify?token={token}",
+        String::from(ConfigEnvKey::PublicUrl).trim_end_matches('/')
+    );
+
+    mail_sender
+        .send(Mail {
+            to: user.pending_email.as_ref().unwrap_or(&user.email).clone(),
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Hi {},\n\nfollow this link to activate your account:\n{link}\n",
+                user.username
+            ),
+        })
         .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
-
-    if user.username == username && user.password == password {
-        tracing::info!(user.username, "matched user!");
-        build_login_response(username)
-    } else {
-        Err(StatusCode::NOT_FOUND)
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+/// Activates the account the verification link was sent for.
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Result<Response, ApiError> {
+    let claims = state
+        .token_issuer
+        .validate_link_token(VERIFY_EMAIL_PURPOSE, &query.token)?;
+
+    verify_email_nonce(state.users.as_ref(), &claims.sub, &claims.nonce).await?;
+    Ok((
+        StatusCode::OK,
+        "email verified! you can log in now".to_string(),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Validate)]
+pub struct DtoResendVerification {
+    #[validate(length(min = 1, max = "USERNAME_MAX_CHARS"))]
+    pub username: String,
+}
+
+/// Sends a new verification link, invalidating any earlier one.
+///
+/// Always answers the same, so it cannot be used to find out which usernames exist.
+pub async fn resend_verification(
+    State(state): State<AppState>,
+    ValidatedForm(form): ValidatedForm<DtoResendVerification>,
+) -> Result<Response, ApiError> {
+    if let Some(user) = renew_verification_nonce(state.users.as_ref(), &form.username).await? {
+        send_verification_mail(&state.token_issuer, state.mail_sender.as_ref(), &user).await?;
     }
+
+    Ok((
+        StatusCode::OK,
+        "if the account exists and is not verified yet, a new link has been sent".to_string(),
+    )
+        .into_response())
 }
 
-fn build_login_response(username: String) -> Result<Response, StatusCode> {
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(
-            "Set-Cookie",
-            format!(
-                "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age=999999{}",
-                Config::AUTH_TOKEN_STRING,
-                format!("testing.{}.testing", username),
-                if !bool::from(ConfigEnvKey::DevMode) {
-                    "; Secure;"
-                } else {
-                    ""
-                }
+/// Mails a password reset token to the user.
+///
+/// Always answers the same, so it cannot be used to find out which usernames exist.
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    ValidatedForm(form): ValidatedForm<DtoPasswordForgot>,
+) -> Result<Response, ApiError> {
+    let user = state.users.find_by_username(&form.username).await?;
+
+    if let Some(user) = user {
+        let ttl = Config::password_reset_ttl();
+        let token = create_password_reset(state.password_resets.as_ref(), user._id, ttl).await?;
+        let link = format!(
+            "{}/auth/password/reset?token={token}",
+            String::from(ConfigEnvKey::PublicUrl).trim_end_matches('/')
+        );
+
+        let sent = state
+            .mail_sender
+            .send(Mail {
+                to: user.email,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nfollow this link within {} minutes to choose a new password:\n{link}\n\nIf you did not ask for this, you can ignore this mail.\n",
+                    user.username,
+                    ttl.as_secs() / 60
+                ),
+            })
+            .await;
+        // An error would only ever show up for usernames that exist.
+        match sent {
+            Ok(()) => tracing::info!(user.username, "sent password reset"),
+            Err(err) => tracing::error!(user.username, "could not send password reset: {err}"),
+        }
+    }
+
+    Ok((
+        StatusCode::OK,
+        "if the account exists, a password reset link has been sent".to_string(),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordQuery {
+    pub token: String,
+}
+
+/// The page the link from [`forgot_password`] opens, a form that posts the token along with the
+/// new password to [`reset_password`].
+pub async fn reset_password_page(Query(query): Query<ResetPasswordQuery>) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Reset your password</title>\
+         </head>\n<body>\n<form method=\"post\" action=\"reset\">\n\
+         <input type=\"hidden\" name=\"token\" value=\"{}\">\n\
+         <label>New password <input type=\"password\" name=\"password\" minlength=\"{}\" \
+         maxlength=\"{}\" required></label>\n\
+         <button type=\"submit\">Reset password</button>\n</form>\n</body>\n</html>\n",
+        escape_html(&query.token),
+        PASSWORD_MIN_CHARS,
+        PASSWORD_MAX_CHARS,
+    ))
+}
+
+/// The token comes straight from the query string, so it must not be able to add markup.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Sets a new password using a token from [`forgot_password`], then logs the user out everywhere.
+pub async fn reset_password(
+    State(state): State<AppState>,
+    ValidatedForm(form): ValidatedForm<DtoPasswordReset>,
+) -> Result<Response, ApiError> {
+    let user_id = consume_password_reset(state.password_resets.as_ref(), &form.token).await?;
+    let user = reset_user_password(
+        state.users.as_ref(),
+        user_id,
+        &form.password,
+        &Config::password_params(),
+    )
+    .await?;
+
+    // Whoever knew the old password should not stay logged in.
+    let ended = end_all_sessions(
+        state.sessions.as_ref(),
+        user._id,
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
+    record_success(state.login_attempts.as_ref(), &user.username).await?;
+
+    tracing::info!(
+        user.username,
+        "ended {ended} session(s) after password reset"
+    );
+    build_logout_response()
+}
+
+/// Serves the public keys used to sign our access tokens.
+pub async fn jwks(State(state): State<AppState>) -> Response {
+    Json(state.token_issuer.jwks_document()).into_response()
+}
+
+/// The refresh token is only ever sent to the `/auth` routes, the access token goes everywhere.
+fn build_cookie(name: &str, value: &str, path: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; Path={}; HttpOnly; SameSite=Strict; Max-Age={}{}",
+        name,
+        value,
+        path,
+        max_age,
+        if !bool::from(ConfigEnvKey::DevMode) {
+            "; Secure"
+        } else {
+            ""
+        }
+    )
+}
+
+/// The access token is also returned in the body, for clients that send it as a bearer token
+/// instead of relying on the cookie.
+#[derive(Serialize)]
+pub struct DtoAccessToken {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+}
+
+fn build_login_response(
+    token_issuer: &TokenIssuer,
+    access_token: String,
+    refresh_token: String,
+) -> Result<Response, ApiError> {
+    let expires_in = token_issuer.access_token_ttl().as_secs();
+    Ok((
+        StatusCode::OK,
+        AppendHeaders([
+            (
+                SET_COOKIE,
+                build_cookie(Config::AUTH_TOKEN_STRING, &access_token, "/", expires_in),
             ),
-        )
-        .body(http_body::Empty::new())
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
+            (
+                SET_COOKIE,
+                build_cookie(
+                    Config::REFRESH_TOKEN_STRING,
+                    &refresh_token,
+                    "/auth",
+                    Config::refresh_token_ttl().as_secs(),
+                ),
+            ),
+        ]),
+        Json(DtoAccessToken {
+            access_token,
+            token_type: "Bearer",
+            expires_in,
+        }),
+    )
         .into_response())
 }
 
-fn build_logout_response() -> Result<Response, StatusCode> {
+pub(crate) fn build_logout_response() -> Result<Response, ApiError> {
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(
             "Set-Cookie",
-            format!(
-                "{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age=999999{}",
-                Config::AUTH_TOKEN_STRING,
-                "invalidated",
-                if !bool::from(ConfigEnvKey::DevMode) {
-                    "; Secure;"
-                } else {
-                    ""
-                }
-            ),
+            build_cookie(Config::AUTH_TOKEN_STRING, "", "/", 0),
+        )
+        .header(
+            "Set-Cookie",
+            build_cookie(Config::REFRESH_TOKEN_STRING, "", "/auth", 0),
         )
         .body(http_body::Empty::new())
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(ApiError::internal)?
         .into_response())
 }
 
+/// Ends the session the request was made with, and revokes the access token itself.
 pub async fn user_logout(
-    Extension(client): Extension<Arc<Client>>,
+    State(state): State<AppState>,
     Extension(user): Extension<DtoUser>,
-) -> Result<Response, StatusCode> {
-    let user_collection: Collection<UserModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_USERS);
+    Extension(auth_context): Extension<AuthContext>,
+) -> Result<Response, ApiError> {
+    if let (Some(jti), Some(expires_at)) = (&auth_context.jti, auth_context.expires_at) {
+        let expires_at = mongodb::bson::DateTime::from_millis(expires_at * 1000);
+        revoke_access_token(state.sessions.as_ref(), user._id, jti, expires_at).await?;
+    }
 
-    user_collection
-        .find_one(doc! { "username": &user.username }, None)
+    if let Some(session_id) = &auth_context.session_id {
+        match end_session(
+            state.sessions.as_ref(),
+            user._id,
+            session_id,
+            state.token_issuer.access_token_ttl(),
+        )
         .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        {
+            // The refresh tokens may already have been cleaned up, the access token is revoked.
+            Ok(()) | Err(ApiError::NotFound) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    tracing::info!(user.username, "logged out");
+    build_logout_response()
+}
+
+/// Lists the caller's active sessions.
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Extension(auth_context): Extension<AuthContext>,
+) -> Result<Response, ApiError> {
+    let mut sessions = list_sessions(state.sessions.as_ref(), user._id).await?;
+    for session in sessions.iter_mut() {
+        session.current = auth_context.session_id.as_ref() == Some(&session.session_id);
+    }
 
+    Ok((StatusCode::OK, Json(sessions)).into_response())
+}
+
+/// Logs the caller out everywhere, including the session the request was made with.
+pub async fn delete_sessions(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+) -> Result<Response, ApiError> {
+    let ended = end_all_sessions(
+        state.sessions.as_ref(),
+        user._id,
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
+
+    tracing::info!(user.username, "ended {ended} session(s)");
     build_logout_response()
 }
+
+/// Ends one of the caller's sessions, for example one left logged in on another device.
+pub async fn delete_session(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Path(session_id): Path<String>,
+) -> Result<Response, ApiError> {
+    end_session(
+        state.sessions.as_ref(),
+        user._id,
+        &session_id,
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, "session ended!".to_string()).into_response())
+}
+
+/// Lists the usernames and IPs with recent failed logins, and whether they are locked out.
+pub async fn get_lockouts(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let lockouts = list_lockouts(state.login_attempts.as_ref()).await?;
+    Ok((StatusCode::OK, Json(lockouts)).into_response())
+}
+
+/// Lifts a lockout, for example for a user that forgot their password and got locked out.
+pub async fn delete_lockout(
+    State(state): State<AppState>,
+    Extension(admin): Extension<DtoUser>,
+    Path((kind, subject)): Path<(LockoutKind, String)>,
+) -> Result<Response, ApiError> {
+    unlock(state.login_attempts.as_ref(), kind, &subject).await?;
+    tracing::info!(admin.username, "unlocked {} {subject}", kind.as_str());
+    Ok((StatusCode::OK, "unlocked!".to_string()).into_response())
+}
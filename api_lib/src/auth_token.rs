@@ -0,0 +1,355 @@
+use error_lib::api_error::ApiError;
+use std::time::Duration;
+
+use alcoholic_jwt::{validate, Validation, JWKS};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use config_lib::{config::Config, config_env::ConfigEnvKey};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    rand::rand_bytes,
+    rsa::Rsa,
+    sha::sha256,
+    sign::Signer,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use user_lib::user_models::Role;
+
+/// Claims carried by the access tokens this service issues.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccessClaims {
+    pub iss: String,
+    /// The username of the user the token was issued to.
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub jti: String,
+    /// The session (refresh token family) the token was issued for.
+    pub sid: String,
+    /// The roles the user had when the token was issued. Access checks use the current roles of
+    /// the user instead, so a demotion takes effect immediately.
+    pub roles: Vec<Role>,
+}
+
+/// Claims carried by single purpose tokens, like the ones in links we mail to users.
+///
+/// `purpose` keeps a token meant for one kind of link from being used for another, and tokens
+/// with a `purpose` are never accepted as access tokens.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinkClaims {
+    pub iss: String,
+    /// The username of the user the token was issued to.
+    pub sub: String,
+    pub iat: i64,
+    pub exp: i64,
+    pub purpose: String,
+    /// For links that should only work once, this is also stored on the user.
+    pub nonce: String,
+}
+
+/// Signs access tokens with the service's own RSA key, and publishes the matching public key.
+///
+/// Tokens are RS256 so that they can be validated by `alcoholic_jwt` in exactly the same way
+/// as tokens from an external authority.
+pub struct TokenIssuer {
+    issuer: String,
+    kid: String,
+    key: PKey<Private>,
+    jwks: JWKS,
+    jwks_document: Value,
+    access_token_ttl: Duration,
+}
+
+impl TokenIssuer {
+    pub fn new(
+        key: Rsa<Private>,
+        issuer: String,
+        access_token_ttl: Duration,
+    ) -> anyhow::Result<TokenIssuer> {
+        let n = URL_SAFE_NO_PAD.encode(key.n().to_vec());
+        let e = URL_SAFE_NO_PAD.encode(key.e().to_vec());
+
+        // RFC 7638 thumbprint, so the kid changes whenever the key does.
+        let thumbprint = format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#);
+        let kid = URL_SAFE_NO_PAD.encode(sha256(thumbprint.as_bytes()));
+
+        let jwks_document = json!({
+            "keys": [{
+                "kty": "RSA",
+                "alg": "RS256",
+                "use": "sig",
+                "kid": kid,
+                "n": n,
+                "e": e,
+            }]
+        });
+        let jwks = serde_json::from_value(jwks_document.clone())?;
+
+        Ok(TokenIssuer {
+            issuer,
+            kid,
+            key: PKey::from_rsa(key)?,
+            jwks,
+            jwks_document,
+            access_token_ttl,
+        })
+    }
+
+    /// Builds the issuer from the environment.
+    ///
+    /// In dev mode an ephemeral key is generated when none is configured, meaning tokens will not
+    /// survive a restart.
+    pub fn from_config() -> TokenIssuer {
+        let key = match Config::jwt_private_key_pem() {
+            Some(pem) => Rsa::private_key_from_pem(&pem)
+                .expect("jwt private key should be a PEM encoded RSA private key"),
+            None if bool::from(ConfigEnvKey::DevMode) => {
+                tracing::warn!("no jwt private key configured, generating an ephemeral one");
+                Rsa::generate(2048).expect("generating an rsa key should succeed")
+            }
+            None => panic!(
+                "{} must be set when not running in dev mode",
+                ConfigEnvKey::JwtPrivateKeyPath.as_str()
+            ),
+        };
+
+        TokenIssuer::new(
+            key,
+            String::from(ConfigEnvKey::JwtIssuer),
+            Duration::from_secs(u32::from(ConfigEnvKey::AccessTokenTtlSecs).into()),
+        )
+        .expect("building the token issuer should succeed")
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn access_token_ttl(&self) -> Duration {
+        self.access_token_ttl
+    }
+
+    pub fn jwks(&self) -> &JWKS {
+        &self.jwks
+    }
+
+    /// The public key set, as served from `/.well-known/jwks.json`.
+    pub fn jwks_document(&self) -> &Value {
+        &self.jwks_document
+    }
+
+    pub fn issue_access_token(
+        &self,
+        subject: &str,
+        session_id: &str,
+        roles: &[Role],
+    ) -> Result<(String, AccessClaims), ApiError> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = AccessClaims {
+            iss: self.issuer.clone(),
+            sub: subject.to_string(),
+            iat: now,
+            exp: now + self.access_token_ttl.as_secs() as i64,
+            jti: new_token_id()?,
+            sid: session_id.to_string(),
+            roles: roles.to_vec(),
+        };
+
+        Ok((self.sign(&claims)?, claims))
+    }
+
+    /// Issues a single purpose token for `subject`, see [`LinkClaims`].
+    pub fn issue_link_token(
+        &self,
+        purpose: &str,
+        subject: &str,
+        nonce: &str,
+        ttl: Duration,
+    ) -> Result<String, ApiError> {
+        let now = chrono::Utc::now().timestamp();
+        self.sign(&LinkClaims {
+            iss: self.issuer.clone(),
+            sub: subject.to_string(),
+            iat: now,
+            exp: now + ttl.as_secs() as i64,
+            purpose: purpose.to_string(),
+            nonce: nonce.to_string(),
+        })
+    }
+
+    /// Checks a single purpose token was issued by us, for `purpose`, and has not expired.
+    pub fn validate_link_token(&self, purpose: &str, token: &str) -> Result<LinkClaims, ApiError> {
+        let jwk = self
+            .jwks
+            .find(&self.kid)
+            .ok_or_else(|| ApiError::internal(anyhow::anyhow!("own signing key is missing")))?;
+        let valid_jwt = validate(
+            token,
+            jwk,
+            vec![
+                Validation::Issuer(self.issuer.clone()),
+                Validation::SubjectPresent,
+                Validation::NotExpired,
+            ],
+        )
+        .map_err(|_err| invalid_link())?;
+
+        let claims: LinkClaims =
+            serde_json::from_value(valid_jwt.claims).map_err(|_err| invalid_link())?;
+        if claims.purpose != purpose {
+            return Err(invalid_link());
+        }
+        Ok(claims)
+    }
+
+    fn sign<C: Serialize>(&self, claims: &C) -> Result<String, ApiError> {
+        let header = json!({ "alg": "RS256", "typ": "JWT", "kid": self.kid });
+        let signing_input = format!("{}.{}", encode_part(&header)?, encode_part(claims)?);
+
+        let signature = Signer::new(MessageDigest::sha256(), &self.key)
+            .and_then(|mut signer| {
+                signer.update(signing_input.as_bytes())?;
+                signer.sign_to_vec()
+            })
+            .map_err(|err| {
+                ApiError::internal(anyhow::Error::new(err).context("failed to sign token"))
+            })?;
+
+        Ok(format!(
+            "{signing_input}.{}",
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+fn invalid_link() -> ApiError {
+    ApiError::BadRequest("this link is invalid or has expired".to_string())
+}
+
+/// Generates a random, url safe identifier suitable for a `jti` claim.
+pub fn new_token_id() -> Result<String, ApiError> {
+    let mut bytes = [0u8; 16];
+    rand_bytes(&mut bytes).map_err(ApiError::internal)?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn encode_part<T: Serialize>(part: &T) -> Result<String, ApiError> {
+    let json = serde_json::to_vec(part).map_err(ApiError::internal)?;
+    Ok(URL_SAFE_NO_PAD.encode(json))
+}
+
+#[cfg(test)]
+mod token_tests {
+    use alcoholic_jwt::token_kid;
+    use axum::http::StatusCode;
+
+    use super::*;
+
+    fn test_issuer() -> TokenIssuer {
+        TokenIssuer::new(
+            Rsa::generate(2048).unwrap(),
+            "poc_rear_test".to_string(),
+            Duration::from_secs(60),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn issued_token_validates_against_jwks() {
+        let issuer = test_issuer();
+        let (token, claims) = issuer
+            .issue_access_token("jorkridesher", "session", &[Role::Moderator])
+            .unwrap();
+
+        let kid = token_kid(&token).unwrap().unwrap();
+        assert_eq!(issuer.kid(), kid);
+
+        let jwk = issuer.jwks().find(&kid).unwrap();
+        let valid = validate(
+            &token,
+            jwk,
+            vec![
+                Validation::Issuer("poc_rear_test".to_string()),
+                Validation::SubjectPresent,
+                Validation::NotExpired,
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(valid.claims["sub"], "jorkridesher");
+        assert_eq!(valid.claims["jti"], claims.jti.as_str());
+        assert_eq!(valid.claims["sid"], "session");
+        assert_eq!(valid.claims["roles"], json!(["moderator"]));
+        assert_eq!(claims.exp - claims.iat, 60);
+    }
+
+    #[test]
+    fn token_from_other_key_is_rejected() {
+        let issuer = test_issuer();
+        let (token, _) = test_issuer()
+            .issue_access_token("jorkridesher", "session", &[Role::Moderator])
+            .unwrap();
+
+        let jwk = issuer.jwks().find(issuer.kid()).unwrap();
+        assert!(validate(&token, jwk, vec![]).is_err());
+    }
+
+    #[test]
+    fn link_token_round_trip() {
+        let issuer = test_issuer();
+        let token = issuer
+            .issue_link_token(
+                "verify-email",
+                "jorkridesher",
+                "nonce",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+
+        let claims = issuer.validate_link_token("verify-email", &token).unwrap();
+
+        assert_eq!("jorkridesher", claims.sub);
+        assert_eq!("nonce", claims.nonce);
+    }
+
+    #[test]
+    fn link_token_for_other_purpose_is_rejected() {
+        let issuer = test_issuer();
+        let token = issuer
+            .issue_link_token(
+                "verify-email",
+                "jorkridesher",
+                "nonce",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let (access_token, _) = issuer
+            .issue_access_token("jorkridesher", "session", &[Role::User])
+            .unwrap();
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            issuer
+                .validate_link_token("reset-password", &token)
+                .unwrap_err()
+                .status()
+        );
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            issuer
+                .validate_link_token("verify-email", &access_token)
+                .unwrap_err()
+                .status()
+        );
+    }
+
+    #[test]
+    fn token_ids_are_unique() {
+        assert_ne!(new_token_id().unwrap(), new_token_id().unwrap());
+    }
+}
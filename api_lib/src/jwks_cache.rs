@@ -0,0 +1,384 @@
+use error_lib::api_error::ApiError;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use alcoholic_jwt::{JWK, JWKS};
+use anyhow::Result;
+use config_lib::config_env::ConfigEnvKey;
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::JoinHandle,
+};
+
+struct CachedJwks {
+    jwks: JWKS,
+    fetched_at: Instant,
+    max_age: Duration,
+}
+
+impl CachedJwks {
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < self.max_age
+    }
+}
+
+/// Keeps the key set of the external authority in memory, so it is not fetched for every request.
+///
+/// - Keys are cached for as long as the authority's `Cache-Control: max-age` says.
+/// - A token with an unknown `kid` triggers a re-fetch, in case the authority rotated its keys.
+/// - If the authority cannot be reached the last known keys keep being used, and it is left
+///   alone for `RETRY_INTERVAL` before anyone tries again.
+pub struct JwksCache {
+    authority: String,
+    uri: String,
+    http: reqwest::Client,
+    cached: RwLock<Option<Arc<CachedJwks>>>,
+    /// When the last fetch failed, cleared once one succeeds.
+    failed_at: RwLock<Option<Instant>>,
+    fetch_lock: Mutex<()>,
+    unknown_kid_refetch_interval: Duration,
+}
+
+impl JwksCache {
+    /// Used when the authority does not send a usable `Cache-Control` header.
+    pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+    /// The `max-age` from the authority is clamped to this range.
+    pub const MIN_MAX_AGE: Duration = Duration::from_secs(30);
+    pub const MAX_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Stops tokens with made up `kid`s from making us hammer the authority.
+    pub const UNKNOWN_KID_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+    /// How long to wait before trying the authority again after a failed fetch.
+    pub const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+    pub const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(authority: String) -> JwksCache {
+        let uri = format!("{}{}", authority.as_str(), ".well-known/jwks.json");
+        JwksCache {
+            authority,
+            uri,
+            http: reqwest::Client::builder()
+                .timeout(JwksCache::FETCH_TIMEOUT)
+                .build()
+                .expect("building the http client should succeed"),
+            cached: RwLock::new(None),
+            failed_at: RwLock::new(None),
+            fetch_lock: Mutex::new(()),
+            unknown_kid_refetch_interval: JwksCache::UNKNOWN_KID_REFETCH_INTERVAL,
+        }
+    }
+
+    /// Returns `None` when no external authority is configured.
+    pub fn from_config() -> Option<JwksCache> {
+        Option::<String>::from(ConfigEnvKey::Authority).map(JwksCache::new)
+    }
+
+    /// The issuer tokens validated with these keys must have.
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// Finds the key a token was signed with.
+    pub async fn find(&self, kid: &str) -> Result<JWK, ApiError> {
+        let cached = self.cached.read().await.clone();
+
+        if let Some(cached) = &cached {
+            if cached.is_fresh() {
+                if let Some(jwk) = cached.jwks.find(kid) {
+                    return Ok(jwk.clone());
+                }
+                if cached.fetched_at.elapsed() < self.unknown_kid_refetch_interval {
+                    return Err(ApiError::Unauthorized);
+                }
+                tracing::debug!(kid, "unknown kid, re-fetching jwks");
+            }
+        }
+
+        // Without this every request would wait its turn for a fetch of its own while the
+        // authority is down.
+        if !self.failed_recently().await {
+            match self.refresh(cached.as_ref().map(|c| c.fetched_at)).await {
+                Ok(refreshed) => {
+                    return refreshed
+                        .jwks
+                        .find(kid)
+                        .cloned()
+                        .ok_or(ApiError::Unauthorized)
+                }
+                Err(err) => tracing::warn!("failed to fetch jwks from {}: {err}", self.uri),
+            }
+        }
+
+        match cached {
+            Some(stale) => stale.jwks.find(kid).cloned().ok_or(ApiError::Unauthorized),
+            None => Err(ApiError::Unavailable(
+                "the keys of the token authority cannot be fetched".to_string(),
+            )),
+        }
+    }
+
+    async fn failed_recently(&self) -> bool {
+        self.failed_at
+            .read()
+            .await
+            .is_some_and(|failed_at| failed_at.elapsed() < JwksCache::RETRY_INTERVAL)
+    }
+
+    /// Fetches the key set, unless another caller already did so after `since`, or a fetch
+    /// failed less than `RETRY_INTERVAL` ago.
+    async fn refresh(&self, since: Option<Instant>) -> Result<Arc<CachedJwks>> {
+        let _guard = self.fetch_lock.lock().await;
+
+        if let Some(cached) = self.cached.read().await.as_ref() {
+            let refreshed_meanwhile = match since {
+                Some(since) => cached.fetched_at > since,
+                None => true,
+            };
+            if refreshed_meanwhile {
+                return Ok(cached.clone());
+            }
+        }
+        // The callers that waited on the lock while that fetch failed do not try again.
+        if self.failed_recently().await {
+            anyhow::bail!(
+                "the last fetch failed less than {:?} ago",
+                JwksCache::RETRY_INTERVAL
+            );
+        }
+
+        match self.fetch().await {
+            Ok(fetched) => {
+                let fetched = Arc::new(fetched);
+                *self.cached.write().await = Some(fetched.clone());
+                *self.failed_at.write().await = None;
+                tracing::trace!("got jwks from authority");
+                Ok(fetched)
+            }
+            Err(err) => {
+                *self.failed_at.write().await = Some(Instant::now());
+                Err(err)
+            }
+        }
+    }
+
+    async fn fetch(&self) -> Result<CachedJwks> {
+        let response = self.http.get(&self.uri).send().await?.error_for_status()?;
+        let max_age = response
+            .headers()
+            .get(http::header::CACHE_CONTROL)
+            .and_then(|header| header.to_str().ok())
+            .and_then(parse_max_age)
+            .unwrap_or(JwksCache::DEFAULT_MAX_AGE)
+            .clamp(JwksCache::MIN_MAX_AGE, JwksCache::MAX_MAX_AGE);
+
+        Ok(CachedJwks {
+            jwks: response.json::<JWKS>().await?,
+            fetched_at: Instant::now(),
+            max_age,
+        })
+    }
+
+    /// Keeps the keys fresh in the background, so requests rarely have to wait on the authority.
+    pub fn spawn_refresh_task(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let since = self.cached.read().await.as_ref().map(|c| c.fetched_at);
+                let wait = match self.refresh(since).await {
+                    // Refresh a little early, so the keys never go stale while in use.
+                    Ok(cached) => cached.max_age.mul_f64(0.9),
+                    Err(err) => {
+                        tracing::warn!("failed to refresh jwks from {}: {err}", self.uri);
+                        JwksCache::RETRY_INTERVAL
+                    }
+                };
+                tokio::time::sleep(wait).await;
+            }
+        })
+    }
+}
+
+/// Reads the `max-age` directive of a `Cache-Control` header.
+///
+/// `no-cache` and `no-store` are treated as a `max-age` of zero.
+fn parse_max_age(cache_control: &str) -> Option<Duration> {
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        {
+            return Some(Duration::ZERO);
+        }
+        if let Some((name, value)) = directive.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                max_age = value.trim().parse::<u64>().ok().map(Duration::from_secs);
+            }
+        }
+    }
+    max_age
+}
+
+#[cfg(test)]
+mod jwks_cache_tests {
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::get,
+        Json, Router,
+    };
+    use openssl::rsa::Rsa;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::auth_token::TokenIssuer;
+
+    /// Stands in for the authority, serving whichever key set it currently holds.
+    #[derive(Clone)]
+    struct StubAuthority {
+        jwks: Arc<std::sync::Mutex<Value>>,
+        hits: Arc<AtomicUsize>,
+        down: Arc<AtomicBool>,
+    }
+
+    async fn serve_jwks(State(stub): State<StubAuthority>) -> Response {
+        stub.hits.fetch_add(1, Ordering::SeqCst);
+        if stub.down.load(Ordering::SeqCst) {
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+        let jwks = stub.jwks.lock().unwrap().clone();
+        (
+            [(http::header::CACHE_CONTROL, "public, max-age=600")],
+            Json(jwks),
+        )
+            .into_response()
+    }
+
+    fn key_set() -> (String, Value) {
+        let issuer = TokenIssuer::new(
+            Rsa::generate(2048).unwrap(),
+            "stub".to_string(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        (issuer.kid().to_string(), issuer.jwks_document().clone())
+    }
+
+    async fn start_stub(jwks: Value) -> (StubAuthority, String) {
+        let stub = StubAuthority {
+            jwks: Arc::new(std::sync::Mutex::new(jwks)),
+            hits: Arc::new(AtomicUsize::new(0)),
+            down: Arc::new(AtomicBool::new(false)),
+        };
+        let app = Router::new()
+            .route("/.well-known/jwks.json", get(serve_jwks))
+            .with_state(stub.clone());
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let authority = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        (stub, authority)
+    }
+
+    #[tokio::test]
+    async fn keys_are_cached() {
+        let (kid, jwks) = key_set();
+        let (stub, authority) = start_stub(jwks).await;
+        let cache = JwksCache::new(authority);
+
+        assert!(cache.find(&kid).await.is_ok());
+        assert!(cache.find(&kid).await.is_ok());
+        assert_eq!(1, stub.hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_triggers_refetch() {
+        let (old_kid, old_jwks) = key_set();
+        let (new_kid, new_jwks) = key_set();
+        let (stub, authority) = start_stub(old_jwks).await;
+        let mut cache = JwksCache::new(authority);
+        cache.unknown_kid_refetch_interval = Duration::ZERO;
+
+        assert!(cache.find(&old_kid).await.is_ok());
+
+        *stub.jwks.lock().unwrap() = new_jwks;
+        assert!(cache.find(&new_kid).await.is_ok());
+        assert_eq!(2, stub.hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn unknown_kid_refetch_is_rate_limited() {
+        let (kid, jwks) = key_set();
+        let (stub, authority) = start_stub(jwks).await;
+        let cache = JwksCache::new(authority);
+
+        assert!(cache.find(&kid).await.is_ok());
+        assert_eq!(
+            Err(StatusCode::UNAUTHORIZED),
+            cache
+                .find("made-up")
+                .await
+                .map(|_| ())
+                .map_err(|err| err.status())
+        );
+        assert_eq!(1, stub.hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn stale_keys_are_served_during_outage() {
+        let (kid, jwks) = key_set();
+        let (stub, authority) = start_stub(jwks).await;
+        let cache = JwksCache::new(authority);
+
+        assert!(cache.find(&kid).await.is_ok());
+
+        // Expire the cached keys, then take the authority down.
+        if let Some(cached) = cache.cached.write().await.as_mut() {
+            *cached = Arc::new(CachedJwks {
+                jwks: cached.jwks.clone(),
+                fetched_at: cached.fetched_at,
+                max_age: Duration::ZERO,
+            });
+        }
+        stub.down.store(true, Ordering::SeqCst);
+
+        assert!(cache.find(&kid).await.is_ok());
+        // Served from the stale keys without asking the authority again.
+        assert!(cache.find(&kid).await.is_ok());
+        assert_eq!(2, stub.hits.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn no_keys_and_no_authority_is_unavailable() {
+        let (kid, jwks) = key_set();
+        let (stub, authority) = start_stub(jwks).await;
+        stub.down.store(true, Ordering::SeqCst);
+        let cache = JwksCache::new(authority);
+
+        assert_eq!(
+            Err(StatusCode::SERVICE_UNAVAILABLE),
+            cache
+                .find(&kid)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.status())
+        );
+    }
+
+    #[test]
+    fn parse_cache_control() {
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            parse_max_age("public, max-age=600")
+        );
+        assert_eq!(Some(Duration::ZERO), parse_max_age("no-store"));
+        assert_eq!(None, parse_max_age("public"));
+        assert_eq!(None, parse_max_age("max-age=soon"));
+    }
+}
@@ -1,5 +1,11 @@
+pub mod app_router;
+pub mod app_state;
 pub mod auth_guard;
 pub mod auth_routes;
+pub mod auth_token;
+pub mod jwks_cache;
+pub mod totp_routes;
 pub mod user_routes;
+pub mod validation;
 pub mod webutil;
 pub mod word_routes;
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use config_lib::config_env::ConfigEnvKey;
+use error_lib::api_error::ApiError;
+use user_lib::{
+    user_lockout::record_success,
+    user_models::{DtoUser, UserModel},
+    user_totp::{
+        begin_enrollment, confirm_enrollment, disable_totp, otpauth_uri, verify_second_factor,
+        DtoRecoveryCodes, DtoTotpCode, DtoTotpEnrollment, DtoTotpLogin,
+    },
+};
+
+use crate::{
+    app_state::AppState,
+    auth_routes::{check_lockout, record_login_failure, start_session, LOGIN_CHALLENGE_PURPOSE},
+    validation::{ValidatedBody, ValidatedJson},
+    webutil::client_ip,
+};
+
+/// The guard only hands out a `DtoUser`, the second factor state lives on the `UserModel`.
+async fn find_user_model(state: &AppState, user: &DtoUser) -> Result<UserModel, ApiError> {
+    state
+        .users
+        .find_by_id(user._id)
+        .await?
+        .ok_or(ApiError::Unauthorized)
+}
+
+/// Starts enrolling the caller, returning the secret to add to their authenticator app.
+///
+/// Calling this again before confirming replaces the secret.
+pub async fn enroll(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+) -> Result<Response, ApiError> {
+    let secret = begin_enrollment(state.users.as_ref(), user._id).await?;
+    let otpauth_uri = otpauth_uri(state.token_issuer.issuer(), &user.username, &secret);
+
+    Ok((
+        StatusCode::OK,
+        Json(DtoTotpEnrollment {
+            secret,
+            otpauth_uri,
+        }),
+    )
+        .into_response())
+}
+
+/// Finishes enrollment with a code from the authenticator app, returning the recovery codes.
+pub async fn confirm(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    ValidatedJson(dto_code): ValidatedJson<DtoTotpCode>,
+) -> Result<Response, ApiError> {
+    let user_model = find_user_model(&state, &user).await?;
+    let recovery_codes =
+        confirm_enrollment(state.users.as_ref(), &user_model, &dto_code.code).await?;
+
+    Ok((StatusCode::OK, Json(DtoRecoveryCodes { recovery_codes })).into_response())
+}
+
+/// Turns the second factor off. Needs a current code, so a stolen session is not enough.
+pub async fn disable(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    ValidatedJson(dto_code): ValidatedJson<DtoTotpCode>,
+) -> Result<Response, ApiError> {
+    let user_model = find_user_model(&state, &user).await?;
+    disable_totp(state.users.as_ref(), &user_model, &dto_code.code).await?;
+
+    Ok((
+        StatusCode::OK,
+        "two-factor authentication disabled!".to_string(),
+    )
+        .into_response())
+}
+
+/// Completes a login that `/auth/login` answered with a challenge.
+///
+/// Wrong codes count as failed logins, so they lead to a lockout just like wrong passwords.
+pub async fn login_totp(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ValidatedBody(form): ValidatedBody<DtoTotpLogin>,
+) -> Result<Response, ApiError> {
+    let claims = state
+        .token_issuer
+        .validate_link_token(LOGIN_CHALLENGE_PURPOSE, &form.challenge)
+        .map_err(|_err| ApiError::Unauthorized)?;
+
+    let ip = client_ip(&headers, peer, bool::from(ConfigEnvKey::TrustForwardedFor)).to_string();
+    check_lockout(&state, &claims.sub, &ip).await?;
+
+    let user = state
+        .users
+        .find_by_username(&claims.sub)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    match verify_second_factor(state.users.as_ref(), &user, &form.code).await {
+        Ok(()) => {}
+        Err(ApiError::Unauthorized) => {
+            tracing::info!(user.username, "wrong second factor");
+            record_login_failure(&state, &user.username, &ip).await?;
+            return Err(ApiError::Unauthorized);
+        }
+        Err(err) => return Err(err),
+    }
+    record_success(state.login_attempts.as_ref(), &user.username).await?;
+
+    tracing::info!(user.username, "matched user and second factor!");
+    start_session(&state, &user).await
+}
@@ -1,29 +1,243 @@
-use axum::{extract::Path, http::StatusCode, response::Response, Extension, Form};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use bson::oid::ObjectId;
 use config_lib::config::Config;
-use mongodb::Client;
+use error_lib::api_error::ApiError;
 use user_lib::{
-    user_logic::{create_new_user, get_one_user},
-    user_models::{DtoUserCreate, UserModel},
+    user_api_key::{
+        create_api_key, delete_api_keys, list_api_keys, revoke_api_key, ApiKeyScope,
+        DtoApiKeyCreate,
+    },
+    user_lockout::record_success,
+    user_logic::{
+        change_password, confirm_password, create_new_user, delete_user, get_one_user,
+        set_user_roles, update_user,
+    },
+    user_models::{
+        DtoAccountDelete, DtoPasswordChange, DtoPrivateProfile, DtoUser, DtoUserCreate,
+        DtoUserRoles, DtoUserUpdate, Role,
+    },
+    user_password_reset::delete_password_resets,
+    user_session::end_all_sessions,
+};
+use wotd_lib::word_logic::anonymise_creator;
+
+use crate::{
+    app_state::AppState,
+    auth_routes::{build_logout_response, send_verification_mail, start_session},
+    validation::{ValidatedBody, ValidatedJson},
 };
 
 pub async fn create_user(
-    Extension(client): Extension<std::sync::Arc<Client>>,
-    Form(create_user_form): Form<DtoUserCreate>,
-) -> Result<Response, StatusCode> {
-    let collection = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_USERS);
+    State(state): State<AppState>,
+    ValidatedBody(create_user_form): ValidatedBody<DtoUserCreate>,
+) -> Result<Response, ApiError> {
+    let user = create_new_user(
+        state.users.as_ref(),
+        create_user_form,
+        &Config::password_params(),
+    )
+    .await?;
+
+    // The account exists either way, an error would make a retry run into a 409.
+    let message = match send_verification_mail(
+        &state.token_issuer,
+        state.mail_sender.as_ref(),
+        &user,
+    )
+    .await
+    {
+        Ok(()) => "user created! check your email to activate your account",
+        Err(err) => {
+            tracing::error!(user.username, "could not send verification mail: {err}");
+            "user created! the activation mail could not be sent, ask for a new one at \
+             /auth/verify/resend"
+        }
+    };
 
-    create_new_user(collection, create_user_form).await
+    Ok((StatusCode::OK, message.to_string()).into_response())
 }
 
 pub async fn get_user(
-    Extension(client): Extension<std::sync::Arc<Client>>,
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Path(username): Path<String>,
+) -> Result<Response, ApiError> {
+    get_one_user(state.users.as_ref(), &user, username).await
+}
+
+/// The private profile of the caller.
+pub async fn get_me(Extension(user): Extension<DtoUser>) -> Result<Response, ApiError> {
+    Ok((StatusCode::OK, Json(DtoPrivateProfile::from(user))).into_response())
+}
+
+pub async fn update_user_roles(
+    State(state): State<AppState>,
+    Extension(admin): Extension<DtoUser>,
     Path(username): Path<String>,
-) -> Result<Response, StatusCode> {
-    let collection: mongodb::Collection<UserModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_USERS);
+    ValidatedJson(dto_roles): ValidatedJson<DtoUserRoles>,
+) -> Result<Response, ApiError> {
+    tracing::info!(admin.username, "updating roles of {username}");
+    set_user_roles(state.users.as_ref(), username, dto_roles.roles).await
+}
+
+/// Changes the display name of the caller. A new email is only used once the caller follows the
+/// link sent to it.
+pub async fn update_me(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    ValidatedJson(dto_update): ValidatedJson<DtoUserUpdate>,
+) -> Result<Response, ApiError> {
+    let email_changed = dto_update.email.is_some();
+    let updated = update_user(
+        state.users.as_ref(),
+        user._id,
+        dto_update,
+        &Config::password_params(),
+    )
+    .await?;
+    if email_changed {
+        send_verification_mail(&state.token_issuer, state.mail_sender.as_ref(), &updated).await?;
+    }
+    tracing::info!(user.username, "updated profile");
+    Ok((
+        StatusCode::OK,
+        Json(DtoPrivateProfile::from(DtoUser::from(updated))),
+    )
+        .into_response())
+}
+
+/// Changes the password of the caller. Every other session is ended, the caller gets a new one.
+pub async fn change_my_password(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    ValidatedJson(dto_change): ValidatedJson<DtoPasswordChange>,
+) -> Result<Response, ApiError> {
+    let updated = change_password(
+        state.users.as_ref(),
+        user._id,
+        &dto_change.current_password,
+        &dto_change.new_password,
+        &Config::password_params(),
+    )
+    .await?;
+
+    end_all_sessions(
+        state.sessions.as_ref(),
+        user._id,
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
+    start_session(&state, &updated).await
+}
+
+/// Deletes the account of the caller, along with their sessions and keys.
+///
+/// Words they created stay, but no longer point at them.
+pub async fn delete_me(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    ValidatedJson(dto_delete): ValidatedJson<DtoAccountDelete>,
+) -> Result<Response, ApiError> {
+    confirm_password(
+        state.users.as_ref(),
+        user._id,
+        &dto_delete.password,
+        &Config::password_params(),
+    )
+    .await?;
+
+    anonymise_creator(
+        state.words.as_ref(),
+        state.queue.as_ref(),
+        state.history.as_ref(),
+        user._id,
+    )
+    .await?;
+    end_all_sessions(
+        state.sessions.as_ref(),
+        user._id,
+        state.token_issuer.access_token_ttl(),
+    )
+    .await?;
+    delete_api_keys(state.api_keys.as_ref(), user._id).await?;
+    delete_password_resets(state.password_resets.as_ref(), user._id).await?;
+    record_success(state.login_attempts.as_ref(), &user.username).await?;
+    delete_user(state.users.as_ref(), user._id).await?;
+
+    tracing::info!(user.username, "deleted account");
+    build_logout_response()
+}
+
+/// Users manage their own keys, admins can also see and revoke the keys of others.
+async fn key_owner_id(
+    state: &AppState,
+    user: &DtoUser,
+    username: &str,
+) -> Result<ObjectId, ApiError> {
+    if user.username == username {
+        return Ok(user._id);
+    }
+    if !user.has_role(Role::Admin) {
+        return Err(ApiError::Forbidden);
+    }
+
+    let owner = state
+        .users
+        .find_by_username(username)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    Ok(owner._id)
+}
+
+/// Creates an API key for the caller. The key is only ever shown in this response.
+pub async fn create_key(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Path(username): Path<String>,
+    ValidatedJson(create_key_form): ValidatedJson<DtoApiKeyCreate>,
+) -> Result<Response, ApiError> {
+    // A key acts as its owner, nobody gets to create one for someone else.
+    if user.username != username {
+        return Err(ApiError::Forbidden);
+    }
+    if create_key_form.scopes.contains(&ApiKeyScope::RotateWotd) && !user.has_role(Role::Moderator)
+    {
+        return Err(ApiError::Forbidden);
+    }
+
+    let new_key = create_api_key(state.api_keys.as_ref(), user._id, create_key_form).await?;
+    tracing::info!(
+        user.username,
+        "created api key {}",
+        new_key.api_key.key_hint
+    );
+    Ok((StatusCode::CREATED, Json(new_key)).into_response())
+}
+
+pub async fn get_keys(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Path(username): Path<String>,
+) -> Result<Response, ApiError> {
+    let owner_id = key_owner_id(&state, &user, &username).await?;
+
+    let keys = list_api_keys(state.api_keys.as_ref(), owner_id).await?;
+    Ok((StatusCode::OK, Json(keys)).into_response())
+}
+
+pub async fn delete_key(
+    State(state): State<AppState>,
+    Extension(user): Extension<DtoUser>,
+    Path((username, key_id)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    let owner_id = key_owner_id(&state, &user, &username).await?;
 
-    get_one_user(collection, username).await
+    revoke_api_key(state.api_keys.as_ref(), owner_id, &key_id).await?;
+    tracing::info!(user.username, "revoked api key {key_id} of {username}");
+    Ok((StatusCode::OK, "api key revoked!".to_string()).into_response())
 }
@@ -0,0 +1,359 @@
+use axum::{
+    async_trait,
+    extract::{
+        multipart::MultipartRejection,
+        rejection::{FormRejection, JsonRejection},
+        FromRequest, Multipart,
+    },
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    response::IntoResponse,
+    Form, Json,
+};
+use error_lib::api_error::{ApiError, DtoFieldError};
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+/// Flattens nested validation errors into a list, sorted by field.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<DtoFieldError> {
+    let mut flat = Vec::new();
+    collect_field_errors(errors, None, &mut flat);
+    flat.sort_by(|a, b| (&a.field, &a.code).cmp(&(&b.field, &b.code)));
+    flat
+}
+
+fn collect_field_errors(
+    errors: &ValidationErrors,
+    prefix: Option<&str>,
+    flat: &mut Vec<DtoFieldError>,
+) {
+    for (field, kind) in errors.errors() {
+        let path = match (prefix, *field) {
+            (prefix, "__all__") => prefix.map(String::from),
+            (Some(prefix), field) => Some(format!("{prefix}.{field}")),
+            (None, field) => Some(field.to_string()),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                flat.extend(field_errors.iter().map(|err| {
+                    DtoFieldError {
+                        field: path.clone(),
+                        code: err.code.to_string(),
+                        message: err.message.as_ref().map(|message| message.to_string()),
+                        params: err
+                            .params
+                            .iter()
+                            .filter(|(name, _)| *name != "value")
+                            .map(|(name, value)| (name.to_string(), value.clone()))
+                            .collect(),
+                    }
+                }))
+            }
+            ValidationErrorsKind::Struct(nested) => {
+                collect_field_errors(nested, path.as_deref(), flat)
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    let item_path = format!("{}[{index}]", path.as_deref().unwrap_or_default());
+                    collect_field_errors(nested, Some(&item_path), flat)
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the status axum picked for a body it could not read, but answers with a problem.
+fn rejected(rejection: impl IntoResponse + ToString) -> ApiError {
+    let detail = rejection.to_string();
+    ApiError::Rejected {
+        status: rejection.into_response().status(),
+        detail,
+    }
+}
+
+fn invalid(errors: ValidationErrors) -> ApiError {
+    ApiError::Validation(field_errors(&errors))
+}
+
+/// Like [`Form`], but the DTO also has to pass its `validator` rules.
+pub struct ValidatedForm<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedForm<T>
+where
+    T: DeserializeOwned + Validate,
+    Form<T>: FromRequest<S, B, Rejection = FormRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Form(value) = Form::<T>::from_request(req, state)
+            .await
+            .map_err(rejected)?;
+        value.validate().map_err(invalid)?;
+        Ok(ValidatedForm(value))
+    }
+}
+
+/// Like [`Json`], but the DTO also has to pass its `validator` rules.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(rejected)?;
+        value.validate().map_err(invalid)?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// The kinds of body [`ValidatedBody`] can read.
+#[derive(Debug, PartialEq)]
+enum BodyKind {
+    Json,
+    Form,
+    Multipart,
+}
+
+fn body_kind<B>(req: &Request<B>) -> Option<BodyKind> {
+    let mime: mime::Mime = req
+        .headers()
+        .get(CONTENT_TYPE)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+
+    match (mime.type_(), mime.subtype(), mime.suffix()) {
+        (mime::APPLICATION, mime::JSON, _) | (mime::APPLICATION, _, Some(mime::JSON)) => {
+            Some(BodyKind::Json)
+        }
+        (mime::APPLICATION, mime::WWW_FORM_URLENCODED, _) => Some(BodyKind::Form),
+        (mime::MULTIPART, mime::FORM_DATA, _) => Some(BodyKind::Multipart),
+        _ => None,
+    }
+}
+
+/// Reads the text fields of a multipart body into `T`, the same way a urlencoded form would be.
+async fn from_multipart<T: DeserializeOwned>(mut multipart: Multipart) -> Result<T, ApiError> {
+    let mut fields = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(rejected)? {
+        if field.file_name().is_some() {
+            return Err(ApiError::BadRequest(
+                "file uploads are not accepted".to_string(),
+            ));
+        }
+        let name = field.name().unwrap_or_default().to_string();
+        fields.push((name, field.text().await.map_err(rejected)?));
+    }
+
+    let encoded = serde_urlencoded::to_string(&fields).map_err(ApiError::internal)?;
+    serde_urlencoded::from_str(&encoded).map_err(|err| ApiError::Rejected {
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        detail: format!("Failed to deserialize multipart body: {err}"),
+    })
+}
+
+/// Reads a JSON, urlencoded form or multipart body into the same DTO, picked by `Content-Type`,
+/// and then checks its `validator` rules. Any other body is a `415 Unsupported Media Type`.
+pub struct ValidatedBody<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedBody<T>
+where
+    T: DeserializeOwned + Validate,
+    Json<T>: FromRequest<S, B, Rejection = JsonRejection>,
+    Form<T>: FromRequest<S, B, Rejection = FormRejection>,
+    Multipart: FromRequest<S, B, Rejection = MultipartRejection>,
+    S: Send + Sync,
+    B: Send + 'static,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let value = match body_kind(&req) {
+            Some(BodyKind::Json) => {
+                let Json(value) = Json::<T>::from_request(req, state)
+                    .await
+                    .map_err(rejected)?;
+                value
+            }
+            Some(BodyKind::Form) => {
+                let Form(value) = Form::<T>::from_request(req, state)
+                    .await
+                    .map_err(rejected)?;
+                value
+            }
+            Some(BodyKind::Multipart) => {
+                let multipart = Multipart::from_request(req, state)
+                    .await
+                    .map_err(rejected)?;
+                from_multipart(multipart).await?
+            }
+            None => {
+                return Err(ApiError::Rejected {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    detail: "expected a JSON, urlencoded form or multipart body".to_string(),
+                })
+            }
+        };
+        value.validate().map_err(invalid)?;
+        Ok(ValidatedBody(value))
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::*;
+    use axum::body::Body;
+    use serde_json::Value;
+    use user_lib::user_models::{DtoUserCreate, DtoUserLogin};
+    use wotd_lib::word_models::DtoWotdCreate;
+
+    fn request(content_type: &str, body: &'static str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn form_request(body: &'static str) -> Request<Body> {
+        request("application/x-www-form-urlencoded", body)
+    }
+
+    #[test]
+    fn field_errors_leave_out_the_value() {
+        let form = DtoUserCreate {
+            username: "jo".to_string(),
+            password: "hunter2".to_string(),
+            email: "jork@example.com".to_string(),
+        };
+
+        let errors = field_errors(&form.validate().unwrap_err());
+
+        assert_eq!(2, errors.len());
+        assert_eq!(Some("password".to_string()), errors[0].field);
+        assert_eq!(Some("username".to_string()), errors[1].field);
+        assert_eq!(Some(&Value::from(3)), errors[1].params.get("min"));
+        assert!(errors.iter().all(|err| !err.params.contains_key("value")));
+    }
+
+    #[test]
+    fn schema_errors_have_no_field() {
+        let wotd = DtoWotdCreate {
+            word: "petrichor".to_string(),
+            definition: "the smell of rain".to_string(),
+            sentence: "It smelled nice.".to_string(),
+        };
+
+        let errors = field_errors(&wotd.validate().unwrap_err());
+
+        assert_eq!(None, errors[0].field);
+        assert_eq!("sentence_uses_word", errors[0].code);
+    }
+
+    #[tokio::test]
+    async fn invalid_form_is_unprocessable() {
+        let rejection = ValidatedForm::<DtoUserLogin>::from_request(
+            form_request("username=&password=hunter2"),
+            &(),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, rejection.status());
+    }
+
+    #[tokio::test]
+    async fn unreadable_json_keeps_its_status() {
+        let rejection =
+            ValidatedJson::<DtoWotdCreate>::from_request(request("application/json", "{"), &())
+                .await
+                .err()
+                .unwrap();
+
+        assert_eq!(StatusCode::BAD_REQUEST, rejection.status());
+        assert!(matches!(rejection, ApiError::Rejected { .. }));
+    }
+
+    #[tokio::test]
+    async fn valid_form_is_extracted() {
+        let ValidatedForm(login) = ValidatedForm::<DtoUserLogin>::from_request(
+            form_request("username=jork&password=hunter2"),
+            &(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("jork", login.username);
+    }
+
+    #[tokio::test]
+    async fn body_is_read_by_content_type() {
+        let ValidatedBody(from_json) = ValidatedBody::<DtoUserLogin>::from_request(
+            request(
+                "application/json; charset=utf-8",
+                r#"{"username":"jork","password":"hunter2"}"#,
+            ),
+            &(),
+        )
+        .await
+        .unwrap();
+        let ValidatedBody(from_form) = ValidatedBody::<DtoUserLogin>::from_request(
+            form_request("username=jork&password=hunter2"),
+            &(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("jork", from_json.username);
+        assert_eq!("jork", from_form.username);
+    }
+
+    #[tokio::test]
+    async fn multipart_body_is_read_like_a_form() {
+        let body = "--X\r\nContent-Disposition: form-data; name=\"username\"\r\n\r\njork\r\n\
+                    --X\r\nContent-Disposition: form-data; name=\"password\"\r\n\r\nhunter2\r\n\
+                    --X--\r\n";
+
+        let ValidatedBody(login) = ValidatedBody::<DtoUserLogin>::from_request(
+            request("multipart/form-data; boundary=X", body),
+            &(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!("hunter2", login.password);
+    }
+
+    #[tokio::test]
+    async fn other_bodies_are_unsupported() {
+        for content_type in ["text/plain", "application/xml", "nonsense"] {
+            let rejection =
+                ValidatedBody::<DtoUserLogin>::from_request(request(content_type, "jork"), &())
+                    .await
+                    .err()
+                    .unwrap();
+
+            assert_eq!(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                rejection.status(),
+                "{content_type}"
+            );
+        }
+    }
+}
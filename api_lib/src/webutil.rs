@@ -1,5 +1,8 @@
+use error_lib::api_error::ApiError;
+use std::net::{IpAddr, SocketAddr};
+
 use axum::{
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Json, Router,
@@ -17,7 +20,7 @@ pub fn health_router() -> Router {
         .route("/liveness", get(healthcheck_liveness))
 }
 
-pub async fn healthcheck_readiness() -> Result<Response, StatusCode> {
+pub async fn healthcheck_readiness() -> Result<Response, ApiError> {
     Ok((
         StatusCode::OK,
         Json(CustomResponse {
@@ -27,7 +30,7 @@ pub async fn healthcheck_readiness() -> Result<Response, StatusCode> {
         .into_response())
 }
 
-pub async fn healthcheck_liveness() -> Result<Response, StatusCode> {
+pub async fn healthcheck_liveness() -> Result<Response, ApiError> {
     Ok((
         StatusCode::OK,
         Json(CustomResponse {
@@ -37,12 +40,69 @@ pub async fn healthcheck_liveness() -> Result<Response, StatusCode> {
         .into_response())
 }
 
-pub async fn not_found() -> Result<Response, StatusCode> {
-    Ok((
-        StatusCode::NOT_FOUND,
-        Json(CustomResponse {
-            message: "Resource not found".to_string(),
-        }),
-    )
-        .into_response())
+pub async fn not_found() -> Result<Response, ApiError> {
+    Err(ApiError::NotFound)
+}
+
+/// The IP of the client that made the request.
+///
+/// Behind a proxy the peer is always the proxy, so when `trust_forwarded_for` is set the last
+/// address in `X-Forwarded-For` is used instead. That is the one added by our own proxy, anything
+/// before it was sent by the client and cannot be trusted.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trust_forwarded_for: bool) -> IpAddr {
+    if trust_forwarded_for {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+        if let Some(ip) = forwarded {
+            return ip;
+        }
+    }
+    peer.ip()
+}
+
+#[cfg(test)]
+mod webutil_tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], 4321))
+    }
+
+    #[test]
+    fn client_ip_is_peer_by_default() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("1.2.3.4"));
+
+        assert_eq!(peer().ip(), client_ip(&headers, peer(), false));
+    }
+
+    #[test]
+    fn client_ip_uses_last_forwarded_address() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 1.2.3.4"),
+        );
+
+        assert_eq!(
+            "1.2.3.4".parse::<IpAddr>().unwrap(),
+            client_ip(&headers, peer(), true)
+        );
+    }
+
+    #[test]
+    fn client_ip_falls_back_to_peer() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("garbage"));
+
+        assert_eq!(peer().ip(), client_ip(&headers, peer(), true));
+        assert_eq!(peer().ip(), client_ip(&HeaderMap::new(), peer(), true));
+    }
 }
@@ -1,175 +1,209 @@
 use axum::{
-    extract::Path,
+    extract::{Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Extension, Form, Json,
+    Extension, Json,
 };
-use config_lib::config::Config;
-use mongodb::{bson::oid::ObjectId, Client};
+use std::collections::HashMap;
+
+use bson::oid::ObjectId;
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
 use user_lib::user_models::DtoUser;
 use wotd_lib::{
-    word_logic::{create_one_word, get_all_words, get_one_word},
-    word_models::{DtoWotdCreate, WordModel},
-    word_queue::QueueItemWordModel,
+    word_history::DtoHistoryQuery,
+    word_logic::{
+        create_one_word, get_all_words, get_history_page, get_last_rotation, get_one_word,
+        get_queue_entry, get_wotd_on, list_queue, move_queue_item, pin_word, remove_queue_item,
+        rotate_wotd, suggest_word, unpin_date,
+    },
+    word_models::DtoWotdCreate,
+    word_queue::{DtoQueueEntry, DtoQueueMove, DtoQueuePin},
 };
 
+use crate::{app_state::AppState, validation::ValidatedBody};
+
 pub async fn suggest_new_wotd(
+    State(state): State<AppState>,
     Extension(dto_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
-    Form(dto_word_suggestion): Form<DtoWotdCreate>,
-) -> Result<Response, StatusCode> {
-    let queue_collection: mongodb::Collection<QueueItemWordModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_QUEUE_WORDS);
-
-    match queue_collection
-        .find_one(
-            mongodb::bson::doc! {"word.word": dto_word_suggestion.word.clone() },
-            None,
-        )
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-    {
-        Some(word) => Ok((
-            StatusCode::BAD_REQUEST,
-            format!(
-                "{} word has already been suggested, and is in the queue!",
-                word.word.word
-            ),
-        )
-            .into_response()),
-        None => {
-            let words_collection: mongodb::Collection<WordModel> = client
-                .database(Config::MONGO_DB_NAME)
-                .collection(Config::MONGO_COLL_NAME_WORDS);
-
-            let suggested_word = match words_collection
-                .find_one(
-                    mongodb::bson::doc! {"word": dto_word_suggestion.word.clone() },
-                    None,
-                )
-                .await
-                .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-            {
-                Some(word) => {
-                    tracing::info!("found existing word!");
-                    word
-                }
-                None => {
-                    let new_word = WordModel {
-                        _id: ObjectId::new(),
-                        created_by_id: dto_user._id,
-                        word: dto_word_suggestion.word.clone(),
-                        definition: dto_word_suggestion.definition,
-                        sentence: dto_word_suggestion.sentence,
-                        created_at: chrono::Utc::now().into(),
-                        updated_at: chrono::Utc::now().into(),
-                    };
-
-                    tracing::info!("creating new word!");
-
-                    if let Err(_err) = words_collection.insert_one(new_word.clone(), None).await {
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                    }
-
-                    new_word
-                }
-            };
-
-            let suggestion = QueueItemWordModel {
-                _id: ObjectId::new(),
-                added_at: chrono::Utc::now().into(),
-                word: suggested_word,
-            };
-
-            queue_collection
-                .insert_one(suggestion, None)
-                .await
-                .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-            return Ok((StatusCode::OK, "wotd added!".to_string()).into_response());
-        }
-    }
+    ValidatedBody(dto_word_suggestion): ValidatedBody<DtoWotdCreate>,
+) -> Result<Response, ApiError> {
+    suggest_word(state.queue.as_ref(), dto_user._id, dto_word_suggestion).await
 }
 
+/// The word the queue rotated to today, the same one `GET /api/wotd/:date` has for today.
 pub async fn get_wotd(
+    State(state): State<AppState>,
     Extension(_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
-) -> Result<Response, StatusCode> {
-    let collection: mongodb::Collection<QueueItemWordModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_QUEUE_WORDS);
-
-    let options = mongodb::options::FindOneOptions::builder()
-        .sort(mongodb::bson::doc! {"added_at": 1})
-        .build();
-
-    match collection.find_one(mongodb::bson::doc! {}, options).await {
-        Ok(Some(wotd)) => Ok((StatusCode::OK, Json(Some(wotd))).into_response()),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_err) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Response, ApiError> {
+    get_wotd_on(state.history.as_ref(), state.wotd_today()).await
 }
 
 pub async fn update_wotd(
+    State(state): State<AppState>,
     Extension(_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
-) -> Result<Response, StatusCode> {
-    let collection: mongodb::Collection<QueueItemWordModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_QUEUE_WORDS);
-
-    let options = mongodb::options::FindOneOptions::builder()
-        .sort(mongodb::bson::doc! {"added_at": 1})
-        .build();
-
-    let wotd = collection
-        .find_one(mongodb::bson::doc! {}, options)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
-
-    // Delete the document
-    let deleted = collection
-        .delete_one(mongodb::bson::doc! {"_id": wotd._id}, None)
-        .await
-        .map_err(|_err| StatusCode::NOT_FOUND)?;
-
-    tracing::debug!("Deleted {} document(s).", deleted.deleted_count);
+) -> Result<Response, ApiError> {
+    let wotd = rotate_wotd(
+        state.queue.as_ref(),
+        state.history.as_ref(),
+        state.wotd_today(),
+    )
+    .await?
+    .ok_or(ApiError::NotFound)?;
+
     Ok((StatusCode::OK, Json(Some(wotd))).into_response())
 }
 
+pub async fn get_history(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    Query(query): Query<DtoHistoryQuery>,
+) -> Result<Response, ApiError> {
+    get_history_page(state.history.as_ref(), query).await
+}
+
+/// The word of the day on a `YYYY-MM-DD` date.
+pub async fn get_wotd_by_date(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    date: Path<String>,
+) -> Result<Response, ApiError> {
+    get_wotd_on(state.history.as_ref(), parse_date(&date)?).await
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, ApiError> {
+    date.parse()
+        .map_err(|_| ApiError::BadRequest("dates are written as YYYY-MM-DD".to_string()))
+}
+
+/// Makes the queued word the word of the day on a `YYYY-MM-DD` date, ahead of its turn.
+pub async fn pin_wotd(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    date: Path<String>,
+    ValidatedBody(dto_pin): ValidatedBody<DtoQueuePin>,
+) -> Result<Response, ApiError> {
+    pin_word(
+        state.queue.as_ref(),
+        &dto_pin.word,
+        parse_date(&date)?,
+        state.wotd_today(),
+    )
+    .await
+}
+
+pub async fn unpin_wotd(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    date: Path<String>,
+) -> Result<Response, ApiError> {
+    unpin_date(state.queue.as_ref(), parse_date(&date)?).await
+}
+
+/// The whole queue in line, with who suggested each word.
+pub async fn get_queue(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+) -> Result<Response, ApiError> {
+    let mut entries = list_queue(state.queue.as_ref()).await?;
+    let mut usernames = HashMap::new();
+    for entry in &mut entries {
+        entry.suggested_by = suggester(&state, entry, &mut usernames).await?;
+    }
+
+    Ok((StatusCode::OK, Json(entries)).into_response())
+}
+
+pub async fn get_queue_item(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    id: Path<String>,
+) -> Result<Response, ApiError> {
+    let mut entry = get_queue_entry(state.queue.as_ref(), parse_queue_id(&id)?).await?;
+    entry.suggested_by = suggester(&state, &entry, &mut HashMap::new()).await?;
+
+    Ok((StatusCode::OK, Json(entry)).into_response())
+}
+
+pub async fn move_queue_item_to(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    id: Path<String>,
+    ValidatedBody(dto_move): ValidatedBody<DtoQueueMove>,
+) -> Result<Response, ApiError> {
+    let mut entry = move_queue_item(
+        state.queue.as_ref(),
+        parse_queue_id(&id)?,
+        dto_move.position,
+    )
+    .await?;
+    entry.suggested_by = suggester(&state, &entry, &mut HashMap::new()).await?;
+
+    Ok((StatusCode::OK, Json(entry)).into_response())
+}
+
+pub async fn delete_queue_item(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+    id: Path<String>,
+) -> Result<Response, ApiError> {
+    remove_queue_item(state.queue.as_ref(), parse_queue_id(&id)?).await
+}
+
+/// An id that cannot be one is not in the queue either.
+fn parse_queue_id(id: &str) -> Result<ObjectId, ApiError> {
+    ObjectId::parse_str(id).map_err(|_err| ApiError::NotFound)
+}
+
+/// The username of whoever suggested the entry's word, looked up once per user in `usernames`.
+async fn suggester(
+    state: &AppState,
+    entry: &DtoQueueEntry,
+    usernames: &mut HashMap<ObjectId, Option<String>>,
+) -> Result<Option<String>, ApiError> {
+    let Some(user_id) = entry.item.suggested_by_id else {
+        return Ok(None);
+    };
+    if let Some(username) = usernames.get(&user_id) {
+        return Ok(username.clone());
+    }
+
+    let username = state
+        .users
+        .find_by_id(user_id)
+        .await?
+        .map(|user| user.username);
+    usernames.insert(user_id, username.clone());
+    Ok(username)
+}
+
 pub async fn get_word(
+    State(state): State<AppState>,
     Extension(_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
     word: Path<String>,
-) -> Result<Response, StatusCode> {
-    let collection: mongodb::Collection<WordModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_WORDS);
-
-    get_one_word(collection, word.to_string()).await
+) -> Result<Response, ApiError> {
+    get_one_word(state.words.as_ref(), word.to_string()).await
 }
 
 pub async fn get_words(
+    State(state): State<AppState>,
     Extension(_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
-) -> Result<Response, StatusCode> {
-    let collection: mongodb::Collection<WordModel> = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_WORDS);
-
-    get_all_words(collection).await
+) -> Result<Response, ApiError> {
+    get_all_words(state.words.as_ref()).await
 }
 
 pub async fn create_word(
+    State(state): State<AppState>,
     Extension(dto_user): Extension<DtoUser>,
-    Extension(client): Extension<std::sync::Arc<Client>>,
-    Form(create_word_dto): Form<DtoWotdCreate>,
-) -> Result<Response, StatusCode> {
-    let collection = client
-        .database(Config::MONGO_DB_NAME)
-        .collection(Config::MONGO_COLL_NAME_WORDS);
-
-    create_one_word(collection, dto_user._id, create_word_dto).await
+    ValidatedBody(create_word_dto): ValidatedBody<DtoWotdCreate>,
+) -> Result<Response, ApiError> {
+    create_one_word(state.words.as_ref(), dto_user._id, create_word_dto).await
+}
+
+/// How the last scheduled rotation went, on whichever replica ran it.
+pub async fn get_rotation_status(
+    State(state): State<AppState>,
+    Extension(_user): Extension<DtoUser>,
+) -> Result<Response, ApiError> {
+    get_last_rotation(state.rotations.as_ref()).await
 }
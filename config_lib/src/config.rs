@@ -1,11 +1,21 @@
 use dotenv::dotenv;
 use tracing::metadata::LevelFilter;
+use user_lib::user_api_key::ApiKeyModel;
+use user_lib::user_lockout::{LockoutPolicy, LoginAttemptModel};
+use user_lib::user_mail::{FileMailSender, InMemoryMailSender, MailSender, SmtpMailSender};
 use user_lib::user_models::UserModel;
+use user_lib::user_password::PasswordParams;
+use user_lib::user_password_reset::PasswordResetModel;
+use user_lib::user_session::{RefreshTokenModel, RevokedTokenModel};
+use user_lib::user_sql;
+use wotd_lib::word_history::WotdHistoryModel;
 use wotd_lib::word_models::WordModel;
 use wotd_lib::word_queue::QueueItemWordModel;
+use wotd_lib::word_rotation::RotationRunModel;
 
 use std::net::Ipv4Addr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use opentelemetry::sdk::propagation::TraceContextPropagator;
 use opentelemetry::sdk::Resource;
@@ -17,6 +27,16 @@ use opentelemetry_otlp::WithExportConfig;
 
 use crate::config_env::ConfigEnvKey;
 
+/// The backends the repositories can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageBackend {
+    Mongo,
+    /// SQLite or Postgres, picked by the scheme of `DATABASE_URL`.
+    Sql,
+    /// Kept in the process, with the same unique keys as the Mongo indexes.
+    Memory,
+}
+
 pub struct Config {
     using_dotenv_path: Option<PathBuf>,
     service_ip: Ipv4Addr,
@@ -24,6 +44,12 @@ pub struct Config {
     otel_url: String,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Config {
     // Used for consistent span keys.
     // pub const LEMONS_KEY: Key = Key::from_static_str("lemons");
@@ -37,11 +63,41 @@ impl Config {
     pub const DEFAULT_LOG_FILTER: &str = "INFO";
     pub const DEFAULT_DEV_MODE: bool = false;
     pub const AUTH_TOKEN_STRING: &str = "access_token";
+    pub const REFRESH_TOKEN_STRING: &str = "refresh_token";
+    // OWASP recommended minimum for Argon2id.
+    pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19456;
+    pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+    pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+    pub const DEFAULT_JWT_ISSUER: &str = Config::APP_NAME;
+    pub const DEFAULT_ACCESS_TOKEN_TTL_SECS: u32 = 60 * 15;
+    pub const DEFAULT_REFRESH_TOKEN_TTL_SECS: u32 = 60 * 60 * 24 * 30;
+    pub const DEFAULT_LOGIN_MAX_ATTEMPTS_PER_USERNAME: u32 = 5;
+    pub const DEFAULT_LOGIN_MAX_ATTEMPTS_PER_IP: u32 = 20;
+    pub const DEFAULT_LOGIN_LOCKOUT_BASE_SECS: u32 = 30;
+    pub const DEFAULT_LOGIN_LOCKOUT_MAX_SECS: u32 = 60 * 60;
+    pub const LOGIN_ATTEMPT_WINDOW_SECS: u64 = 60 * 60 * 24;
+    pub const DEFAULT_TRUST_FORWARDED_FOR: bool = false;
+    pub const DEFAULT_MAIL_FROM: &str = "poc_rear <no-reply@localhost>";
+    pub const DEFAULT_PUBLIC_URL: &str = "http://localhost:8080";
+    pub const DEFAULT_VERIFICATION_LINK_TTL_SECS: u32 = 60 * 60 * 24;
+    pub const DEFAULT_PASSWORD_RESET_TTL_SECS: u32 = 60 * 60;
+    pub const LOGIN_CHALLENGE_TTL_SECS: u64 = 60 * 5;
+    pub const DEFAULT_WOTD_TIMEZONE: &str = "UTC";
+    /// How often expired sessions, lockouts and reset tokens are deleted from SQL storage.
+    pub const EXPIRY_CLEANUP_PERIOD_SECS: u64 = 60 * 10;
 
     pub const MONGO_DB_NAME: &str = Config::APP_NAME;
     pub const MONGO_COLL_NAME_WORDS: &str = "words";
     pub const MONGO_COLL_NAME_USERS: &str = "users";
     pub const MONGO_COLL_NAME_QUEUE_WORDS: &str = "queue_words";
+    pub const MONGO_COLL_NAME_WOTD_HISTORY: &str = "wotd_history";
+    pub const MONGO_COLL_NAME_ROTATION_LEASES: &str = "rotation_leases";
+    pub const MONGO_COLL_NAME_ROTATION_RUNS: &str = "rotation_runs";
+    pub const MONGO_COLL_NAME_REFRESH_TOKENS: &str = "refresh_tokens";
+    pub const MONGO_COLL_NAME_REVOKED_TOKENS: &str = "revoked_tokens";
+    pub const MONGO_COLL_NAME_API_KEYS: &str = "api_keys";
+    pub const MONGO_COLL_NAME_LOGIN_ATTEMPTS: &str = "login_attempts";
+    pub const MONGO_COLL_NAME_PASSWORD_RESETS: &str = "password_resets";
 
     pub fn new() -> Config {
         Config {
@@ -63,6 +119,19 @@ impl Config {
             self.service_port()
         );
         log::log!(level, "Sending traces to   : [{}]", self.otel_url());
+        log::log!(
+            level,
+            "Issuing tokens as   : [{}]",
+            String::from(ConfigEnvKey::JwtIssuer)
+        );
+        if let Some(schedule) = Config::rotation_schedule() {
+            log::log!(
+                level,
+                "Rotating the wotd at: [{}] in [{}]",
+                schedule,
+                Config::wotd_timezone()
+            );
+        }
     }
 
     pub fn service_ip(&self) -> Ipv4Addr {
@@ -81,6 +150,139 @@ impl Config {
         self.using_dotenv_path.to_owned()
     }
 
+    /// Reads the PEM encoded private key used to sign access tokens, if one is configured.
+    pub fn jwt_private_key_pem() -> Option<Vec<u8>> {
+        Option::<String>::from(ConfigEnvKey::JwtPrivateKeyPath).map(|path| {
+            std::fs::read(&path).unwrap_or_else(|err| {
+                panic!(
+                    "{} should point to a readable PEM file! could not read {}: {}",
+                    ConfigEnvKey::JwtPrivateKeyPath.as_str(),
+                    path,
+                    err
+                )
+            })
+        })
+    }
+
+    pub fn refresh_token_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(u32::from(ConfigEnvKey::RefreshTokenTtlSecs).into())
+    }
+
+    pub fn password_params() -> PasswordParams {
+        PasswordParams {
+            memory_kib: u32::from(ConfigEnvKey::Argon2MemoryKib),
+            iterations: u32::from(ConfigEnvKey::Argon2Iterations),
+            parallelism: u32::from(ConfigEnvKey::Argon2Parallelism),
+        }
+    }
+
+    pub fn lockout_policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_attempts_per_username: u32::from(ConfigEnvKey::LoginMaxAttemptsPerUsername),
+            max_attempts_per_ip: u32::from(ConfigEnvKey::LoginMaxAttemptsPerIp),
+            base_delay: std::time::Duration::from_secs(
+                u32::from(ConfigEnvKey::LoginLockoutBaseSecs).into(),
+            ),
+            max_delay: std::time::Duration::from_secs(
+                u32::from(ConfigEnvKey::LoginLockoutMaxSecs).into(),
+            ),
+            window: std::time::Duration::from_secs(Config::LOGIN_ATTEMPT_WINDOW_SECS),
+        }
+    }
+
+    pub fn verification_link_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(u32::from(ConfigEnvKey::VerificationLinkTtlSecs).into())
+    }
+
+    pub fn password_reset_ttl() -> std::time::Duration {
+        std::time::Duration::from_secs(u32::from(ConfigEnvKey::PasswordResetTtlSecs).into())
+    }
+
+    pub fn storage_backend() -> StorageBackend {
+        match Option::<String>::from(ConfigEnvKey::Storage).as_deref() {
+            Some("mongo") => StorageBackend::Mongo,
+            Some("sql") => StorageBackend::Sql,
+            Some("memory") => StorageBackend::Memory,
+            Some(other) => panic!(
+                "{} should be one of mongo, sql or memory! {other} is not valid.",
+                ConfigEnvKey::Storage.as_str()
+            ),
+            None if Option::<String>::from(ConfigEnvKey::DatabaseUrl).is_some() => {
+                StorageBackend::Sql
+            }
+            None => StorageBackend::Mongo,
+        }
+    }
+
+    /// Panics on a timezone that does not exist, so it is read once on startup, by
+    /// `AppState::from_config`.
+    pub fn wotd_timezone() -> chrono_tz::Tz {
+        let timezone = String::from(ConfigEnvKey::WotdTimezone);
+        timezone.parse().unwrap_or_else(|_| {
+            panic!(
+                "{} should be an IANA timezone like Europe/Amsterdam! {timezone} is not valid.",
+                ConfigEnvKey::WotdTimezone.as_str()
+            )
+        })
+    }
+
+    /// When the queue is rotated automatically, if at all.
+    pub fn rotation_schedule() -> Option<cron::Schedule> {
+        Option::<String>::from(ConfigEnvKey::WotdRotationCron).map(|expression| {
+            expression.parse().unwrap_or_else(|err| {
+                panic!(
+                    "{} should be a cron expression with seconds, like 0 0 0 * * *! {expression} \
+                     is not valid: {err}",
+                    ConfigEnvKey::WotdRotationCron.as_str()
+                )
+            })
+        })
+    }
+
+    /// Identifies this replica, falling back to a name of its own when `HOSTNAME` is not set.
+    pub fn instance_name() -> String {
+        Option::<String>::from(ConfigEnvKey::Hostname).unwrap_or_else(|| {
+            format!(
+                "{}-{}",
+                Config::APP_NAME,
+                mongodb::bson::oid::ObjectId::new()
+            )
+        })
+    }
+
+    /// Picks how mail is delivered: SMTP if `SMTP_URL` is set, otherwise files in `MAIL_DIR`.
+    ///
+    /// In dev mode mails are only logged when neither is set.
+    pub fn mail_sender() -> Arc<dyn MailSender> {
+        let from = String::from(ConfigEnvKey::MailFrom);
+
+        if let Some(url) = Option::<String>::from(ConfigEnvKey::SmtpUrl) {
+            return Arc::new(SmtpMailSender::new(&url, &from).unwrap_or_else(|err| {
+                panic!(
+                    "{} and {} should be valid! {err}",
+                    ConfigEnvKey::SmtpUrl.as_str(),
+                    ConfigEnvKey::MailFrom.as_str()
+                )
+            }));
+        }
+        if let Some(dir) = Option::<String>::from(ConfigEnvKey::MailDir) {
+            return Arc::new(
+                FileMailSender::new(dir.into(), &from).unwrap_or_else(|err| {
+                    panic!("{} should be valid! {err}", ConfigEnvKey::MailFrom.as_str())
+                }),
+            );
+        }
+        if bool::from(ConfigEnvKey::DevMode) {
+            tracing::warn!("no mail transport configured, mails are only logged");
+            return Arc::new(InMemoryMailSender::default());
+        }
+        panic!(
+            "{} or {} must be set when not running in dev mode",
+            ConfigEnvKey::SmtpUrl.as_str(),
+            ConfigEnvKey::MailDir.as_str()
+        )
+    }
+
     pub fn init_otel() {
         global::set_text_map_propagator(TraceContextPropagator::new());
 
@@ -117,6 +319,40 @@ impl Config {
         }
     }
 
+    /// Connects to `DATABASE_URL` and brings its tables up to date with `migrations/`.
+    pub async fn init_sql() -> sqlx::AnyPool {
+        let url = Option::<String>::from(ConfigEnvKey::DatabaseUrl).unwrap_or_else(|| {
+            panic!(
+                "{} must be set to use sql storage",
+                ConfigEnvKey::DatabaseUrl.as_str()
+            )
+        });
+        let options = match url.split_once(':') {
+            // Every connection to an in-memory SQLite database gets a database of its own.
+            Some(("sqlite", rest)) if rest.contains(":memory:") => {
+                sqlx::any::AnyPoolOptions::new().max_connections(1)
+            }
+            Some(("sqlite" | "postgres" | "postgresql", _)) => sqlx::any::AnyPoolOptions::new(),
+            _ => panic!(
+                "{} should be a sqlite: or postgres: url!",
+                ConfigEnvKey::DatabaseUrl.as_str()
+            ),
+        };
+
+        sqlx::any::install_default_drivers();
+        let pool = options.connect(&url).await.expect("failed to connect");
+        sqlx::migrate!("../migrations")
+            .run(&pool)
+            .await
+            .expect("migrations should run");
+        // What the TTL indexes of `init_mongo` do for Mongo.
+        user_sql::spawn_expiry_cleanup(
+            pool.clone(),
+            std::time::Duration::from_secs(Config::EXPIRY_CLEANUP_PERIOD_SECS),
+        );
+        pool
+    }
+
     pub async fn init_mongo() -> mongodb::Client {
         // TODO: add way to timeout if mongo does not connect.
         let uri = String::from(ConfigEnvKey::MongoDBUri);
@@ -144,10 +380,63 @@ impl Config {
             .keys(mongodb::bson::doc! { "word.word": 1 })
             .options(options.clone())
             .build();
+        // Only one word can be pinned to a day, any number of them can be unpinned.
+        let word_queue_pin_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "pinned_for": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .unique(true)
+                    .partial_filter_expression(
+                        mongodb::bson::doc! { "pinned_for": { "$type": "string" } },
+                    )
+                    .build(),
+            )
+            .build();
+        let word_queue_rank_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "rank": 1, "added_at": 1 })
+            .build();
+        let queue = client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<QueueItemWordModel>(Config::MONGO_COLL_NAME_QUEUE_WORDS);
+        queue
+            .create_indexes(
+                [
+                    word_queue_model,
+                    word_queue_pin_model,
+                    word_queue_rank_model,
+                ],
+                None,
+            )
+            .await
+            .expect("creating database indexes for the queue should work");
+        // Items queued before they had a rank keep their place in line.
+        queue
+            .update_many(
+                mongodb::bson::doc! { "rank": { "$exists": false } },
+                vec![mongodb::bson::doc! { "$set": { "rank": { "$toLong": "$added_at" } } }],
+                None,
+            )
+            .await
+            .expect("ranking the queue should work");
+
+        // Not unique, the queue can be rotated more than once a day.
+        let wotd_history_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "date": -1, "featured_at": -1 })
+            .build();
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<WotdHistoryModel>(Config::MONGO_COLL_NAME_WOTD_HISTORY)
+            .create_index(wotd_history_model, None)
+            .await
+            .expect("creating an index should succeed");
+
+        let rotation_run_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "started_at": -1 })
+            .build();
         client
             .database(Config::MONGO_DB_NAME)
-            .collection::<QueueItemWordModel>(Config::MONGO_COLL_NAME_QUEUE_WORDS)
-            .create_index(word_queue_model, None)
+            .collection::<RotationRunModel>(Config::MONGO_COLL_NAME_ROTATION_RUNS)
+            .create_index(rotation_run_model, None)
             .await
             .expect("creating an index should succeed");
 
@@ -163,6 +452,128 @@ impl Config {
             .await
             .expect("creating database index for users should work");
 
+        let refresh_token_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "token_hash": 1 })
+            .options(options.clone())
+            .build();
+        let refresh_token_family_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "family_id": 1 })
+            .build();
+        // Mongo removes expired refresh tokens for us.
+        let refresh_token_ttl_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "expires_at": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<RefreshTokenModel>(Config::MONGO_COLL_NAME_REFRESH_TOKENS)
+            .create_indexes(
+                [
+                    refresh_token_model,
+                    refresh_token_family_model,
+                    refresh_token_ttl_model,
+                ],
+                None,
+            )
+            .await
+            .expect("creating database indexes for refresh tokens should work");
+
+        let revoked_jti_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "jti": 1 })
+            .build();
+        let revoked_session_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "session_id": 1 })
+            .build();
+        let revoked_ttl_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "expires_at": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<RevokedTokenModel>(Config::MONGO_COLL_NAME_REVOKED_TOKENS)
+            .create_indexes(
+                [revoked_jti_model, revoked_session_model, revoked_ttl_model],
+                None,
+            )
+            .await
+            .expect("creating database indexes for revoked tokens should work");
+
+        let api_key_hash_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "key_hash": 1 })
+            .options(options.clone())
+            .build();
+        let api_key_user_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "user_id": 1 })
+            .build();
+
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<ApiKeyModel>(Config::MONGO_COLL_NAME_API_KEYS)
+            .create_indexes([api_key_hash_model, api_key_user_model], None)
+            .await
+            .expect("creating database indexes for api keys should work");
+
+        let login_attempt_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "kind": 1, "subject": 1 })
+            .options(options.clone())
+            .build();
+        // Failed attempts are forgotten once the window has passed without another one.
+        let login_attempt_ttl_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "expires_at": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<LoginAttemptModel>(Config::MONGO_COLL_NAME_LOGIN_ATTEMPTS)
+            .create_indexes([login_attempt_model, login_attempt_ttl_model], None)
+            .await
+            .expect("creating database indexes for login attempts should work");
+
+        let password_reset_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "token_hash": 1 })
+            .options(options.clone())
+            .build();
+        let password_reset_user_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "user_id": 1 })
+            .build();
+        let password_reset_ttl_model = mongodb::IndexModel::builder()
+            .keys(mongodb::bson::doc! { "expires_at": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        client
+            .database(Config::MONGO_DB_NAME)
+            .collection::<PasswordResetModel>(Config::MONGO_COLL_NAME_PASSWORD_RESETS)
+            .create_indexes(
+                [
+                    password_reset_model,
+                    password_reset_user_model,
+                    password_reset_ttl_model,
+                ],
+                None,
+            )
+            .await
+            .expect("creating database indexes for password resets should work");
+
         client
     }
 }
@@ -251,6 +662,69 @@ mod tests {
         env::remove_var(ConfigEnvKey::OtelCollectorUrl.as_str())
     }
 
+    #[test]
+    fn test_password_params_default() {
+        // Arrange
+        env::remove_var(ConfigEnvKey::Argon2MemoryKib.as_str());
+        env::remove_var(ConfigEnvKey::Argon2Iterations.as_str());
+        env::remove_var(ConfigEnvKey::Argon2Parallelism.as_str());
+
+        // Act / Assert
+        assert_eq!(PasswordParams::default(), Config::password_params());
+    }
+
+    #[test]
+    fn test_lockout_policy_default() {
+        // Arrange
+        env::remove_var(ConfigEnvKey::LoginMaxAttemptsPerUsername.as_str());
+        env::remove_var(ConfigEnvKey::LoginMaxAttemptsPerIp.as_str());
+        env::remove_var(ConfigEnvKey::LoginLockoutBaseSecs.as_str());
+        env::remove_var(ConfigEnvKey::LoginLockoutMaxSecs.as_str());
+
+        // Act / Assert
+        assert_eq!(LockoutPolicy::default(), Config::lockout_policy());
+    }
+
+    #[test]
+    fn test_storage_backend() {
+        // Arrange
+        env::remove_var(ConfigEnvKey::Storage.as_str());
+        env::remove_var(ConfigEnvKey::DatabaseUrl.as_str());
+
+        // Act / Assert
+        assert_eq!(StorageBackend::Mongo, Config::storage_backend());
+
+        env::set_var(ConfigEnvKey::DatabaseUrl.as_str(), "sqlite::memory:");
+        assert_eq!(StorageBackend::Sql, Config::storage_backend());
+
+        env::set_var(ConfigEnvKey::Storage.as_str(), "memory");
+        assert_eq!(StorageBackend::Memory, Config::storage_backend());
+
+        // Cleanup
+        env::remove_var(ConfigEnvKey::Storage.as_str());
+        env::remove_var(ConfigEnvKey::DatabaseUrl.as_str())
+    }
+
+    #[test]
+    fn test_rotation_schedule() {
+        // Arrange
+        env::remove_var(ConfigEnvKey::WotdRotationCron.as_str());
+        env::remove_var(ConfigEnvKey::WotdTimezone.as_str());
+
+        // Act / Assert
+        assert!(Config::rotation_schedule().is_none());
+        assert_eq!(chrono_tz::UTC, Config::wotd_timezone());
+
+        env::set_var(ConfigEnvKey::WotdRotationCron.as_str(), "0 0 0 * * *");
+        env::set_var(ConfigEnvKey::WotdTimezone.as_str(), "Europe/Amsterdam");
+        assert!(Config::rotation_schedule().is_some());
+        assert_eq!(chrono_tz::Europe::Amsterdam, Config::wotd_timezone());
+
+        // Cleanup
+        env::remove_var(ConfigEnvKey::WotdRotationCron.as_str());
+        env::remove_var(ConfigEnvKey::WotdTimezone.as_str())
+    }
+
     #[test]
     fn test_config_new() {
         // Arrange / Act
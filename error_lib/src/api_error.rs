@@ -0,0 +1,314 @@
+use std::{fmt, time::Duration};
+
+use axum::{
+    http::{
+        header::{CONTENT_TYPE, RETRY_AFTER},
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::error::{BulkWriteFailure, ErrorKind, WriteFailure};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The error code MongoDB uses for a write that breaks a unique index.
+const DUPLICATE_KEY_CODE: i32 = 11000;
+const DUPLICATE_DETAIL: &str = "this already exists";
+
+/// A single rule a request broke.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DtoFieldError {
+    /// Dotted path of the field, `None` for rules about the request as a whole.
+    pub field: Option<String>,
+    pub code: String,
+    pub message: Option<String>,
+    /// The limits of the rule, like `min` and `max` for lengths. The value that was sent is left
+    /// out, it could be a password.
+    pub params: Map<String, Value>,
+}
+
+/// Everything that can go wrong while handling a request, shared by every crate with handlers
+/// or logic in them.
+///
+/// Responds with an RFC 7807 `application/problem+json` body. Errors that are our fault are
+/// logged along with their source, but the client only learns that something went wrong.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request does not make sense, the detail says why.
+    BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    /// The request clashes with the current state, the detail says how.
+    Conflict(String),
+    /// The request body broke these validation rules.
+    Validation(Vec<DtoFieldError>),
+    /// The client has to wait before trying again.
+    TooManyRequests {
+        retry_after: Duration,
+    },
+    /// A service we depend on cannot be reached.
+    Unavailable(String),
+    /// The request could not be read at all, for example a body that is not valid JSON.
+    Rejected {
+        status: StatusCode,
+        detail: String,
+    },
+    /// A write broke a unique index or constraint of a storage backend other than MongoDB.
+    Duplicate,
+    /// A database operation failed. Writes that break a unique index become a 409.
+    Database(mongodb::error::Error),
+    /// Anything else that is our fault.
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// Wraps any error that is our fault, for use with `map_err`.
+    pub fn internal(err: impl Into<anyhow::Error>) -> ApiError {
+        ApiError::Internal(err.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) | ApiError::Duplicate => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Rejected { status, .. } => *status,
+            ApiError::Database(err) if is_duplicate_key(err) => StatusCode::CONFLICT,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether this is a write that broke a unique index, whichever backend it was made to.
+    pub fn is_duplicate_key(&self) -> bool {
+        match self {
+            ApiError::Duplicate => true,
+            ApiError::Database(err) => is_duplicate_key(err),
+            _ => false,
+        }
+    }
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) => {
+            write_error.code == DUPLICATE_KEY_CODE
+        }
+        ErrorKind::BulkWrite(BulkWriteFailure {
+            write_errors: Some(write_errors),
+            ..
+        }) => write_errors
+            .iter()
+            .any(|write_error| write_error.code == DUPLICATE_KEY_CODE),
+        // An upserting findAndModify reports it as a command error.
+        ErrorKind::Command(command_error) => command_error.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::Unavailable(detail) => write!(f, "{}: {detail}", self.status()),
+            ApiError::Rejected { detail, .. } => write!(f, "{}: {detail}", self.status()),
+            ApiError::Database(err) => write!(f, "database error: {err}"),
+            ApiError::Internal(err) => write!(f, "internal error: {err:#}"),
+            _ => write!(f, "{}", self.status()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Database(err) => Some(err),
+            ApiError::Internal(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(err: mongodb::error::Error) -> Self {
+        ApiError::Database(err)
+    }
+}
+
+/// Unique constraint violations become [`ApiError::Duplicate`], anything else is our fault.
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => ApiError::Duplicate,
+            _ => ApiError::internal(err),
+        }
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for ApiError {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        ApiError::internal(err)
+    }
+}
+
+/// An RFC 7807 problem details body.
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    /// Always `about:blank`, the status says everything there is to say about the type.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Members specific to a problem, like the `errors` of a failed validation.
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode) -> Problem {
+        Problem {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: None,
+            extensions: Map::new(),
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (
+            status,
+            [(CONTENT_TYPE, "application/problem+json")],
+            Json(self),
+        )
+            .into_response()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let mut problem = Problem::new(status);
+
+        match self {
+            ApiError::BadRequest(detail)
+            | ApiError::Conflict(detail)
+            | ApiError::Rejected { detail, .. } => problem.detail = Some(detail),
+            ApiError::Unavailable(detail) => {
+                tracing::warn!("service unavailable: {detail}");
+                problem.detail = Some(detail);
+            }
+            ApiError::Validation(errors) => {
+                problem.detail = Some("the request broke some validation rules".to_string());
+                problem.extensions.insert(
+                    "errors".to_string(),
+                    serde_json::to_value(errors).unwrap_or_default(),
+                );
+            }
+            ApiError::TooManyRequests { retry_after } => {
+                let mut response = problem.into_response();
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+                return response;
+            }
+            ApiError::Duplicate => problem.detail = Some(DUPLICATE_DETAIL.to_string()),
+            ApiError::Database(err) if status == StatusCode::CONFLICT => {
+                tracing::info!("rejected duplicate key: {err}");
+                problem.detail = Some(DUPLICATE_DETAIL.to_string());
+            }
+            ApiError::Database(err) => tracing::error!("database error: {err}"),
+            ApiError::Internal(err) => tracing::error!("internal error: {err:#}"),
+            ApiError::Unauthorized | ApiError::Forbidden | ApiError::NotFound => {}
+        }
+        problem.into_response()
+    }
+}
+
+#[cfg(test)]
+mod api_error_tests {
+    use super::*;
+
+    fn write_error(code: i32) -> mongodb::error::Error {
+        let write_error =
+            bson::from_document(bson::doc! { "code": code, "errmsg": "E11000 duplicate key" })
+                .unwrap();
+        mongodb::error::Error::from(ErrorKind::Write(WriteFailure::WriteError(write_error)))
+    }
+
+    #[test]
+    fn duplicate_key_is_conflict() {
+        assert_eq!(
+            StatusCode::CONFLICT,
+            ApiError::from(write_error(11000)).status()
+        );
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::from(write_error(2)).status()
+        );
+        assert!(ApiError::from(write_error(11000)).is_duplicate_key());
+        assert!(ApiError::Duplicate.is_duplicate_key());
+    }
+
+    #[test]
+    fn sql_errors_are_ours() {
+        let err = ApiError::from(sqlx::Error::RowNotFound);
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, err.status());
+        assert!(!err.is_duplicate_key());
+    }
+
+    #[test]
+    fn response_is_problem_json() {
+        let response = ApiError::BadRequest("no".to_string()).into_response();
+
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert_eq!(
+            "application/problem+json",
+            response.headers()[CONTENT_TYPE].to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn problem_has_rfc_7807_members() {
+        let mut problem = Problem::new(StatusCode::NOT_FOUND);
+        problem.extensions.insert("errors".to_string(), Value::Null);
+
+        let value = serde_json::to_value(problem).unwrap();
+        assert_eq!("about:blank", value["type"]);
+        assert_eq!("Not Found", value["title"]);
+        assert_eq!(404, value["status"]);
+        assert!(value.get("detail").is_none());
+        assert!(value.get("errors").is_some());
+    }
+
+    #[test]
+    fn too_many_requests_has_retry_after() {
+        let response = ApiError::TooManyRequests {
+            retry_after: Duration::from_secs(30),
+        }
+        .into_response();
+
+        assert_eq!("30", response.headers()[RETRY_AFTER].to_str().unwrap());
+    }
+
+    #[test]
+    fn internal_error_keeps_source() {
+        let err = ApiError::internal(anyhow::anyhow!("disk on fire"));
+
+        assert!(std::error::Error::source(&err).is_some());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, err.status());
+    }
+}
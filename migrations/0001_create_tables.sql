@@ -0,0 +1,104 @@
+-- Works on both SQLite and Postgres. Ids are ObjectId hex strings, times are milliseconds since
+-- the epoch like bson::DateTime, and flags are 0 or 1 because sqlx's Any driver cannot read
+-- booleans from SQLite. The unique constraints are the ones `Config::init_mongo` creates as
+-- indexes.
+
+CREATE TABLE users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    display_name TEXT,
+    password TEXT NOT NULL,
+    email TEXT NOT NULL,
+    -- JSON array of role names.
+    roles TEXT NOT NULL,
+    state TEXT NOT NULL,
+    verification_nonce TEXT,
+    -- All NULL when the user has no second factor.
+    totp_secret TEXT,
+    totp_confirmed BIGINT,
+    totp_last_used_step BIGINT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE TABLE recovery_codes (
+    user_id TEXT NOT NULL REFERENCES users (id),
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
+
+CREATE TABLE refresh_tokens (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    family_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT,
+    revoked BIGINT NOT NULL
+);
+CREATE INDEX refresh_tokens_user_id ON refresh_tokens (user_id);
+
+CREATE TABLE revoked_tokens (
+    id TEXT PRIMARY KEY,
+    jti TEXT,
+    session_id TEXT,
+    user_id TEXT NOT NULL,
+    revoked_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+CREATE INDEX revoked_tokens_jti ON revoked_tokens (jti);
+CREATE INDEX revoked_tokens_session_id ON revoked_tokens (session_id);
+
+CREATE TABLE api_keys (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    key_hint TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    -- JSON array of scope names.
+    scopes TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    last_used_at BIGINT,
+    revoked_at BIGINT
+);
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
+
+CREATE TABLE login_attempts (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    failures BIGINT NOT NULL,
+    last_failure_at BIGINT NOT NULL,
+    locked_until BIGINT,
+    expires_at BIGINT NOT NULL,
+    UNIQUE (kind, subject)
+);
+
+CREATE TABLE password_resets (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_id TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT
+);
+
+CREATE TABLE words (
+    id TEXT PRIMARY KEY,
+    created_by_id TEXT,
+    word TEXT NOT NULL UNIQUE,
+    definition TEXT NOT NULL,
+    sentence TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+-- Queued words refer to their row in `words` instead of keeping a copy, so a word can only be
+-- queued once.
+CREATE TABLE queue_words (
+    id TEXT PRIMARY KEY,
+    word_id TEXT NOT NULL UNIQUE REFERENCES words (id),
+    added_at BIGINT NOT NULL
+);
+CREATE INDEX queue_words_added_at ON queue_words (added_at);
@@ -0,0 +1,8 @@
+-- Every word of the day there has been. Dates are `YYYY-MM-DD` text, which sorts like the dates.
+CREATE TABLE wotd_history (
+    id TEXT PRIMARY KEY,
+    date TEXT NOT NULL,
+    word_id TEXT NOT NULL REFERENCES words (id),
+    featured_at BIGINT NOT NULL
+);
+CREATE INDEX wotd_history_date ON wotd_history (date, featured_at);
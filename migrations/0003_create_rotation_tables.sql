@@ -0,0 +1,17 @@
+-- What the rotation scheduler keeps, see `wotd_lib::word_rotation`.
+CREATE TABLE rotation_leases (
+    name TEXT PRIMARY KEY,
+    holder TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
+
+CREATE TABLE rotation_runs (
+    id TEXT PRIMARY KEY,
+    holder TEXT NOT NULL,
+    date TEXT NOT NULL,
+    started_at BIGINT NOT NULL,
+    finished_at BIGINT NOT NULL,
+    -- JSON of the outcome, like `{"result": "rotated", "word": "petrichor"}`.
+    outcome TEXT NOT NULL
+);
+CREATE INDEX rotation_runs_started_at ON rotation_runs (started_at);
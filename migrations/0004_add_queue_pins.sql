@@ -0,0 +1,4 @@
+-- The day a queued word is pinned to, as `YYYY-MM-DD`. Unique, but any number of words can be
+-- unpinned.
+ALTER TABLE queue_words ADD COLUMN pinned_for TEXT;
+CREATE UNIQUE INDEX queue_words_pinned_for ON queue_words (pinned_for);
@@ -0,0 +1,9 @@
+-- Where an item stands in the queue, lowest first. Queued items keep their order, their rank
+-- starts out as when they were added.
+ALTER TABLE queue_words ADD COLUMN rank BIGINT NOT NULL DEFAULT 0;
+UPDATE queue_words SET rank = added_at;
+CREATE INDEX queue_words_rank ON queue_words (rank);
+
+-- Who suggested the word, NULL when they deleted their account.
+ALTER TABLE queue_words ADD COLUMN suggested_by_id TEXT;
+CREATE INDEX queue_words_suggested_by_id ON queue_words (suggested_by_id);
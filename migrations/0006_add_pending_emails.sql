@@ -0,0 +1,2 @@
+-- The address a user changed to, until they follow the link sent to it.
+ALTER TABLE users ADD COLUMN pending_email TEXT;
@@ -0,0 +1,5 @@
+-- Expired rows are deleted periodically, see `user_sql::delete_expired`.
+CREATE INDEX refresh_tokens_expires_at ON refresh_tokens (expires_at);
+CREATE INDEX revoked_tokens_expires_at ON revoked_tokens (expires_at);
+CREATE INDEX login_attempts_expires_at ON login_attempts (expires_at);
+CREATE INDEX password_resets_expires_at ON password_resets (expires_at);
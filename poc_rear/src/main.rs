@@ -1,11 +1,11 @@
-use api_lib::{auth_guard, auth_routes, user_routes, webutil, word_routes};
-use axum::{
-    middleware,
-    routing::{get, post},
-    Extension, Router,
+mod rotation_scheduler;
+
+use api_lib::{
+    app_router::app_router, app_state::AppState, auth_token::TokenIssuer, jwks_cache::JwksCache,
+    webutil,
 };
 use config_lib::config;
-use mongodb::Client;
+use rotation_scheduler::RotationScheduler;
 use std::{net::SocketAddr, sync::Arc};
 use tower_http::trace::{self, TraceLayer};
 use tracing::Level;
@@ -14,21 +14,18 @@ use tracing::Level;
 async fn main() {
     let config = config::Config::new();
     config::Config::init_otel();
-    let client: Arc<Client> = Arc::new(config::Config::init_mongo().await);
+    let token_issuer = Arc::new(TokenIssuer::from_config());
+    let authority_keys = JwksCache::from_config().map(Arc::new);
+    if let Some(authority_keys) = &authority_keys {
+        authority_keys.clone().spawn_refresh_task();
+    }
+    let mail_sender = config::Config::mail_sender();
+    let state = AppState::from_config(token_issuer, authority_keys, mail_sender).await;
     config.log_config_values(log::Level::Info);
-    let app = Router::new()
-        .route("/api/wotd", get(word_routes::get_wotd))
-        .route("/api/wotd/update", post(word_routes::update_wotd))
-        .route("/api/wotd/suggest", post(word_routes::suggest_new_wotd))
-        .route("/api/words", post(word_routes::create_word))
-        .route("/api/words", get(word_routes::get_words))
-        .route("/api/words/:word", get(word_routes::get_word))
-        .route("/api/users/:username", get(user_routes::get_user))
-        .route("/auth/logout", get(auth_routes::user_logout))
-        .route_layer(middleware::from_fn(auth_guard::auth)) // All routes above will require 'access_token' cookie
-        .route("/auth/login", post(auth_routes::user_login))
-        .route("/auth/account", post(user_routes::create_user))
-        .layer(Extension(client))
+    if let Some(scheduler) = RotationScheduler::from_config(state.clone()) {
+        scheduler.spawn();
+    }
+    let app = app_router(state)
         .layer(
             TraceLayer::new_for_http()
                 .on_request(trace::DefaultOnRequest::new().level(Level::INFO))
@@ -45,7 +42,7 @@ async fn main() {
 
     let addr = SocketAddr::from((config.service_ip(), config.service_port()));
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use api_lib::app_state::AppState;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use config_lib::config::Config;
+use cron::Schedule;
+use tokio::task::JoinHandle;
+use wotd_lib::word_logic::run_scheduled_rotation;
+
+/// Rotates the queue on a cron schedule. Every replica runs one, the lease makes sure only one of
+/// them rotates at each firing.
+pub struct RotationScheduler {
+    schedule: Schedule,
+    timezone: Tz,
+    holder: String,
+    state: AppState,
+}
+
+impl RotationScheduler {
+    /// `None` when `WOTD_ROTATION_CRON` is not set.
+    pub fn from_config(state: AppState) -> Option<RotationScheduler> {
+        Some(RotationScheduler {
+            schedule: Config::rotation_schedule()?,
+            timezone: state.wotd_timezone,
+            holder: Config::instance_name(),
+            state,
+        })
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut fire_at = self.next_after(Utc::now());
+            while let Some(at) = fire_at {
+                tokio::time::sleep(until(at)).await;
+                self.rotate(at).await;
+                // A slow rotation can run into the next firing, that one is skipped then.
+                fire_at = self.next_after(at.max(Utc::now()));
+            }
+            tracing::warn!("the rotation schedule has no firings left, the scheduler stops");
+        })
+    }
+
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        next_firing(&self.schedule, self.timezone, after)
+    }
+
+    /// The lease lasts until the next firing, so a replica whose clock runs a little behind
+    /// cannot rotate a second time.
+    async fn rotate(&self, at: DateTime<Utc>) {
+        let date = at.with_timezone(&self.timezone).date_naive();
+        let lease_until = self
+            .next_after(at)
+            .unwrap_or(at + chrono::Duration::days(1));
+
+        let result = run_scheduled_rotation(
+            self.state.queue.as_ref(),
+            self.state.history.as_ref(),
+            self.state.rotations.as_ref(),
+            &self.holder,
+            date,
+            lease_until.into(),
+        )
+        .await;
+        match result {
+            Ok(Some(run)) => tracing::info!("rotation for {date}: {:?}", run.outcome),
+            Ok(None) => {}
+            Err(err) => tracing::error!("could not run the rotation for {date}: {err}"),
+        }
+    }
+}
+
+/// The first firing of `schedule` in `timezone` after `after`.
+fn next_firing(schedule: &Schedule, timezone: Tz, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&after.with_timezone(&timezone))
+        .next()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+fn until(at: DateTime<Utc>) -> Duration {
+    (at - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod rotation_scheduler_tests {
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().into()
+    }
+
+    #[test]
+    fn firings_follow_the_timezone() {
+        let midnight: Schedule = "0 0 0 * * *".parse().unwrap();
+
+        let next = next_firing(
+            &midnight,
+            chrono_tz::Europe::Amsterdam,
+            at("2024-02-13T12:00:00Z"),
+        );
+
+        assert_eq!(Some(at("2024-02-13T23:00:00Z")), next);
+    }
+
+    #[test]
+    fn past_firings_are_not_waited_for() {
+        assert_eq!(Duration::ZERO, until(at("2024-02-14T00:00:00Z")));
+    }
+}
@@ -1,2 +1,13 @@
+pub mod user_api_key;
+pub mod user_lockout;
 pub mod user_logic;
+pub mod user_mail;
+pub mod user_memory;
 pub mod user_models;
+pub mod user_mongo;
+pub mod user_password;
+pub mod user_password_reset;
+pub mod user_repository;
+pub mod user_session;
+pub mod user_sql;
+pub mod user_totp;
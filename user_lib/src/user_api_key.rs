@@ -0,0 +1,232 @@
+use bson::oid::ObjectId;
+use error_lib::api_error::ApiError;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    user_repository::ApiKeyRepository,
+    user_session::{generate_token, hash_token},
+};
+
+/// Every API key starts with this, which is how the auth guard tells them apart from JWTs.
+pub const API_KEY_PREFIX: &str = "wotd_";
+pub const API_KEY_NAME_MAX_CHARS: u64 = 64;
+/// There are only this many scopes, a longer list repeats some.
+pub const SCOPES_MAX: u64 = 3;
+
+/// How many characters of a key are kept in plain text, so the owner can tell their keys apart.
+const KEY_HINT_LEN: usize = API_KEY_PREFIX.len() + 6;
+
+/// What an API key may be used for. Keys can only reach routes that ask for one of their scopes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKeyScope {
+    /// Reading words and the word of the day.
+    ReadWords,
+    /// Suggesting words for the queue.
+    Suggest,
+    /// Rotating the word of the day. The owner must also be a moderator.
+    RotateWotd,
+}
+
+/// An API key as it is stored in the database. Only the hash of the key is kept.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKeyModel {
+    pub _id: ObjectId,
+    pub user_id: ObjectId,
+    pub name: String,
+    pub key_hint: String,
+    pub key_hash: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_at: mongodb::bson::DateTime,
+    pub last_used_at: Option<mongodb::bson::DateTime>,
+    pub revoked_at: Option<mongodb::bson::DateTime>,
+}
+
+/// What is required when creating a new API key.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoApiKeyCreate {
+    #[validate(length(min = 1, max = "API_KEY_NAME_MAX_CHARS"))]
+    pub name: String,
+    #[validate(length(max = "SCOPES_MAX"))]
+    pub scopes: Vec<ApiKeyScope>,
+}
+
+/// An API key as shown to its owner.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoApiKey {
+    pub id: String,
+    pub name: String,
+    pub key_hint: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<ApiKeyModel> for DtoApiKey {
+    fn from(api_key: ApiKeyModel) -> Self {
+        DtoApiKey {
+            id: api_key._id.to_hex(),
+            name: api_key.name,
+            key_hint: api_key.key_hint,
+            scopes: api_key.scopes,
+            created_at: api_key.created_at.into(),
+            last_used_at: api_key.last_used_at.map(Into::into),
+        }
+    }
+}
+
+/// A newly created API key. This is the only time the key itself is ever returned.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoNewApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: DtoApiKey,
+}
+
+/// Whether a credential presented to the auth guard is an API key rather than a JWT.
+pub fn is_api_key(credential: &str) -> bool {
+    credential.starts_with(API_KEY_PREFIX)
+}
+
+fn new_api_key(user_id: ObjectId, create_form: DtoApiKeyCreate) -> (String, ApiKeyModel) {
+    let (token, _) = generate_token();
+    let key = format!("{API_KEY_PREFIX}{token}");
+
+    let mut scopes = create_form.scopes;
+    scopes.sort();
+    scopes.dedup();
+
+    let model = ApiKeyModel {
+        _id: ObjectId::new(),
+        user_id,
+        name: create_form.name,
+        key_hint: key[..KEY_HINT_LEN].to_string(),
+        key_hash: hash_token(&key),
+        scopes,
+        created_at: chrono::Utc::now().into(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    (key, model)
+}
+
+/// Creates an API key owned by `user_id`.
+pub async fn create_api_key(
+    api_keys: &dyn ApiKeyRepository,
+    user_id: ObjectId,
+    create_form: DtoApiKeyCreate,
+) -> Result<DtoNewApiKey, ApiError> {
+    if create_form.scopes.is_empty() {
+        return Err(ApiError::BadRequest(
+            "an api key needs at least one scope".to_string(),
+        ));
+    }
+
+    let (key, model) = new_api_key(user_id, create_form);
+    api_keys.insert(&model).await?;
+
+    Ok(DtoNewApiKey {
+        key,
+        api_key: DtoApiKey::from(model),
+    })
+}
+
+/// Lists the API keys of a user that have not been revoked, newest first.
+pub async fn list_api_keys(
+    api_keys: &dyn ApiKeyRepository,
+    user_id: ObjectId,
+) -> Result<Vec<DtoApiKey>, ApiError> {
+    let active = api_keys.list_active(user_id).await?;
+    Ok(active.into_iter().map(DtoApiKey::from).collect())
+}
+
+/// Revokes an API key. Returns `NotFound` if `user_id` has no such key.
+pub async fn revoke_api_key(
+    api_keys: &dyn ApiKeyRepository,
+    user_id: ObjectId,
+    key_id: &str,
+) -> Result<(), ApiError> {
+    let key_id = ObjectId::parse_str(key_id).map_err(|_err| ApiError::NotFound)?;
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+
+    if !api_keys.revoke(user_id, key_id, now).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+/// Deletes every key of a user, for when their account is deleted.
+pub async fn delete_api_keys(
+    api_keys: &dyn ApiKeyRepository,
+    user_id: ObjectId,
+) -> Result<(), ApiError> {
+    api_keys.delete_all(user_id).await
+}
+
+/// Looks up the API key that was presented, recording that it was used.
+///
+/// Returns `Unauthorized` for unknown and revoked keys.
+pub async fn authenticate_api_key(
+    api_keys: &dyn ApiKeyRepository,
+    presented_key: &str,
+) -> Result<ApiKeyModel, ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+
+    api_keys
+        .authenticate(&hash_token(presented_key), now)
+        .await?
+        .ok_or(ApiError::Unauthorized)
+}
+
+#[cfg(test)]
+mod api_key_tests {
+    use super::*;
+
+    fn create_form(scopes: Vec<ApiKeyScope>) -> DtoApiKeyCreate {
+        DtoApiKeyCreate {
+            name: "nightly rotation".to_string(),
+            scopes,
+        }
+    }
+
+    #[test]
+    fn new_key_is_recognised_and_hashed() {
+        let (key, model) = new_api_key(ObjectId::new(), create_form(vec![ApiKeyScope::Suggest]));
+
+        assert!(is_api_key(&key));
+        assert_eq!(hash_token(&key), model.key_hash);
+        assert!(key.starts_with(&model.key_hint));
+        assert_eq!(KEY_HINT_LEN, model.key_hint.len());
+    }
+
+    #[test]
+    fn jwt_is_not_an_api_key() {
+        assert!(!is_api_key("eyJhbGciOiJSUzI1NiJ9.e30.c2ln"));
+    }
+
+    #[test]
+    fn dto_does_not_expose_hash() {
+        let (_, model) = new_api_key(ObjectId::new(), create_form(vec![ApiKeyScope::ReadWords]));
+
+        let json = serde_json::to_value(DtoApiKey::from(model)).unwrap();
+
+        assert!(json.get("key_hash").is_none());
+        assert_eq!(json["scopes"], serde_json::json!(["read-words"]));
+    }
+
+    #[test]
+    fn scopes_use_kebab_case() {
+        let scopes: Vec<ApiKeyScope> =
+            serde_json::from_str(r#"["read-words", "suggest", "rotate-wotd"]"#).unwrap();
+
+        assert_eq!(
+            vec![
+                ApiKeyScope::ReadWords,
+                ApiKeyScope::Suggest,
+                ApiKeyScope::RotateWotd
+            ],
+            scopes
+        );
+    }
+}
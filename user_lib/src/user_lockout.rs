@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use bson::oid::ObjectId;
+use error_lib::api_error::ApiError;
+use serde::{Deserialize, Serialize};
+
+use crate::user_repository::LoginAttemptRepository;
+
+/// What failed login attempts are counted against.
+///
+/// Counting per username protects a single account from a distributed attack, counting per IP
+/// stops one client from trying a common password against many accounts.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockoutKind {
+    Username,
+    Ip,
+}
+
+impl LockoutKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockoutKind::Username => "username",
+            LockoutKind::Ip => "ip",
+        }
+    }
+}
+
+/// How many failures are tolerated and how long the lockouts after that get.
+///
+/// These are read from the environment by `config_lib`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Failed attempts allowed for a single username before it is locked.
+    pub max_attempts_per_username: u32,
+    /// Failed attempts allowed from a single IP before it is locked. Higher than the username
+    /// limit, since many users can share an IP.
+    pub max_attempts_per_ip: u32,
+    /// Lockout after the first failure over the limit, it doubles with every failure after that.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Failures are forgotten once there has not been one for this long.
+    pub window: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        LockoutPolicy {
+            max_attempts_per_username: 5,
+            max_attempts_per_ip: 20,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(60 * 60),
+            window: Duration::from_secs(60 * 60 * 24),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// How long to lock `kind` out for after its `failures`th failed attempt, if at all.
+    pub fn lockout_for(&self, kind: LockoutKind, failures: u32) -> Option<Duration> {
+        let allowed = match kind {
+            LockoutKind::Username => self.max_attempts_per_username,
+            LockoutKind::Ip => self.max_attempts_per_ip,
+        };
+        if failures < allowed {
+            return None;
+        }
+
+        // Capping the exponent keeps the multiplication from overflowing, max_delay caps the rest.
+        let doublings = (failures - allowed).min(20);
+        Some(
+            self.base_delay
+                .saturating_mul(2u32.pow(doublings))
+                .min(self.max_delay),
+        )
+    }
+}
+
+/// Failed login attempts for a single username or IP.
+///
+/// A TTL index on `expires_at` removes the entry once the policy's window has passed without a
+/// failure.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoginAttemptModel {
+    pub _id: ObjectId,
+    pub kind: LockoutKind,
+    pub subject: String,
+    pub failures: u32,
+    pub last_failure_at: mongodb::bson::DateTime,
+    pub locked_until: Option<mongodb::bson::DateTime>,
+    pub expires_at: mongodb::bson::DateTime,
+}
+
+/// Lockout state as shown to admins.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoLockout {
+    pub kind: LockoutKind,
+    pub subject: String,
+    pub failures: u32,
+    pub last_failure_at: chrono::DateTime<chrono::Utc>,
+    pub locked_until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<LoginAttemptModel> for DtoLockout {
+    fn from(attempt: LoginAttemptModel) -> Self {
+        DtoLockout {
+            kind: attempt.kind,
+            subject: attempt.subject,
+            failures: attempt.failures,
+            last_failure_at: attempt.last_failure_at.into(),
+            locked_until: attempt.locked_until.map(Into::into),
+        }
+    }
+}
+
+/// Returns how much longer the longest of the lockouts on `subjects` lasts, if any is locked.
+pub async fn lockout_remaining(
+    attempts: &dyn LoginAttemptRepository,
+    subjects: &[(LockoutKind, &str)],
+) -> Result<Option<Duration>, ApiError> {
+    let now = chrono::Utc::now();
+
+    let remaining = attempts
+        .find_locked(subjects, now.into())
+        .await?
+        .into_iter()
+        .filter_map(|attempt| attempt.locked_until)
+        .map(|locked_until| {
+            (chrono::DateTime::<chrono::Utc>::from(locked_until) - now)
+                .to_std()
+                .unwrap_or_default()
+        })
+        .max();
+    Ok(remaining)
+}
+
+/// Counts a failed login against `subject`, locking it out if the policy says so.
+pub async fn record_failure(
+    attempts: &dyn LoginAttemptRepository,
+    kind: LockoutKind,
+    subject: &str,
+    policy: &LockoutPolicy,
+) -> Result<(), ApiError> {
+    let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::seconds(policy.window.as_secs() as i64);
+
+    let attempt = attempts
+        .record_failure(kind, subject, now.into(), expires_at.into())
+        .await?;
+
+    if let Some(lockout) = policy.lockout_for(kind, attempt.failures) {
+        let locked_until = now + chrono::Duration::seconds(lockout.as_secs() as i64);
+        tracing::warn!(
+            subject,
+            "locking out {} after {} failed logins until {locked_until}",
+            kind.as_str(),
+            attempt.failures
+        );
+        attempts.lock(attempt._id, locked_until.into()).await?;
+    }
+    Ok(())
+}
+
+/// Forgets the failed attempts of a username after it logged in successfully.
+///
+/// The IP keeps its count, otherwise an attacker could reset it by logging into their own account.
+pub async fn record_success(
+    attempts: &dyn LoginAttemptRepository,
+    username: &str,
+) -> Result<(), ApiError> {
+    attempts.delete(LockoutKind::Username, username).await?;
+    Ok(())
+}
+
+/// Lists every username and IP with recent failed attempts, most recent first.
+pub async fn list_lockouts(
+    attempts: &dyn LoginAttemptRepository,
+) -> Result<Vec<DtoLockout>, ApiError> {
+    let lockouts = attempts.list().await?;
+    Ok(lockouts.into_iter().map(DtoLockout::from).collect())
+}
+
+/// Lifts the lockout on `subject` and forgets its failed attempts.
+///
+/// Returns `NotFound` if there were none.
+pub async fn unlock(
+    attempts: &dyn LoginAttemptRepository,
+    kind: LockoutKind,
+    subject: &str,
+) -> Result<(), ApiError> {
+    if !attempts.delete(kind, subject).await? {
+        return Err(ApiError::NotFound);
+    }
+    tracing::info!(subject, "unlocked {}", kind.as_str());
+    Ok(())
+}
+
+#[cfg(test)]
+mod lockout_tests {
+    use super::*;
+
+    #[test]
+    fn no_lockout_below_limit() {
+        let policy = LockoutPolicy::default();
+
+        assert_eq!(None, policy.lockout_for(LockoutKind::Username, 4));
+        assert_eq!(None, policy.lockout_for(LockoutKind::Ip, 19));
+    }
+
+    #[test]
+    fn lockout_doubles_with_each_failure() {
+        let policy = LockoutPolicy::default();
+
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            policy.lockout_for(LockoutKind::Username, 5)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            policy.lockout_for(LockoutKind::Username, 6)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            policy.lockout_for(LockoutKind::Username, 7)
+        );
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        let policy = LockoutPolicy::default();
+
+        assert_eq!(
+            Some(policy.max_delay),
+            policy.lockout_for(LockoutKind::Ip, 500)
+        );
+        assert_eq!(
+            Some(policy.max_delay),
+            policy.lockout_for(LockoutKind::Username, u32::MAX)
+        );
+    }
+}
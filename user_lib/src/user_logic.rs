@@ -3,57 +3,318 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use bson::{doc, oid::ObjectId};
-use mongodb::Collection;
+use bson::oid::ObjectId;
+use error_lib::api_error::ApiError;
 
-use crate::user_models::{DtoUserCreate, UserModel};
+use crate::{
+    user_models::{
+        default_roles, AccountState, DtoPrivateProfile, DtoPublicProfile, DtoUser, DtoUserCreate,
+        DtoUserLogin, DtoUserUpdate, Role, UserModel,
+    },
+    user_password::{
+        hash_password, verify_dummy_password, verify_password, PasswordParams, PasswordVerification,
+    },
+    user_repository::{PendingEmail, UserChanges, UserRepository},
+    user_session::generate_token,
+};
 
+/// Creates a new, unverified user. The caller is expected to send them a verification link.
 pub async fn create_new_user(
-    collection: Collection<UserModel>,
+    users: &dyn UserRepository,
     create_user_form: DtoUserCreate,
-) -> Result<Response, StatusCode> {
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
     // First check if the username already exists, if it does return a 409 CONFLICT code.
-    if collection
-        .find_one(doc! { "username": create_user_form.username.clone()}, None)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
+    if users
+        .find_by_username(&create_user_form.username)
+        .await?
         .is_some()
     {
-        return Err(StatusCode::CONFLICT);
+        return Err(ApiError::Conflict(format!(
+            "username {} is taken",
+            create_user_form.username
+        )));
     }
 
     let user = UserModel {
         _id: ObjectId::new(),
         username: create_user_form.username.clone(),
-        password: create_user_form.password.clone(),
+        display_name: None,
+        password: hash_password(&create_user_form.password, password_params).await?,
         email: create_user_form.email.clone(),
+        roles: default_roles(),
+        state: AccountState::Unverified,
+        verification_nonce: Some(generate_token().0),
+        pending_email: None,
+        totp: None,
         created_at: chrono::Utc::now().into(),
         updated_at: chrono::Utc::now().into(),
     };
 
-    collection
-        .insert_one(user, None)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
+    users.insert(&user).await?;
 
-    Ok((StatusCode::OK, "user created!".to_string()).into_response())
+    Ok(user)
 }
 
+/// Looks up a user by `username`, as seen by `viewer`.
+///
+/// Only the user themself and admins get the private profile, everyone else the public one.
 pub async fn get_one_user(
-    collection: Collection<UserModel>,
+    users: &dyn UserRepository,
+    viewer: &DtoUser,
     username: String,
-) -> Result<Response, StatusCode> {
-    let user = collection
-        .find_one(
-            mongodb::bson::doc! { "username": &username.to_string() },
-            None,
-        )
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+) -> Result<Response, ApiError> {
+    let user = users
+        .find_by_username(&username)
+        .await?
+        .ok_or(ApiError::NotFound)?;
 
     let id = user._id;
     tracing::debug!("found user with id: {id}");
 
-    Ok((StatusCode::OK, Json(Some(user))).into_response())
+    let user = DtoUser::from(user);
+    if viewer._id == user._id || viewer.has_role(Role::Admin) {
+        Ok((StatusCode::OK, Json(DtoPrivateProfile::from(user))).into_response())
+    } else {
+        Ok((StatusCode::OK, Json(DtoPublicProfile::from(user))).into_response())
+    }
+}
+
+/// Check the credentials in `login_form`, returning the matching user when they are valid.
+///
+/// Users that have not verified their email yet get a `Forbidden`, but only once their password
+/// has been checked.
+pub async fn login_user(
+    users: &dyn UserRepository,
+    login_form: DtoUserLogin,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    let user = check_credentials(users, login_form, password_params).await?;
+    if user.state == AccountState::Unverified {
+        tracing::info!(user.username, "rejected login of unverified user");
+        return Err(ApiError::Forbidden);
+    }
+    Ok(user)
+}
+
+/// Users whose stored password is legacy plaintext (or was hashed with old cost params) have it
+/// rehashed here, since this is the only time the plaintext password is available.
+async fn check_credentials(
+    users: &dyn UserRepository,
+    login_form: DtoUserLogin,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    let user = match users.find_by_username(&login_form.username).await? {
+        Some(user) => user,
+        None => {
+            verify_dummy_password(&login_form.password, password_params).await;
+            return Err(ApiError::NotFound);
+        }
+    };
+
+    match verify_password(&login_form.password, &user.password, password_params).await? {
+        PasswordVerification::Invalid => Err(ApiError::NotFound),
+        PasswordVerification::Valid => Ok(user),
+        PasswordVerification::ValidNeedsRehash => {
+            let rehashed = hash_password(&login_form.password, password_params).await?;
+            let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+            // A failed upgrade should not stop the user from logging in, it will be retried next time.
+            match users.set_password(user._id, &rehashed, now).await {
+                Ok(_) => tracing::info!(user.username, "rehashed stored password"),
+                Err(err) => {
+                    tracing::warn!(user.username, "failed to rehash stored password: {err}")
+                }
+            }
+            Ok(UserModel {
+                password: rehashed,
+                updated_at: now,
+                ..user
+            })
+        }
+    }
+}
+
+/// Replaces the roles of a user. Every user keeps the `user` role.
+pub async fn set_user_roles(
+    users: &dyn UserRepository,
+    username: String,
+    mut roles: Vec<Role>,
+) -> Result<Response, ApiError> {
+    roles.push(Role::User);
+    roles.sort();
+    roles.dedup();
+
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+    if !users.set_roles(&username, &roles, now).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    tracing::info!(username, "set roles to {roles:?}");
+    Ok((StatusCode::OK, Json(roles)).into_response())
+}
+
+/// Activates a user, if `nonce` is the one from the latest verification link sent to them.
+///
+/// Returns `BadRequest` if the link was already used, or a newer one has been sent since.
+pub async fn verify_email(
+    users: &dyn UserRepository,
+    username: &str,
+    nonce: &str,
+) -> Result<(), ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+    if !users.verify_email(username, nonce, now).await? {
+        return Err(ApiError::BadRequest(
+            "this verification link is no longer valid".to_string(),
+        ));
+    }
+    tracing::info!(username, "verified email");
+    Ok(())
+}
+
+/// Replaces the verification nonce of an unverified user, so that a new link can be sent.
+///
+/// Returns `None` if there is no such user, or they are already verified.
+pub async fn renew_verification_nonce(
+    users: &dyn UserRepository,
+    username: &str,
+) -> Result<Option<UserModel>, ApiError> {
+    users
+        .renew_verification_nonce(username, &generate_token().0)
+        .await
+}
+
+/// Replaces the password of a user that proved they own their email by following a reset link.
+///
+/// That proof is also enough to verify the account, if it was not yet.
+pub async fn reset_password(
+    users: &dyn UserRepository,
+    user_id: ObjectId,
+    password: &str,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+    let password = hash_password(password, password_params).await?;
+
+    let user = users
+        .reset_password(user_id, &password, now)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    tracing::info!(user.username, "reset password");
+    Ok(user)
+}
+
+/// Turns an already validated `update` into the changes to store.
+///
+/// A new email gets a new verification nonce, for the link that makes it the user's email.
+fn user_changes(update: DtoUserUpdate) -> UserChanges {
+    let mut changes = UserChanges::default();
+
+    if let Some(email) = update.email {
+        changes.pending_email = Some(PendingEmail {
+            email,
+            verification_nonce: generate_token().0,
+        });
+    }
+    if let Some(display_name) = update.display_name {
+        let display_name = display_name.trim();
+        changes.display_name = Some(Some(display_name.to_string()).filter(|name| !name.is_empty()));
+    }
+    changes
+}
+
+/// Applies a user's changes to their own profile, returning the updated user.
+///
+/// A new email needs the current password, and only becomes `pending_email`. The caller is
+/// expected to send a verification link to it.
+pub async fn update_user(
+    users: &dyn UserRepository,
+    user_id: ObjectId,
+    update: DtoUserUpdate,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    if update.email.is_some() {
+        let current_password = update.current_password.as_deref().unwrap_or_default();
+        confirm_password(users, user_id, current_password, password_params).await?;
+    }
+
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+    users
+        .update_profile(user_id, &user_changes(update), now)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+/// Checks `password` is the current password of the user, before letting them change something
+/// a stolen session should not be able to.
+///
+/// Returns `Forbidden` if it is not.
+pub async fn confirm_password(
+    users: &dyn UserRepository,
+    user_id: ObjectId,
+    password: &str,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    let user = users.find_by_id(user_id).await?.ok_or(ApiError::NotFound)?;
+
+    match verify_password(password, &user.password, password_params).await? {
+        PasswordVerification::Invalid => Err(ApiError::Forbidden),
+        PasswordVerification::Valid | PasswordVerification::ValidNeedsRehash => Ok(user),
+    }
+}
+
+/// Changes the password of a user after checking their current one, returning the updated user.
+pub async fn change_password(
+    users: &dyn UserRepository,
+    user_id: ObjectId,
+    current_password: &str,
+    new_password: &str,
+    password_params: &PasswordParams,
+) -> Result<UserModel, ApiError> {
+    let user = confirm_password(users, user_id, current_password, password_params).await?;
+
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+    let password = hash_password(new_password, password_params).await?;
+    users.set_password(user._id, &password, now).await?;
+
+    tracing::info!(user.username, "changed password");
+    Ok(UserModel {
+        password,
+        updated_at: now,
+        ..user
+    })
+}
+
+/// Deletes a user. Anything else referring to them has to be cleaned up by the caller.
+pub async fn delete_user(users: &dyn UserRepository, user_id: ObjectId) -> Result<(), ApiError> {
+    if !users.delete(user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod user_logic_tests {
+    use super::*;
+
+    #[test]
+    fn update_sets_only_given_fields() {
+        let changes = user_changes(DtoUserUpdate {
+            display_name: Some("  Jork  ".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(Some(Some("Jork".to_string())), changes.display_name);
+        assert_eq!(None, changes.pending_email);
+    }
+
+    #[test]
+    fn update_clears_empty_display_name() {
+        let changes = user_changes(DtoUserUpdate {
+            display_name: Some(" ".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(Some(None), changes.display_name);
+    }
 }
@@ -0,0 +1,695 @@
+use std::{
+    cmp::Reverse,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use error_lib::api_error::ApiError;
+
+use crate::{
+    user_api_key::ApiKeyModel,
+    user_lockout::{LockoutKind, LoginAttemptModel},
+    user_models::{AccountState, Role, UserModel},
+    user_password_reset::PasswordResetModel,
+    user_repository::{
+        ApiKeyRepository, LoginAttemptRepository, PasswordResetRepository, SessionRepository,
+        UserChanges, UserRepository,
+    },
+    user_session::{RefreshTokenModel, RevokedTokenModel},
+    user_totp::UserTotpModel,
+};
+
+// Keeps everything in memory, for tests and local demos that should not need a database.
+//
+// Writes check the same unique keys as the indexes `Config::init_mongo` creates, so conflicts
+// behave like they do against Mongo. Nothing expires on its own, readers compare `expires_at`
+// themselves just like they have to before a TTL index gets around to it.
+
+/// A panic while holding the lock cannot leave a half written entry behind, so the data is still
+/// good to use.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    users: Mutex<Vec<UserModel>>,
+}
+
+impl MemoryUserRepository {
+    /// Applies `update` to the user with `id`, returning the updated user.
+    fn update(&self, id: ObjectId, update: impl FnOnce(&mut UserModel)) -> Option<UserModel> {
+        let mut users = lock(&self.users);
+        let user = users.iter_mut().find(|user| user._id == id)?;
+        update(user);
+        Some(user.clone())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn insert(&self, user: &UserModel) -> Result<(), ApiError> {
+        let mut users = lock(&self.users);
+        if users.iter().any(|taken| taken.username == user.username) {
+            return Err(ApiError::Duplicate);
+        }
+        users.push(user.clone());
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<UserModel>, ApiError> {
+        Ok(lock(&self.users)
+            .iter()
+            .find(|user| user._id == id)
+            .cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<UserModel>, ApiError> {
+        Ok(lock(&self.users)
+            .iter()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn set_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let updated = self.update(id, |user| {
+            user.password = password.to_string();
+            user.updated_at = now;
+        });
+        Ok(updated.is_some())
+    }
+
+    async fn set_roles(
+        &self,
+        username: &str,
+        roles: &[Role],
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        match users.iter_mut().find(|user| user.username == username) {
+            Some(user) => {
+                user.roles = roles.to_vec();
+                user.updated_at = now;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn verify_email(
+        &self,
+        username: &str,
+        nonce: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let user = users.iter_mut().find(|user| {
+            user.username == username
+                && (user.state == AccountState::Unverified || user.pending_email.is_some())
+                && user.verification_nonce.as_deref() == Some(nonce)
+        });
+        match user {
+            Some(user) => {
+                user.state = AccountState::Active;
+                if let Some(pending_email) = user.pending_email.take() {
+                    user.email = pending_email;
+                }
+                user.verification_nonce = None;
+                user.updated_at = now;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn renew_verification_nonce(
+        &self,
+        username: &str,
+        nonce: &str,
+    ) -> Result<Option<UserModel>, ApiError> {
+        let mut users = lock(&self.users);
+        let user = users
+            .iter_mut()
+            .find(|user| user.username == username && user.state == AccountState::Unverified);
+        Ok(user.map(|user| {
+            user.verification_nonce = Some(nonce.to_string());
+            user.clone()
+        }))
+    }
+
+    async fn reset_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        Ok(self.update(id, |user| {
+            user.password = password.to_string();
+            user.state = AccountState::Active;
+            user.verification_nonce = None;
+            user.updated_at = now;
+        }))
+    }
+
+    async fn update_profile(
+        &self,
+        id: ObjectId,
+        changes: &UserChanges,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        Ok(self.update(id, |user| {
+            if let Some(pending_email) = &changes.pending_email {
+                user.pending_email = Some(pending_email.email.clone());
+                user.verification_nonce = Some(pending_email.verification_nonce.clone());
+            }
+            if let Some(display_name) = &changes.display_name {
+                user.display_name = display_name.clone();
+            }
+            user.updated_at = now;
+        }))
+    }
+
+    async fn begin_totp(&self, id: ObjectId, totp: &UserTotpModel) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let user = users
+            .iter_mut()
+            .find(|user| user._id == id && !user.totp.as_ref().is_some_and(|totp| totp.confirmed));
+        match user {
+            Some(user) => {
+                user.totp = Some(totp.clone());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn confirm_totp(
+        &self,
+        id: ObjectId,
+        secret: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let totp = users
+            .iter_mut()
+            .filter(|user| user._id == id)
+            .find_map(|user| user.totp.as_mut())
+            .filter(|totp| totp.secret == secret && !totp.confirmed);
+        match totp {
+            Some(totp) => {
+                totp.confirmed = true;
+                totp.last_used_step = Some(step);
+                totp.recovery_code_hashes = recovery_code_hashes.to_vec();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn use_totp_step(&self, id: ObjectId, step: i64) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let totp = users
+            .iter_mut()
+            .filter(|user| user._id == id)
+            .find_map(|user| user.totp.as_mut())
+            .filter(|totp| totp.last_used_step.map_or(true, |last| last < step));
+        match totp {
+            Some(totp) => {
+                totp.last_used_step = Some(step);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code(&self, id: ObjectId, code_hash: &str) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let totp = users
+            .iter_mut()
+            .filter(|user| user._id == id)
+            .find_map(|user| user.totp.as_mut());
+        let Some(totp) = totp else {
+            return Ok(false);
+        };
+        let before = totp.recovery_code_hashes.len();
+        totp.recovery_code_hashes.retain(|hash| hash != code_hash);
+        Ok(totp.recovery_code_hashes.len() < before)
+    }
+
+    async fn clear_totp(&self, id: ObjectId) -> Result<(), ApiError> {
+        self.update(id, |user| user.totp = None);
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let mut users = lock(&self.users);
+        let before = users.len();
+        users.retain(|user| user._id != id);
+        Ok(users.len() < before)
+    }
+}
+
+#[derive(Default)]
+pub struct MemorySessionRepository {
+    refresh_tokens: Mutex<Vec<RefreshTokenModel>>,
+    revoked_tokens: Mutex<Vec<RevokedTokenModel>>,
+}
+
+#[async_trait]
+impl SessionRepository for MemorySessionRepository {
+    async fn insert_refresh_token(
+        &self,
+        refresh_token: &RefreshTokenModel,
+    ) -> Result<(), ApiError> {
+        let mut refresh_tokens = lock(&self.refresh_tokens);
+        if refresh_tokens
+            .iter()
+            .any(|taken| taken.token_hash == refresh_token.token_hash)
+        {
+            return Err(ApiError::Duplicate);
+        }
+        refresh_tokens.push(refresh_token.clone());
+        Ok(())
+    }
+
+    async fn find_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshTokenModel>, ApiError> {
+        Ok(lock(&self.refresh_tokens)
+            .iter()
+            .find(|refresh_token| refresh_token.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn claim_refresh_token(&self, id: ObjectId, now: DateTime) -> Result<bool, ApiError> {
+        let mut refresh_tokens = lock(&self.refresh_tokens);
+        let refresh_token = refresh_tokens.iter_mut().find(|refresh_token| {
+            refresh_token._id == id && refresh_token.used_at.is_none() && !refresh_token.revoked
+        });
+        match refresh_token {
+            Some(refresh_token) => {
+                refresh_token.used_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn list_active(
+        &self,
+        user_id: ObjectId,
+        now: DateTime,
+    ) -> Result<Vec<RefreshTokenModel>, ApiError> {
+        let mut active: Vec<_> = lock(&self.refresh_tokens)
+            .iter()
+            .filter(|refresh_token| {
+                refresh_token.user_id == user_id
+                    && refresh_token.used_at.is_none()
+                    && !refresh_token.revoked
+                    && refresh_token.expires_at > now
+            })
+            .cloned()
+            .collect();
+        active.sort_by_key(|model| Reverse(model.created_at));
+        Ok(active)
+    }
+
+    async fn revoke_family(&self, user_id: ObjectId, family_id: &str) -> Result<bool, ApiError> {
+        let mut found = false;
+        for refresh_token in lock(&self.refresh_tokens).iter_mut() {
+            if refresh_token.user_id == user_id && refresh_token.family_id == family_id {
+                refresh_token.revoked = true;
+                found = true;
+            }
+        }
+        Ok(found)
+    }
+
+    async fn revoke_all(&self, user_id: ObjectId) -> Result<Vec<String>, ApiError> {
+        let mut family_ids = Vec::new();
+        for refresh_token in lock(&self.refresh_tokens).iter_mut() {
+            if refresh_token.user_id != user_id {
+                continue;
+            }
+            if !refresh_token.revoked && !family_ids.contains(&refresh_token.family_id) {
+                family_ids.push(refresh_token.family_id.clone());
+            }
+            refresh_token.revoked = true;
+        }
+        Ok(family_ids)
+    }
+
+    async fn insert_revoked(&self, revoked: &RevokedTokenModel) -> Result<(), ApiError> {
+        lock(&self.revoked_tokens).push(revoked.clone());
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        session_id: Option<&str>,
+    ) -> Result<bool, ApiError> {
+        Ok(lock(&self.revoked_tokens).iter().any(|revoked| {
+            (jti.is_some() && revoked.jti.as_deref() == jti)
+                || (session_id.is_some() && revoked.session_id.as_deref() == session_id)
+        }))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryApiKeyRepository {
+    api_keys: Mutex<Vec<ApiKeyModel>>,
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryApiKeyRepository {
+    async fn insert(&self, api_key: &ApiKeyModel) -> Result<(), ApiError> {
+        let mut api_keys = lock(&self.api_keys);
+        if api_keys
+            .iter()
+            .any(|taken| taken.key_hash == api_key.key_hash)
+        {
+            return Err(ApiError::Duplicate);
+        }
+        api_keys.push(api_key.clone());
+        Ok(())
+    }
+
+    async fn list_active(&self, user_id: ObjectId) -> Result<Vec<ApiKeyModel>, ApiError> {
+        let mut active: Vec<_> = lock(&self.api_keys)
+            .iter()
+            .filter(|api_key| api_key.user_id == user_id && api_key.revoked_at.is_none())
+            .cloned()
+            .collect();
+        active.sort_by_key(|model| Reverse(model.created_at));
+        Ok(active)
+    }
+
+    async fn revoke(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let mut api_keys = lock(&self.api_keys);
+        let api_key = api_keys.iter_mut().find(|api_key| {
+            api_key._id == id && api_key.user_id == user_id && api_key.revoked_at.is_none()
+        });
+        match api_key {
+            Some(api_key) => {
+                api_key.revoked_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        lock(&self.api_keys).retain(|api_key| api_key.user_id != user_id);
+        Ok(())
+    }
+
+    async fn authenticate(
+        &self,
+        key_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<ApiKeyModel>, ApiError> {
+        let mut api_keys = lock(&self.api_keys);
+        let api_key = api_keys
+            .iter_mut()
+            .find(|api_key| api_key.key_hash == key_hash && api_key.revoked_at.is_none());
+        Ok(api_key.map(|api_key| {
+            api_key.last_used_at = Some(now);
+            api_key.clone()
+        }))
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryLoginAttemptRepository {
+    attempts: Mutex<Vec<LoginAttemptModel>>,
+}
+
+#[async_trait]
+impl LoginAttemptRepository for MemoryLoginAttemptRepository {
+    async fn find_locked(
+        &self,
+        subjects: &[(LockoutKind, &str)],
+        now: DateTime,
+    ) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        Ok(lock(&self.attempts)
+            .iter()
+            .filter(|attempt| {
+                subjects
+                    .iter()
+                    .any(|(kind, subject)| attempt.kind == *kind && attempt.subject == *subject)
+                    && attempt
+                        .locked_until
+                        .is_some_and(|locked_until| locked_until > now)
+                    && attempt.expires_at > now
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn record_failure(
+        &self,
+        kind: LockoutKind,
+        subject: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<LoginAttemptModel, ApiError> {
+        let mut attempts = lock(&self.attempts);
+        let index = match attempts
+            .iter()
+            .position(|attempt| attempt.kind == kind && attempt.subject == subject)
+        {
+            Some(index) => index,
+            None => {
+                attempts.push(LoginAttemptModel {
+                    _id: ObjectId::new(),
+                    kind,
+                    subject: subject.to_string(),
+                    failures: 0,
+                    last_failure_at: now,
+                    locked_until: None,
+                    expires_at,
+                });
+                attempts.len() - 1
+            }
+        };
+
+        let attempt = &mut attempts[index];
+        // Where Mongo's TTL index would have removed the attempt already.
+        if attempt.expires_at <= now {
+            attempt.failures = 0;
+            attempt.locked_until = None;
+        }
+        attempt.failures += 1;
+        attempt.last_failure_at = now;
+        attempt.expires_at = expires_at;
+        Ok(attempt.clone())
+    }
+
+    async fn lock(&self, id: ObjectId, locked_until: DateTime) -> Result<(), ApiError> {
+        if let Some(attempt) = lock(&self.attempts)
+            .iter_mut()
+            .find(|attempt| attempt._id == id)
+        {
+            attempt.locked_until = Some(locked_until);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, kind: LockoutKind, subject: &str) -> Result<bool, ApiError> {
+        let mut attempts = lock(&self.attempts);
+        let before = attempts.len();
+        attempts.retain(|attempt| !(attempt.kind == kind && attempt.subject == subject));
+        Ok(attempts.len() < before)
+    }
+
+    async fn list(&self) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        let mut attempts = lock(&self.attempts).clone();
+        attempts.sort_by_key(|attempt| Reverse(attempt.last_failure_at));
+        Ok(attempts)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryPasswordResetRepository {
+    resets: Mutex<Vec<PasswordResetModel>>,
+}
+
+#[async_trait]
+impl PasswordResetRepository for MemoryPasswordResetRepository {
+    async fn replace_unused(&self, reset: &PasswordResetModel) -> Result<(), ApiError> {
+        let mut resets = lock(&self.resets);
+        resets.retain(|unused| !(unused.user_id == reset.user_id && unused.used_at.is_none()));
+        if resets
+            .iter()
+            .any(|taken| taken.token_hash == reset.token_hash)
+        {
+            return Err(ApiError::Duplicate);
+        }
+        resets.push(reset.clone());
+        Ok(())
+    }
+
+    async fn consume(
+        &self,
+        token_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<PasswordResetModel>, ApiError> {
+        let mut resets = lock(&self.resets);
+        let reset = resets.iter_mut().find(|reset| {
+            reset.token_hash == token_hash && reset.used_at.is_none() && reset.expires_at > now
+        });
+        Ok(reset.map(|reset| {
+            let unused = reset.clone();
+            reset.used_at = Some(now);
+            unused
+        }))
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        lock(&self.resets).retain(|reset| reset.user_id != user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod user_memory_tests {
+    use super::*;
+    use crate::user_models::default_roles;
+
+    fn user(username: &str) -> UserModel {
+        UserModel {
+            _id: ObjectId::new(),
+            username: username.to_string(),
+            display_name: None,
+            password: "hash".to_string(),
+            email: format!("{username}@example.com"),
+            roles: default_roles(),
+            state: AccountState::Unverified,
+            verification_nonce: Some("nonce".to_string()),
+            pending_email: None,
+            totp: None,
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique() {
+        let users = MemoryUserRepository::default();
+        users.insert(&user("jork")).await.unwrap();
+
+        let err = users.insert(&user("jork")).await.unwrap_err();
+
+        assert!(err.is_duplicate_key());
+        assert!(users.insert(&user("gork")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn email_is_verified_once() {
+        let users = MemoryUserRepository::default();
+        users.insert(&user("jork")).await.unwrap();
+
+        assert!(!users
+            .verify_email("jork", "wrong", DateTime::now())
+            .await
+            .unwrap());
+        assert!(users
+            .verify_email("jork", "nonce", DateTime::now())
+            .await
+            .unwrap());
+        assert!(!users
+            .verify_email("jork", "nonce", DateTime::now())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn failures_are_counted_per_subject() {
+        let attempts = MemoryLoginAttemptRepository::default();
+        let (now, later) = (DateTime::from_millis(1000), DateTime::from_millis(2000));
+
+        attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        let second = attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        let other = attempts
+            .record_failure(LockoutKind::Ip, "jork", now, later)
+            .await
+            .unwrap();
+
+        assert_eq!(2, second.failures);
+        assert_eq!(1, other.failures);
+    }
+
+    #[tokio::test]
+    async fn expired_failures_are_forgotten() {
+        let attempts = MemoryLoginAttemptRepository::default();
+        let (now, later) = (DateTime::from_millis(1000), DateTime::from_millis(2000));
+        let attempt = attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        attempts.lock(attempt._id, later).await.unwrap();
+
+        let subjects = [(LockoutKind::Username, "jork")];
+        assert!(attempts
+            .find_locked(&subjects, later)
+            .await
+            .unwrap()
+            .is_empty());
+        let restarted = attempts
+            .record_failure(
+                LockoutKind::Username,
+                "jork",
+                later,
+                DateTime::from_millis(3000),
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, restarted.failures);
+        assert_eq!(None, restarted.locked_until);
+    }
+
+    #[tokio::test]
+    async fn refresh_token_is_claimed_once() {
+        let sessions = MemorySessionRepository::default();
+        let refresh_token = RefreshTokenModel {
+            _id: ObjectId::new(),
+            token_hash: "hash".to_string(),
+            family_id: "family".to_string(),
+            user_id: ObjectId::new(),
+            created_at: DateTime::now(),
+            expires_at: DateTime::now(),
+            used_at: None,
+            revoked: false,
+        };
+        sessions.insert_refresh_token(&refresh_token).await.unwrap();
+
+        assert!(sessions
+            .claim_refresh_token(refresh_token._id, DateTime::now())
+            .await
+            .unwrap());
+        assert!(!sessions
+            .claim_refresh_token(refresh_token._id, DateTime::now())
+            .await
+            .unwrap());
+    }
+}
@@ -1,18 +1,83 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::user_totp::UserTotpModel;
+
+/// What a user is allowed to do. Roles are ordered, each one includes everything below it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    /// Can add words and rotate the word of the day.
+    Moderator,
+    /// Can manage other users.
+    Admin,
+}
+
+/// Users created before roles existed have no `roles` field, they are regular users.
+pub fn default_roles() -> Vec<Role> {
+    vec![Role::User]
+}
+
+/// Whether a user can log in yet.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountState {
+    /// The user has not followed the link sent to their email yet.
+    Unverified,
+    /// Users created before email verification existed have no `state`, they are active.
+    #[default]
+    Active,
+}
+
+pub const USERNAME_MIN_CHARS: u64 = 3;
+pub const USERNAME_MAX_CHARS: u64 = 32;
+pub const PASSWORD_MIN_CHARS: u64 = 8;
+/// Hashing is slow on purpose, this keeps anyone from making us hash megabytes.
+pub const PASSWORD_MAX_CHARS: u64 = 128;
+/// The longest address SMTP can deliver to.
+pub const EMAIL_MAX_CHARS: u64 = 254;
+pub const DISPLAY_NAME_MAX_CHARS: u64 = 64;
+/// There are only this many roles, a longer list repeats some.
+pub const ROLES_MAX: u64 = 3;
+
+/// Usernames end up in URLs, so they are limited to ASCII letters, digits, `_`, `-` and `.`.
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("username_chars");
+        err.message = Some("may only contain letters, digits, '_', '-' and '.'".into());
+        Err(err)
+    }
+}
 
 /// What is required when creating a new user.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
 pub struct DtoUserCreate {
+    #[validate(
+        length(min = "USERNAME_MIN_CHARS", max = "USERNAME_MAX_CHARS"),
+        custom = "validate_username"
+    )]
     pub username: String,
+    #[validate(length(min = "PASSWORD_MIN_CHARS", max = "PASSWORD_MAX_CHARS"))]
     pub password: String,
+    #[validate(email, length(max = "EMAIL_MAX_CHARS"))]
     pub email: String,
 }
 
 /// What is required when a user is logging in.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// Only lengths are checked, users created before validation existed may not follow the rules.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
 pub struct DtoUserLogin {
+    #[validate(length(min = 1, max = "USERNAME_MAX_CHARS"))]
     pub username: String,
+    #[validate(length(min = 1, max = "PASSWORD_MAX_CHARS"))]
     pub password: String,
 }
 
@@ -21,18 +86,142 @@ pub struct DtoUserLogin {
 pub struct DtoUser {
     pub _id: ObjectId,
     pub username: String,
+    pub display_name: Option<String>,
+    pub email: String,
+    pub roles: Vec<Role>,
+    pub state: AccountState,
+    pub totp_enabled: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl DtoUser {
+    /// Whether the user has `role`, or a role above it.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.iter().any(|r| *r >= role)
+    }
+}
+
+/// What every logged in user can see of another user.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoPublicProfile {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub roles: Vec<Role>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<DtoUser> for DtoPublicProfile {
+    fn from(user: DtoUser) -> Self {
+        DtoPublicProfile {
+            username: user.username,
+            display_name: user.display_name,
+            roles: user.roles,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// What a user can see of themself, and admins of anyone.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoPrivateProfile {
+    pub id: String,
+    pub username: String,
+    pub display_name: Option<String>,
     pub email: String,
+    pub roles: Vec<Role>,
+    pub state: AccountState,
+    pub totp_enabled: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl From<DtoUser> for DtoPrivateProfile {
+    fn from(user: DtoUser) -> Self {
+        DtoPrivateProfile {
+            id: user._id.to_hex(),
+            username: user.username,
+            display_name: user.display_name,
+            email: user.email,
+            roles: user.roles,
+            state: user.state,
+            totp_enabled: user.totp_enabled,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// Changing the email needs the current password, like changing the password does.
+fn validate_email_change(update: &DtoUserUpdate) -> Result<(), ValidationError> {
+    if update.email.is_some() && update.current_password.is_none() {
+        let mut err = ValidationError::new("current_password_required");
+        err.message = Some("changing your email needs your current password".into());
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// What a user can change about themself. Fields that are left out stay as they are.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Validate)]
+#[validate(schema(function = "validate_email_change", skip_on_field_errors = true))]
+pub struct DtoUserUpdate {
+    /// Only changes once the link sent to the new address is followed.
+    #[validate(email, length(max = "EMAIL_MAX_CHARS"))]
+    pub email: Option<String>,
+    /// Required to change the email, a stolen session should not be able to.
+    #[validate(length(min = 1, max = "PASSWORD_MAX_CHARS"))]
+    pub current_password: Option<String>,
+    /// An empty display name removes it.
+    #[validate(length(max = "DISPLAY_NAME_MAX_CHARS"))]
+    pub display_name: Option<String>,
+}
+
+/// What is required to change the password of a logged in user.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoPasswordChange {
+    #[validate(length(min = 1, max = "PASSWORD_MAX_CHARS"))]
+    pub current_password: String,
+    #[validate(length(min = "PASSWORD_MIN_CHARS", max = "PASSWORD_MAX_CHARS"))]
+    pub new_password: String,
+}
+
+/// What is required to delete your own account.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoAccountDelete {
+    #[validate(length(min = 1, max = "PASSWORD_MAX_CHARS"))]
+    pub password: String,
+}
+
+/// What is required to change the roles of a user.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoUserRoles {
+    #[validate(length(max = "ROLES_MAX"))]
+    pub roles: Vec<Role>,
+}
+
 /// The final product of user that will go into Database.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserModel {
     pub _id: ObjectId,
     pub username: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
     pub password: String,
     pub email: String,
+    #[serde(default = "default_roles")]
+    pub roles: Vec<Role>,
+    #[serde(default)]
+    pub state: AccountState,
+    /// Changes whenever a verification link is sent, so only the latest link works, and only once.
+    #[serde(default)]
+    pub verification_nonce: Option<String>,
+    /// The address the user changed to, which replaces `email` once they follow the link sent to
+    /// it.
+    #[serde(default)]
+    pub pending_email: Option<String>,
+    #[serde(default)]
+    pub totp: Option<UserTotpModel>,
     pub created_at: mongodb::bson::DateTime,
     pub updated_at: mongodb::bson::DateTime,
 }
@@ -42,7 +231,11 @@ impl From<UserModel> for DtoUser {
         DtoUser {
             _id: user_model._id,
             username: user_model.username,
+            display_name: user_model.display_name,
             email: user_model.email,
+            roles: user_model.roles,
+            state: user_model.state,
+            totp_enabled: user_model.totp.is_some_and(|totp| totp.confirmed),
             created_at: user_model.created_at.into(),
             updated_at: user_model.updated_at.into(),
         }
@@ -51,6 +244,112 @@ impl From<UserModel> for DtoUser {
 
 #[cfg(test)]
 mod model_tests {
+    use super::*;
+
+    fn dto_user(roles: Vec<Role>) -> DtoUser {
+        DtoUser {
+            _id: ObjectId::new(),
+            username: "jorkridesher".to_string(),
+            display_name: Some("Jork".to_string()),
+            email: "jork@example.com".to_string(),
+            roles,
+            state: AccountState::Active,
+            totp_enabled: false,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn roles_include_lower_roles() {
+        let admin = dto_user(vec![Role::Admin]);
+        let moderator = dto_user(vec![Role::Moderator]);
+        let user = dto_user(vec![Role::User]);
+
+        assert!(admin.has_role(Role::Moderator));
+        assert!(moderator.has_role(Role::User));
+        assert!(!moderator.has_role(Role::Admin));
+        assert!(!user.has_role(Role::Moderator));
+        assert!(!dto_user(vec![]).has_role(Role::User));
+    }
+
+    #[test]
+    fn legacy_user_without_roles_is_user() {
+        let legacy = mongodb::bson::doc! {
+            "_id": ObjectId::new(),
+            "username": "jorkridesher",
+            "password": "admin",
+            "email": "jork@example.com",
+            "created_at": mongodb::bson::DateTime::now(),
+            "updated_at": mongodb::bson::DateTime::now(),
+        };
+
+        let user: UserModel = mongodb::bson::from_document(legacy).unwrap();
+        assert_eq!(vec![Role::User], user.roles);
+        assert_eq!(AccountState::Active, user.state);
+    }
+
+    #[test]
+    fn public_profile_hides_private_fields() {
+        let profile =
+            serde_json::to_value(DtoPublicProfile::from(dto_user(vec![Role::User]))).unwrap();
+
+        assert_eq!("jorkridesher", profile["username"]);
+        assert!(profile.get("email").is_none());
+        assert!(profile.get("id").is_none());
+    }
+
+    #[test]
+    fn private_profile_never_has_password() {
+        let user = dto_user(vec![Role::Admin]);
+        let id = user._id.to_hex();
+        let profile = serde_json::to_value(DtoPrivateProfile::from(user)).unwrap();
+
+        assert_eq!(id, profile["id"]);
+        assert_eq!("jork@example.com", profile["email"]);
+        assert!(profile.get("password").is_none());
+    }
+
+    #[test]
+    fn create_form_is_validated() {
+        let form = DtoUserCreate {
+            username: "jork rides her".to_string(),
+            password: "short".to_string(),
+            email: "jork".to_string(),
+        };
+
+        let errors = form.validate().unwrap_err();
+        let fields = errors.field_errors();
+        assert_eq!("username_chars", fields["username"][0].code);
+        assert_eq!("length", fields["password"][0].code);
+        assert_eq!("email", fields["email"][0].code);
+    }
+
+    #[test]
+    fn update_form_is_validated() {
+        let form = DtoUserUpdate {
+            email: Some("not an address".to_string()),
+            current_password: Some("hunter2".to_string()),
+            display_name: Some("x".repeat(DISPLAY_NAME_MAX_CHARS as usize + 1)),
+        };
+
+        let errors = form.validate().unwrap_err();
+        let fields = errors.field_errors();
+        assert_eq!("email", fields["email"][0].code);
+        assert_eq!("length", fields["display_name"][0].code);
+    }
+
+    #[test]
+    fn valid_create_form_passes() {
+        let form = DtoUserCreate {
+            username: "jork.rides_her-2".to_string(),
+            password: "correct horse battery".to_string(),
+            email: "jork@example.com".to_string(),
+        };
+
+        assert!(form.validate().is_ok());
+    }
+
     #[test]
     fn mongo_to_chrono_datetime() {
         let mongo_dt = mongodb::bson::DateTime::now();
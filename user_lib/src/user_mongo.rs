@@ -0,0 +1,664 @@
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use error_lib::api_error::ApiError;
+use mongodb::{
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    Collection, Cursor,
+};
+use serde::de::DeserializeOwned;
+use tokio_stream::StreamExt;
+
+use crate::{
+    user_api_key::ApiKeyModel,
+    user_lockout::{LockoutKind, LoginAttemptModel},
+    user_models::{Role, UserModel},
+    user_password_reset::PasswordResetModel,
+    user_repository::{
+        ApiKeyRepository, LoginAttemptRepository, PasswordResetRepository, SessionRepository,
+        UserChanges, UserRepository,
+    },
+    user_session::{RefreshTokenModel, RevokedTokenModel},
+    user_totp::UserTotpModel,
+};
+
+/// Documents that fail to deserialize are logged and skipped, rather than failing the request.
+async fn collect<T: DeserializeOwned + Unpin + Send + Sync>(mut cursor: Cursor<T>) -> Vec<T> {
+    let mut items = Vec::new();
+    while let Some(item) = cursor.next().await {
+        match item {
+            Ok(item) => items.push(item),
+            Err(err) => {
+                tracing::warn!("error occured during mongo cursor iteration: {err}")
+            }
+        }
+    }
+    items
+}
+
+fn return_after() -> FindOneAndUpdateOptions {
+    FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build()
+}
+
+/// Turns `changes` into the fields to `$set`.
+fn user_changes_doc(changes: &UserChanges, now: DateTime) -> Document {
+    let mut set = doc! { "updated_at": now };
+
+    if let Some(pending_email) = &changes.pending_email {
+        set.insert("pending_email", &pending_email.email);
+        set.insert("verification_nonce", &pending_email.verification_nonce);
+    }
+    match &changes.display_name {
+        Some(Some(display_name)) => {
+            set.insert("display_name", display_name);
+        }
+        Some(None) => {
+            set.insert("display_name", Bson::Null);
+        }
+        None => {}
+    }
+    set
+}
+
+pub struct MongoUserRepository {
+    collection: Collection<UserModel>,
+}
+
+impl MongoUserRepository {
+    pub fn new(collection: Collection<UserModel>) -> MongoUserRepository {
+        MongoUserRepository { collection }
+    }
+}
+
+#[async_trait]
+impl UserRepository for MongoUserRepository {
+    async fn insert(&self, user: &UserModel) -> Result<(), ApiError> {
+        self.collection.insert_one(user, None).await?;
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<UserModel>, ApiError> {
+        Ok(self.collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<UserModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one(doc! { "username": username }, None)
+            .await?)
+    }
+
+    async fn set_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "password": password, "updated_at": now } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn set_roles(
+        &self,
+        username: &str,
+        roles: &[Role],
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "username": username },
+                doc! { "$set": {
+                    "roles": bson::to_bson(roles)?,
+                    "updated_at": now,
+                } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn verify_email(
+        &self,
+        username: &str,
+        nonce: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    "username": username,
+                    "verification_nonce": nonce,
+                    "$or": [
+                        { "state": "unverified" },
+                        { "pending_email": { "$type": "string" } },
+                    ],
+                },
+                // A pipeline, so the pending email can take the place of the current one.
+                vec![doc! { "$set": {
+                    "state": "active",
+                    "email": { "$ifNull": ["$pending_email", "$email"] },
+                    "pending_email": null,
+                    "verification_nonce": null,
+                    "updated_at": now,
+                } }],
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn renew_verification_nonce(
+        &self,
+        username: &str,
+        nonce: &str,
+    ) -> Result<Option<UserModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! { "username": username, "state": "unverified" },
+                doc! { "$set": { "verification_nonce": nonce } },
+                return_after(),
+            )
+            .await?)
+    }
+
+    async fn reset_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! { "_id": id },
+                doc! { "$set": {
+                    "password": password,
+                    "state": "active",
+                    "verification_nonce": null,
+                    "updated_at": now,
+                } },
+                return_after(),
+            )
+            .await?)
+    }
+
+    async fn update_profile(
+        &self,
+        id: ObjectId,
+        changes: &UserChanges,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! { "_id": id },
+                doc! { "$set": user_changes_doc(changes, now) },
+                return_after(),
+            )
+            .await?)
+    }
+
+    async fn begin_totp(&self, id: ObjectId, totp: &UserTotpModel) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id, "totp.confirmed": { "$ne": true } },
+                doc! { "$set": { "totp": bson::to_bson(totp)? } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn confirm_totp(
+        &self,
+        id: ObjectId,
+        secret: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id, "totp.secret": secret, "totp.confirmed": false },
+                doc! { "$set": {
+                    "totp.confirmed": true,
+                    "totp.last_used_step": step,
+                    "totp.recovery_code_hashes": recovery_code_hashes,
+                } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn use_totp_step(&self, id: ObjectId, step: i64) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! {
+                    "_id": id,
+                    "$or": [
+                        { "totp.last_used_step": null },
+                        { "totp.last_used_step": { "$lt": step } },
+                    ],
+                },
+                doc! { "$set": { "totp.last_used_step": step } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn use_recovery_code(&self, id: ObjectId, code_hash: &str) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id, "totp.recovery_code_hashes": code_hash },
+                doc! { "$pull": { "totp.recovery_code_hashes": code_hash } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn clear_totp(&self, id: ObjectId) -> Result<(), ApiError> {
+        self.collection
+            .update_one(doc! { "_id": id }, doc! { "$unset": { "totp": "" } }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let result = self.collection.delete_one(doc! { "_id": id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+}
+
+pub struct MongoSessionRepository {
+    refresh_tokens: Collection<RefreshTokenModel>,
+    revoked_tokens: Collection<RevokedTokenModel>,
+}
+
+impl MongoSessionRepository {
+    pub fn new(
+        refresh_tokens: Collection<RefreshTokenModel>,
+        revoked_tokens: Collection<RevokedTokenModel>,
+    ) -> MongoSessionRepository {
+        MongoSessionRepository {
+            refresh_tokens,
+            revoked_tokens,
+        }
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MongoSessionRepository {
+    async fn insert_refresh_token(
+        &self,
+        refresh_token: &RefreshTokenModel,
+    ) -> Result<(), ApiError> {
+        self.refresh_tokens.insert_one(refresh_token, None).await?;
+        Ok(())
+    }
+
+    async fn find_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshTokenModel>, ApiError> {
+        Ok(self
+            .refresh_tokens
+            .find_one(doc! { "token_hash": token_hash }, None)
+            .await?)
+    }
+
+    async fn claim_refresh_token(&self, id: ObjectId, now: DateTime) -> Result<bool, ApiError> {
+        let claimed = self
+            .refresh_tokens
+            .find_one_and_update(
+                doc! { "_id": id, "used_at": null, "revoked": false },
+                doc! { "$set": { "used_at": now } },
+                None,
+            )
+            .await?;
+        Ok(claimed.is_some())
+    }
+
+    async fn list_active(
+        &self,
+        user_id: ObjectId,
+        now: DateTime,
+    ) -> Result<Vec<RefreshTokenModel>, ApiError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .build();
+        let cursor = self
+            .refresh_tokens
+            .find(
+                doc! {
+                    "user_id": user_id,
+                    "used_at": null,
+                    "revoked": false,
+                    "expires_at": { "$gt": now },
+                },
+                options,
+            )
+            .await?;
+        Ok(collect(cursor).await)
+    }
+
+    async fn revoke_family(&self, user_id: ObjectId, family_id: &str) -> Result<bool, ApiError> {
+        let result = self
+            .refresh_tokens
+            .update_many(
+                doc! { "family_id": family_id, "user_id": user_id },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn revoke_all(&self, user_id: ObjectId) -> Result<Vec<String>, ApiError> {
+        let family_ids = self
+            .refresh_tokens
+            .distinct(
+                "family_id",
+                doc! { "user_id": user_id, "revoked": false },
+                None,
+            )
+            .await?;
+
+        self.refresh_tokens
+            .update_many(
+                doc! { "user_id": user_id },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+
+        Ok(family_ids
+            .iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .collect())
+    }
+
+    async fn insert_revoked(&self, revoked: &RevokedTokenModel) -> Result<(), ApiError> {
+        self.revoked_tokens.insert_one(revoked, None).await?;
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        session_id: Option<&str>,
+    ) -> Result<bool, ApiError> {
+        let mut filters = Vec::new();
+        if let Some(jti) = jti {
+            filters.push(doc! { "jti": jti });
+        }
+        if let Some(session_id) = session_id {
+            filters.push(doc! { "session_id": session_id });
+        }
+        if filters.is_empty() {
+            return Ok(false);
+        }
+
+        let revoked = self
+            .revoked_tokens
+            .find_one(doc! { "$or": filters }, None)
+            .await?;
+        Ok(revoked.is_some())
+    }
+}
+
+pub struct MongoApiKeyRepository {
+    collection: Collection<ApiKeyModel>,
+}
+
+impl MongoApiKeyRepository {
+    pub fn new(collection: Collection<ApiKeyModel>) -> MongoApiKeyRepository {
+        MongoApiKeyRepository { collection }
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MongoApiKeyRepository {
+    async fn insert(&self, api_key: &ApiKeyModel) -> Result<(), ApiError> {
+        self.collection.insert_one(api_key, None).await?;
+        Ok(())
+    }
+
+    async fn list_active(&self, user_id: ObjectId) -> Result<Vec<ApiKeyModel>, ApiError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": -1 })
+            .build();
+        let cursor = self
+            .collection
+            .find(doc! { "user_id": user_id, "revoked_at": null }, options)
+            .await?;
+        Ok(collect(cursor).await)
+    }
+
+    async fn revoke(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "_id": id, "user_id": user_id, "revoked_at": null },
+                doc! { "$set": { "revoked_at": now } },
+                None,
+            )
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        self.collection
+            .delete_many(doc! { "user_id": user_id }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn authenticate(
+        &self,
+        key_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<ApiKeyModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! { "key_hash": key_hash, "revoked_at": null },
+                doc! { "$set": { "last_used_at": now } },
+                return_after(),
+            )
+            .await?)
+    }
+}
+
+pub struct MongoLoginAttemptRepository {
+    collection: Collection<LoginAttemptModel>,
+}
+
+impl MongoLoginAttemptRepository {
+    pub fn new(collection: Collection<LoginAttemptModel>) -> MongoLoginAttemptRepository {
+        MongoLoginAttemptRepository { collection }
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for MongoLoginAttemptRepository {
+    async fn find_locked(
+        &self,
+        subjects: &[(LockoutKind, &str)],
+        now: DateTime,
+    ) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        let filters: Vec<_> = subjects
+            .iter()
+            .map(|(kind, subject)| doc! { "kind": kind.as_str(), "subject": *subject })
+            .collect();
+
+        let cursor = self
+            .collection
+            .find(
+                doc! {
+                    "$or": filters,
+                    "locked_until": { "$gt": now },
+                },
+                None,
+            )
+            .await?;
+        Ok(collect(cursor).await)
+    }
+
+    async fn record_failure(
+        &self,
+        kind: LockoutKind,
+        subject: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<LoginAttemptModel, ApiError> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        self.collection
+            .find_one_and_update(
+                doc! { "kind": kind.as_str(), "subject": subject },
+                doc! {
+                    "$inc": { "failures": 1 },
+                    "$set": {
+                        "last_failure_at": now,
+                        "expires_at": expires_at,
+                    },
+                },
+                options,
+            )
+            .await?
+            .ok_or_else(|| ApiError::internal(anyhow::anyhow!("upsert returned no document")))
+    }
+
+    async fn lock(&self, id: ObjectId, locked_until: DateTime) -> Result<(), ApiError> {
+        self.collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "locked_until": locked_until } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, kind: LockoutKind, subject: &str) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .delete_one(doc! { "kind": kind.as_str(), "subject": subject }, None)
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn list(&self) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "last_failure_at": -1 })
+            .build();
+        let cursor = self.collection.find(None, options).await?;
+        Ok(collect(cursor).await)
+    }
+}
+
+pub struct MongoPasswordResetRepository {
+    collection: Collection<PasswordResetModel>,
+}
+
+impl MongoPasswordResetRepository {
+    pub fn new(collection: Collection<PasswordResetModel>) -> MongoPasswordResetRepository {
+        MongoPasswordResetRepository { collection }
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for MongoPasswordResetRepository {
+    async fn replace_unused(&self, reset: &PasswordResetModel) -> Result<(), ApiError> {
+        self.collection
+            .delete_many(doc! { "user_id": reset.user_id, "used_at": null }, None)
+            .await?;
+        self.collection.insert_one(reset, None).await?;
+        Ok(())
+    }
+
+    async fn consume(
+        &self,
+        token_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<PasswordResetModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "token_hash": token_hash,
+                    "used_at": null,
+                    "expires_at": { "$gt": now },
+                },
+                doc! { "$set": { "used_at": now } },
+                None,
+            )
+            .await?)
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        self.collection
+            .delete_many(doc! { "user_id": user_id }, None)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod user_mongo_tests {
+    use super::*;
+
+    #[test]
+    fn changes_set_only_given_fields() {
+        let now = DateTime::now();
+        let set = user_changes_doc(
+            &UserChanges {
+                pending_email: None,
+                display_name: Some(Some("Jork".to_string())),
+            },
+            now,
+        );
+
+        assert_eq!("Jork", set.get_str("display_name").unwrap());
+        assert!(!set.contains_key("pending_email"));
+        assert_eq!(Some(&Bson::DateTime(now)), set.get("updated_at"));
+    }
+
+    #[test]
+    fn changes_clear_display_name() {
+        let set = user_changes_doc(
+            &UserChanges {
+                pending_email: None,
+                display_name: Some(None),
+            },
+            DateTime::now(),
+        );
+
+        assert_eq!(Some(&Bson::Null), set.get("display_name"));
+    }
+}
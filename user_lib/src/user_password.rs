@@ -0,0 +1,220 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use std::sync::OnceLock;
+
+use error_lib::api_error::ApiError;
+use subtle::ConstantTimeEq;
+
+/// Cost parameters used when hashing passwords with Argon2id.
+///
+/// These are read from the environment by `config_lib`, so that the cost can be tuned for the
+/// hardware the service is deployed on without a rebuild.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasswordParams {
+    /// Memory cost in KiB.
+    pub memory_kib: u32,
+    /// Number of passes over the memory.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for PasswordParams {
+    fn default() -> Self {
+        PasswordParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl PasswordParams {
+    fn hasher(&self) -> Result<Argon2<'static>, ApiError> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|err| {
+                ApiError::internal(anyhow::anyhow!("invalid argon2 params {self:?}: {err}"))
+            })?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn matches(&self, hash: &PasswordHash) -> bool {
+        match Params::try_from(hash) {
+            Ok(params) => {
+                hash.algorithm == Algorithm::Argon2id.ident()
+                    && params.m_cost() == self.memory_kib
+                    && params.t_cost() == self.iterations
+                    && params.p_cost() == self.parallelism
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Outcome of checking a password against what is stored for a user.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordVerification {
+    /// The password is wrong.
+    Invalid,
+    /// The password is correct and the stored hash is up to date.
+    Valid,
+    /// The password is correct, but the stored value is either a legacy plaintext password or a
+    /// hash made with different cost parameters, and should be replaced.
+    ValidNeedsRehash,
+}
+
+/// Runs `work` on the blocking thread pool. Argon2 is slow on purpose, and would otherwise hold
+/// up every other request on the same executor thread.
+async fn off_executor<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(ApiError::internal)
+}
+
+/// Hash a password with Argon2id using a freshly generated salt.
+///
+/// The result is a PHC string (`$argon2id$v=19$m=...`) that carries its own salt and params.
+pub async fn hash_password(password: &str, params: &PasswordParams) -> Result<String, ApiError> {
+    let (password, params) = (password.to_string(), *params);
+    off_executor(move || hash_sync(&password, &params)).await?
+}
+
+fn hash_sync(password: &str, params: &PasswordParams) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    params
+        .hasher()?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| ApiError::internal(anyhow::anyhow!("failed to hash password: {err}")))
+}
+
+/// Check a password against the value stored on a `UserModel`.
+///
+/// Values that do not parse as a PHC string are treated as legacy plaintext passwords, and are
+/// compared in constant time.
+pub async fn verify_password(
+    password: &str,
+    stored: &str,
+    params: &PasswordParams,
+) -> Result<PasswordVerification, ApiError> {
+    let (password, stored, params) = (password.to_string(), stored.to_string(), *params);
+    off_executor(move || verify_sync(&password, &stored, &params)).await
+}
+
+fn verify_sync(password: &str, stored: &str, params: &PasswordParams) -> PasswordVerification {
+    let hash = match PasswordHash::new(stored) {
+        Ok(hash) => hash,
+        Err(_) => {
+            return if bool::from(password.as_bytes().ct_eq(stored.as_bytes())) {
+                PasswordVerification::ValidNeedsRehash
+            } else {
+                PasswordVerification::Invalid
+            };
+        }
+    };
+
+    // The verifier takes the algorithm and params from the stored hash, not from `params`.
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return PasswordVerification::Invalid;
+    }
+
+    if params.matches(&hash) {
+        PasswordVerification::Valid
+    } else {
+        PasswordVerification::ValidNeedsRehash
+    }
+}
+
+/// Does the same work as checking a password against a stored hash, for when there is no user.
+///
+/// Without this a login for a username that does not exist returns noticeably faster, which
+/// tells an attacker which usernames are taken.
+pub async fn verify_dummy_password(password: &str, params: &PasswordParams) {
+    static DUMMY_HASH: OnceLock<Option<String>> = OnceLock::new();
+
+    let (password, params) = (password.to_string(), *params);
+    let _ = off_executor(move || {
+        if let Some(dummy_hash) =
+            DUMMY_HASH.get_or_init(|| hash_sync("not a real password", &params).ok())
+        {
+            verify_sync(&password, dummy_hash, &params);
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod password_tests {
+    use super::*;
+
+    // Keep the tests fast, the defaults are intentionally slow.
+    const TEST_PARAMS: PasswordParams = PasswordParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[tokio::test]
+    async fn hash_then_verify() {
+        let hash = hash_password("hunter2", &TEST_PARAMS).await.unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(
+            PasswordVerification::Valid,
+            verify_password("hunter2", &hash, &TEST_PARAMS)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            PasswordVerification::Invalid,
+            verify_password("hunter3", &hash, &TEST_PARAMS)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn salts_are_unique() {
+        let first = hash_password("hunter2", &TEST_PARAMS).await.unwrap();
+        let second = hash_password("hunter2", &TEST_PARAMS).await.unwrap();
+
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn legacy_plaintext_needs_rehash() {
+        assert_eq!(
+            PasswordVerification::ValidNeedsRehash,
+            verify_password("admin", "admin", &TEST_PARAMS)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            PasswordVerification::Invalid,
+            verify_password("admin", "admin2", &TEST_PARAMS)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn changed_params_need_rehash() {
+        let hash = hash_password("hunter2", &TEST_PARAMS).await.unwrap();
+        let stronger = PasswordParams {
+            iterations: 2,
+            ..TEST_PARAMS
+        };
+
+        assert_eq!(
+            PasswordVerification::ValidNeedsRehash,
+            verify_password("hunter2", &hash, &stronger).await.unwrap()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use bson::oid::ObjectId;
+use error_lib::api_error::ApiError;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    user_models::{PASSWORD_MAX_CHARS, PASSWORD_MIN_CHARS, USERNAME_MAX_CHARS},
+    user_repository::PasswordResetRepository,
+    user_session::{generate_token, hash_token, TOKEN_MAX_CHARS},
+};
+
+/// A password reset token as it is stored in the database. Only the hash of the token is kept.
+///
+/// A TTL index on `expires_at` removes tokens once they can no longer be used.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PasswordResetModel {
+    pub _id: ObjectId,
+    pub token_hash: String,
+    pub user_id: ObjectId,
+    pub created_at: mongodb::bson::DateTime,
+    pub expires_at: mongodb::bson::DateTime,
+    pub used_at: Option<mongodb::bson::DateTime>,
+}
+
+/// What is required to ask for a password reset.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoPasswordForgot {
+    #[validate(length(min = 1, max = "USERNAME_MAX_CHARS"))]
+    pub username: String,
+}
+
+/// What is required to reset a password.
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DtoPasswordReset {
+    #[validate(length(min = 1, max = "TOKEN_MAX_CHARS"))]
+    pub token: String,
+    #[validate(length(min = "PASSWORD_MIN_CHARS", max = "PASSWORD_MAX_CHARS"))]
+    pub password: String,
+}
+
+fn new_password_reset(user_id: ObjectId, ttl: Duration) -> (String, PasswordResetModel) {
+    let (token, token_hash) = generate_token();
+    let now = chrono::Utc::now();
+    let model = PasswordResetModel {
+        _id: ObjectId::new(),
+        token_hash,
+        user_id,
+        created_at: now.into(),
+        expires_at: (now + chrono::Duration::seconds(ttl.as_secs() as i64)).into(),
+        used_at: None,
+    };
+    (token, model)
+}
+
+/// Creates a reset token for `user_id`, returning the token to mail to them.
+///
+/// Any earlier token that has not been used yet stops working, so only the latest mail counts.
+pub async fn create_password_reset(
+    resets: &dyn PasswordResetRepository,
+    user_id: ObjectId,
+    ttl: Duration,
+) -> Result<String, ApiError> {
+    let (token, model) = new_password_reset(user_id, ttl);
+    resets.replace_unused(&model).await?;
+
+    Ok(token)
+}
+
+/// Marks a reset token used, returning the id of the user it was issued for.
+///
+/// Returns `BadRequest` for unknown, expired and already used tokens.
+pub async fn consume_password_reset(
+    resets: &dyn PasswordResetRepository,
+    presented_token: &str,
+) -> Result<ObjectId, ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+
+    // Consuming is conditional, so two concurrent resets with the same token cannot both succeed.
+    let reset = resets
+        .consume(&hash_token(presented_token), now)
+        .await?
+        .ok_or_else(|| ApiError::BadRequest("this reset link is no longer valid".to_string()))?;
+
+    Ok(reset.user_id)
+}
+
+/// Deletes every reset token of a user, for when their account is deleted.
+pub async fn delete_password_resets(
+    resets: &dyn PasswordResetRepository,
+    user_id: ObjectId,
+) -> Result<(), ApiError> {
+    resets.delete_all(user_id).await
+}
+
+#[cfg(test)]
+mod password_reset_tests {
+    use super::*;
+
+    #[test]
+    fn reset_stores_only_hash() {
+        let (token, model) = new_password_reset(ObjectId::new(), Duration::from_secs(60));
+
+        assert_ne!(token, model.token_hash);
+        assert_eq!(hash_token(&token), model.token_hash);
+        assert!(model.used_at.is_none());
+    }
+
+    #[test]
+    fn reset_expires_after_ttl() {
+        let (_, model) = new_password_reset(ObjectId::new(), Duration::from_secs(60 * 30));
+
+        assert_eq!(
+            60 * 30 * 1000,
+            model.expires_at.timestamp_millis() - model.created_at.timestamp_millis()
+        );
+    }
+}
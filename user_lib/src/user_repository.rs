@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use error_lib::api_error::ApiError;
+
+use crate::{
+    user_api_key::ApiKeyModel,
+    user_lockout::{LockoutKind, LoginAttemptModel},
+    user_models::{Role, UserModel},
+    user_password_reset::PasswordResetModel,
+    user_session::{RefreshTokenModel, RevokedTokenModel},
+    user_totp::UserTotpModel,
+};
+
+// The handlers and `*_logic` functions only ever talk to storage through these traits, so the
+// backend can be swapped, or replaced with a fake in tests. Every method that changes something
+// does so in a single atomic step, callers rely on that to keep concurrent requests apart.
+
+/// The changes a user makes to their own profile, already checked by `user_logic`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserChanges {
+    /// A new address is only kept aside, `email` changes once the link sent to it is followed.
+    pub pending_email: Option<PendingEmail>,
+    /// `Some(None)` removes the display name.
+    pub display_name: Option<Option<String>>,
+}
+
+/// An address a user changed to, with the nonce of the link that verifies it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingEmail {
+    pub email: String,
+    pub verification_nonce: String,
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// A username that is taken is a `409 Conflict`.
+    async fn insert(&self, user: &UserModel) -> Result<(), ApiError>;
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<UserModel>, ApiError>;
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<UserModel>, ApiError>;
+
+    /// Replaces the stored password hash, returning whether the user exists.
+    async fn set_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError>;
+
+    /// Returns whether the user exists.
+    async fn set_roles(
+        &self,
+        username: &str,
+        roles: &[Role],
+        now: DateTime,
+    ) -> Result<bool, ApiError>;
+
+    /// Activates an unverified user, or makes the pending email of an active one their email, if
+    /// `nonce` is their current verification nonce.
+    async fn verify_email(
+        &self,
+        username: &str,
+        nonce: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError>;
+
+    /// Stores a new verification nonce for an unverified user, returning the updated user.
+    async fn renew_verification_nonce(
+        &self,
+        username: &str,
+        nonce: &str,
+    ) -> Result<Option<UserModel>, ApiError>;
+
+    /// Replaces the password and activates the user, returning the updated user.
+    async fn reset_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError>;
+
+    /// Returns the updated user.
+    async fn update_profile(
+        &self,
+        id: ObjectId,
+        changes: &UserChanges,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError>;
+
+    /// Stores a second factor that still has to be confirmed, unless the user already has a
+    /// confirmed one. Returns whether it was stored.
+    async fn begin_totp(&self, id: ObjectId, totp: &UserTotpModel) -> Result<bool, ApiError>;
+
+    /// Confirms the pending second factor, if its secret is still `secret`.
+    async fn confirm_totp(
+        &self,
+        id: ObjectId,
+        secret: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, ApiError>;
+
+    /// Records `step` as used, unless it or a later one already was.
+    async fn use_totp_step(&self, id: ObjectId, step: i64) -> Result<bool, ApiError>;
+
+    /// Removes a recovery code, returning whether the user had it.
+    async fn use_recovery_code(&self, id: ObjectId, code_hash: &str) -> Result<bool, ApiError>;
+
+    async fn clear_totp(&self, id: ObjectId) -> Result<(), ApiError>;
+
+    /// Returns whether the user existed.
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError>;
+}
+
+/// Refresh tokens, grouped into sessions by their `family_id`, and revoked access tokens.
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn insert_refresh_token(&self, refresh_token: &RefreshTokenModel)
+        -> Result<(), ApiError>;
+
+    async fn find_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshTokenModel>, ApiError>;
+
+    /// Marks a refresh token as used, unless it already was or has been revoked. Returns whether
+    /// this call was the one to use it.
+    async fn claim_refresh_token(&self, id: ObjectId, now: DateTime) -> Result<bool, ApiError>;
+
+    /// The latest, unused refresh token of every session that has not ended, newest first.
+    async fn list_active(
+        &self,
+        user_id: ObjectId,
+        now: DateTime,
+    ) -> Result<Vec<RefreshTokenModel>, ApiError>;
+
+    /// Revokes every refresh token of a session, returning whether the session exists.
+    async fn revoke_family(&self, user_id: ObjectId, family_id: &str) -> Result<bool, ApiError>;
+
+    /// Revokes every refresh token of a user, returning the sessions that were still going.
+    async fn revoke_all(&self, user_id: ObjectId) -> Result<Vec<String>, ApiError>;
+
+    async fn insert_revoked(&self, revoked: &RevokedTokenModel) -> Result<(), ApiError>;
+
+    /// Whether an access token with either `jti` or `session_id` has been revoked.
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        session_id: Option<&str>,
+    ) -> Result<bool, ApiError>;
+}
+
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn insert(&self, api_key: &ApiKeyModel) -> Result<(), ApiError>;
+
+    /// The keys of a user that have not been revoked, newest first.
+    async fn list_active(&self, user_id: ObjectId) -> Result<Vec<ApiKeyModel>, ApiError>;
+
+    /// Returns whether the user had an active key with that id.
+    async fn revoke(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        now: DateTime,
+    ) -> Result<bool, ApiError>;
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError>;
+
+    /// Finds an active key by its hash, recording that it was used.
+    async fn authenticate(
+        &self,
+        key_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<ApiKeyModel>, ApiError>;
+}
+
+/// Failed logins, counted per username and per IP.
+#[async_trait]
+pub trait LoginAttemptRepository: Send + Sync {
+    /// The attempts of `subjects` that are locked out beyond `now`.
+    async fn find_locked(
+        &self,
+        subjects: &[(LockoutKind, &str)],
+        now: DateTime,
+    ) -> Result<Vec<LoginAttemptModel>, ApiError>;
+
+    /// Counts another failure, creating the attempt if there is none yet, or starting it over if
+    /// it expired by `now`. Returns the attempt with the new count.
+    async fn record_failure(
+        &self,
+        kind: LockoutKind,
+        subject: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<LoginAttemptModel, ApiError>;
+
+    async fn lock(&self, id: ObjectId, locked_until: DateTime) -> Result<(), ApiError>;
+
+    /// Returns whether there was an attempt to delete.
+    async fn delete(&self, kind: LockoutKind, subject: &str) -> Result<bool, ApiError>;
+
+    /// Every attempt, most recent failure first.
+    async fn list(&self) -> Result<Vec<LoginAttemptModel>, ApiError>;
+}
+
+#[async_trait]
+pub trait PasswordResetRepository: Send + Sync {
+    /// Stores `reset`, dropping any earlier reset of the same user that was not used.
+    async fn replace_unused(&self, reset: &PasswordResetModel) -> Result<(), ApiError>;
+
+    /// Marks an unused, unexpired reset as used, returning it if this call was the one to use it.
+    async fn consume(
+        &self,
+        token_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<PasswordResetModel>, ApiError>;
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError>;
+}
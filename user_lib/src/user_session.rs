@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bson::oid::ObjectId;
+use error_lib::api_error::ApiError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{user_models::UserModel, user_repository::SessionRepository};
+
+/// A refresh token as it is stored in the database.
+///
+/// Only the hash of the token is stored. Every refresh token belongs to a family, which is
+/// created at login and shared by all the tokens it is rotated into. The family is what we
+/// consider a session.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RefreshTokenModel {
+    pub _id: ObjectId,
+    pub token_hash: String,
+    pub family_id: String,
+    pub user_id: ObjectId,
+    pub created_at: mongodb::bson::DateTime,
+    pub expires_at: mongodb::bson::DateTime,
+    /// Set once the token has been exchanged for a new one. Presenting it again is a replay.
+    pub used_at: Option<mongodb::bson::DateTime>,
+    pub revoked: bool,
+}
+
+/// An access token, or every access token of a session, that must no longer be accepted.
+///
+/// Entries only need to live as long as the tokens they cover, after that a TTL index on
+/// `expires_at` removes them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RevokedTokenModel {
+    pub _id: ObjectId,
+    pub jti: Option<String>,
+    pub session_id: Option<String>,
+    pub user_id: ObjectId,
+    pub revoked_at: mongodb::bson::DateTime,
+    pub expires_at: mongodb::bson::DateTime,
+}
+
+/// A session as shown to the user that owns it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DtoSession {
+    pub session_id: String,
+    pub last_refreshed_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+}
+
+impl From<RefreshTokenModel> for DtoSession {
+    fn from(refresh_token: RefreshTokenModel) -> Self {
+        DtoSession {
+            session_id: refresh_token.family_id,
+            last_refreshed_at: refresh_token.created_at.into(),
+            expires_at: refresh_token.expires_at.into(),
+            current: false,
+        }
+    }
+}
+
+/// Tokens from [`generate_token`] are 43 characters, anything much longer cannot be one.
+pub const TOKEN_MAX_CHARS: u64 = 64;
+
+/// Generates a new random token, returning it along with the hash that should be stored.
+pub fn generate_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let token_hash = hash_token(&token);
+    (token, token_hash)
+}
+
+/// Tokens are high entropy, so a plain digest is enough to keep them useless if the database leaks.
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+fn new_refresh_token(
+    family_id: String,
+    user_id: ObjectId,
+    ttl: Duration,
+) -> (String, RefreshTokenModel) {
+    let (token, token_hash) = generate_token();
+    let now = chrono::Utc::now();
+    let model = RefreshTokenModel {
+        _id: ObjectId::new(),
+        token_hash,
+        family_id,
+        user_id,
+        created_at: now.into(),
+        expires_at: (now + chrono::Duration::seconds(ttl.as_secs() as i64)).into(),
+        used_at: None,
+        revoked: false,
+    };
+    (token, model)
+}
+
+/// Starts a new session for `user`, returning the refresh token to hand to the client.
+pub async fn create_session(
+    sessions: &dyn SessionRepository,
+    user: &UserModel,
+    ttl: Duration,
+) -> Result<(String, RefreshTokenModel), ApiError> {
+    let (token, model) = new_refresh_token(ObjectId::new().to_hex(), user._id, ttl);
+
+    sessions.insert_refresh_token(&model).await?;
+
+    Ok((token, model))
+}
+
+/// Exchanges a refresh token for a new one in the same family.
+///
+/// A token can only be exchanged once. If an already used token is presented, it has most likely
+/// been stolen, so the whole family is revoked and both the thief and the real user have to log
+/// in again.
+pub async fn rotate_refresh_token(
+    sessions: &dyn SessionRepository,
+    presented_token: &str,
+    ttl: Duration,
+    access_token_ttl: Duration,
+) -> Result<(String, RefreshTokenModel), ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+
+    let current = sessions
+        .find_refresh_token(&hash_token(presented_token))
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+
+    if current.revoked || current.expires_at < now {
+        return Err(ApiError::Unauthorized);
+    }
+
+    // Claiming the token is conditional, so two concurrent refreshes cannot both succeed.
+    if !sessions.claim_refresh_token(current._id, now).await? {
+        tracing::warn!(
+            current.family_id,
+            "refresh token reuse detected, revoking token family"
+        );
+        end_session(
+            sessions,
+            current.user_id,
+            &current.family_id,
+            access_token_ttl,
+        )
+        .await?;
+        return Err(ApiError::Unauthorized);
+    }
+
+    let (token, model) = new_refresh_token(current.family_id, current.user_id, ttl);
+    sessions.insert_refresh_token(&model).await?;
+
+    Ok((token, model))
+}
+
+/// Lists the sessions of a user that can still be refreshed, most recently used first.
+pub async fn list_sessions(
+    sessions: &dyn SessionRepository,
+    user_id: ObjectId,
+) -> Result<Vec<DtoSession>, ApiError> {
+    let now: mongodb::bson::DateTime = chrono::Utc::now().into();
+
+    // Only the newest token of a family is unused, so this yields one token per session.
+    let active = sessions.list_active(user_id, now).await?;
+
+    Ok(active.into_iter().map(DtoSession::from).collect())
+}
+
+/// Ends a session, so neither its refresh token nor any access token issued for it works anymore.
+///
+/// Returns `NotFound` if `user_id` has no such session.
+pub async fn end_session(
+    sessions: &dyn SessionRepository,
+    user_id: ObjectId,
+    session_id: &str,
+    access_token_ttl: Duration,
+) -> Result<(), ApiError> {
+    if !sessions.revoke_family(user_id, session_id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    revoke_session_access(sessions, user_id, session_id, access_token_ttl).await
+}
+
+/// Ends every session of a user, returning how many sessions were ended.
+pub async fn end_all_sessions(
+    sessions: &dyn SessionRepository,
+    user_id: ObjectId,
+    access_token_ttl: Duration,
+) -> Result<usize, ApiError> {
+    let session_ids = sessions.revoke_all(user_id).await?;
+
+    for session_id in &session_ids {
+        revoke_session_access(sessions, user_id, session_id, access_token_ttl).await?;
+    }
+
+    Ok(session_ids.len())
+}
+
+/// Revokes a single access token until it would have expired anyway.
+pub async fn revoke_access_token(
+    sessions: &dyn SessionRepository,
+    user_id: ObjectId,
+    jti: &str,
+    expires_at: mongodb::bson::DateTime,
+) -> Result<(), ApiError> {
+    let revoked = RevokedTokenModel {
+        _id: ObjectId::new(),
+        jti: Some(jti.to_string()),
+        session_id: None,
+        user_id,
+        revoked_at: chrono::Utc::now().into(),
+        expires_at,
+    };
+
+    sessions.insert_revoked(&revoked).await
+}
+
+/// Any access token for the session was issued at most `access_token_ttl` ago, so that is how long
+/// the revocation has to be remembered.
+async fn revoke_session_access(
+    sessions: &dyn SessionRepository,
+    user_id: ObjectId,
+    session_id: &str,
+    access_token_ttl: Duration,
+) -> Result<(), ApiError> {
+    let now = chrono::Utc::now();
+    let revoked = RevokedTokenModel {
+        _id: ObjectId::new(),
+        jti: None,
+        session_id: Some(session_id.to_string()),
+        user_id,
+        revoked_at: now.into(),
+        expires_at: (now + chrono::Duration::seconds(access_token_ttl.as_secs() as i64)).into(),
+    };
+
+    sessions.insert_revoked(&revoked).await
+}
+
+/// Checks whether an access token has been revoked, either by itself or along with its session.
+pub async fn is_access_revoked(
+    sessions: &dyn SessionRepository,
+    jti: Option<&str>,
+    session_id: Option<&str>,
+) -> Result<bool, ApiError> {
+    sessions.is_revoked(jti, session_id).await
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+
+    #[test]
+    fn generated_token_matches_hash() {
+        let (token, token_hash) = generate_token();
+
+        assert_eq!(token_hash, hash_token(&token));
+        assert_ne!(token, token_hash);
+    }
+
+    #[test]
+    fn rotated_token_keeps_family() {
+        let user_id = ObjectId::new();
+        let (first, first_model) =
+            new_refresh_token("family".to_string(), user_id, Duration::from_secs(60));
+        let (second, second_model) = new_refresh_token(
+            first_model.family_id.clone(),
+            user_id,
+            Duration::from_secs(60),
+        );
+
+        assert_ne!(first, second);
+        assert_eq!(first_model.family_id, second_model.family_id);
+        assert_eq!(
+            60_000,
+            second_model.expires_at.timestamp_millis() - second_model.created_at.timestamp_millis()
+        );
+    }
+}
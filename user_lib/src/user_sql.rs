@@ -0,0 +1,1090 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use error_lib::api_error::ApiError;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sqlx::{any::AnyRow, Any, AnyConnection, AnyPool, Decode, Row, Type, TypeInfo, ValueRef};
+use tokio::task::JoinHandle;
+
+use crate::{
+    user_api_key::ApiKeyModel,
+    user_lockout::{LockoutKind, LoginAttemptModel},
+    user_models::{AccountState, Role, UserModel},
+    user_password_reset::PasswordResetModel,
+    user_repository::{
+        ApiKeyRepository, LoginAttemptRepository, PasswordResetRepository, SessionRepository,
+        UserChanges, UserRepository,
+    },
+    user_session::{RefreshTokenModel, RevokedTokenModel},
+    user_totp::UserTotpModel,
+};
+
+// Stores everything in the tables of `migrations/`, through sqlx's `Any` driver so the same
+// queries run on SQLite and Postgres. That limits them to what both understand: `$n` parameters,
+// `RETURNING` and `ON CONFLICT`.
+
+/// The tables whose rows only matter until their `expires_at`. Mongo drops them with TTL indexes.
+const EXPIRING_TABLES: [&str; 4] = [
+    "refresh_tokens",
+    "revoked_tokens",
+    "login_attempts",
+    "password_resets",
+];
+
+/// Deletes every row of the [`EXPIRING_TABLES`] that expired by `now`, returning how many.
+pub async fn delete_expired(pool: &AnyPool, now: DateTime) -> Result<u64, ApiError> {
+    let mut deleted = 0;
+    for table in EXPIRING_TABLES {
+        let result = sqlx::query(&format!("DELETE FROM {table} WHERE expires_at <= $1"))
+            .bind(now.timestamp_millis())
+            .execute(pool)
+            .await?;
+        deleted += result.rows_affected();
+    }
+    Ok(deleted)
+}
+
+/// Runs [`delete_expired`] every `period`, standing in for the TTL indexes SQL does not have.
+pub fn spawn_expiry_cleanup(pool: AnyPool, period: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match delete_expired(&pool, DateTime::now()).await {
+                Ok(0) => {}
+                Ok(deleted) => tracing::debug!("deleted {deleted} expired row(s)"),
+                Err(err) => tracing::warn!("could not delete expired rows: {err}"),
+            }
+        }
+    })
+}
+
+fn object_id(row: &AnyRow, column: &str) -> Result<ObjectId, ApiError> {
+    ObjectId::parse_str(row.try_get::<String, _>(column)?).map_err(ApiError::internal)
+}
+
+fn date(row: &AnyRow, column: &str) -> Result<DateTime, ApiError> {
+    Ok(DateTime::from_millis(row.try_get(column)?))
+}
+
+/// The Any driver never reports a value as NULL, so decoding one into an `Option` fails. The name
+/// of its type does say NULL though.
+fn optional<'r, T: Decode<'r, Any> + Type<Any>>(
+    row: &'r AnyRow,
+    column: &str,
+) -> Result<Option<T>, ApiError> {
+    if row.try_get_raw(column)?.type_info().name() == "NULL" {
+        return Ok(None);
+    }
+    Ok(Some(row.try_get(column)?))
+}
+
+fn optional_date(row: &AnyRow, column: &str) -> Result<Option<DateTime>, ApiError> {
+    Ok(optional::<i64>(row, column)?.map(DateTime::from_millis))
+}
+
+fn flag(row: &AnyRow, column: &str) -> Result<bool, ApiError> {
+    Ok(row.try_get::<i64, _>(column)? != 0)
+}
+
+/// The name serde gives a unit variant, like `active` for `AccountState::Active`.
+fn variant_name(value: impl Serialize) -> Result<String, ApiError> {
+    match serde_json::to_value(value).map_err(ApiError::internal)? {
+        Value::String(name) => Ok(name),
+        other => Err(ApiError::internal(anyhow::anyhow!(
+            "{other} is not a unit variant"
+        ))),
+    }
+}
+
+fn from_variant_name<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T, ApiError> {
+    serde_json::from_value(Value::String(row.try_get(column)?)).map_err(ApiError::internal)
+}
+
+fn to_json(value: impl Serialize) -> Result<String, ApiError> {
+    serde_json::to_string(&value).map_err(ApiError::internal)
+}
+
+fn from_json<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T, ApiError> {
+    serde_json::from_str(row.try_get(column)?).map_err(ApiError::internal)
+}
+
+pub struct SqlUserRepository {
+    pool: AnyPool,
+}
+
+impl SqlUserRepository {
+    pub fn new(pool: AnyPool) -> SqlUserRepository {
+        SqlUserRepository { pool }
+    }
+
+    /// Adds the recovery codes, which live in their own table, to a row of `users`.
+    async fn load(&self, row: Option<AnyRow>) -> Result<Option<UserModel>, ApiError> {
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let totp = match optional::<String>(&row, "totp_secret")? {
+            Some(secret) => {
+                let recovery_code_hashes =
+                    sqlx::query_scalar("SELECT code_hash FROM recovery_codes WHERE user_id = $1")
+                        .bind(row.try_get::<String, _>("id")?)
+                        .fetch_all(&self.pool)
+                        .await?;
+                Some(UserTotpModel {
+                    secret,
+                    confirmed: optional::<i64>(&row, "totp_confirmed")? == Some(1),
+                    last_used_step: optional(&row, "totp_last_used_step")?,
+                    recovery_code_hashes,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Some(UserModel {
+            _id: object_id(&row, "id")?,
+            username: row.try_get("username")?,
+            display_name: optional(&row, "display_name")?,
+            password: row.try_get("password")?,
+            email: row.try_get("email")?,
+            roles: from_json(&row, "roles")?,
+            state: from_variant_name(&row, "state")?,
+            verification_nonce: optional(&row, "verification_nonce")?,
+            pending_email: optional(&row, "pending_email")?,
+            totp,
+            created_at: date(&row, "created_at")?,
+            updated_at: date(&row, "updated_at")?,
+        }))
+    }
+}
+
+async fn replace_recovery_codes(
+    conn: &mut AnyConnection,
+    id: ObjectId,
+    recovery_code_hashes: &[String],
+) -> Result<(), ApiError> {
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(id.to_hex())
+        .execute(&mut *conn)
+        .await?;
+    for code_hash in recovery_code_hashes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(id.to_hex())
+            .bind(code_hash)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl UserRepository for SqlUserRepository {
+    async fn insert(&self, user: &UserModel) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO users (id, username, display_name, password, email, roles, state, \
+             verification_nonce, totp_secret, totp_confirmed, totp_last_used_step, created_at, \
+             updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+        )
+        .bind(user._id.to_hex())
+        .bind(&user.username)
+        .bind(&user.display_name)
+        .bind(&user.password)
+        .bind(&user.email)
+        .bind(to_json(&user.roles)?)
+        .bind(variant_name(user.state)?)
+        .bind(&user.verification_nonce)
+        .bind(user.totp.as_ref().map(|totp| totp.secret.clone()))
+        .bind(user.totp.as_ref().map(|totp| i64::from(totp.confirmed)))
+        .bind(user.totp.as_ref().and_then(|totp| totp.last_used_step))
+        .bind(user.created_at.timestamp_millis())
+        .bind(user.updated_at.timestamp_millis())
+        .execute(&mut *tx)
+        .await?;
+        if let Some(totp) = &user.totp {
+            replace_recovery_codes(&mut tx, user._id, &totp.recovery_code_hashes).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<UserModel>, ApiError> {
+        let row = sqlx::query("SELECT * FROM users WHERE id = $1")
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool)
+            .await?;
+        self.load(row).await
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<UserModel>, ApiError> {
+        let row = sqlx::query("SELECT * FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        self.load(row).await
+    }
+
+    async fn set_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = sqlx::query("UPDATE users SET password = $1, updated_at = $2 WHERE id = $3")
+            .bind(password)
+            .bind(now.timestamp_millis())
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_roles(
+        &self,
+        username: &str,
+        roles: &[Role],
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result =
+            sqlx::query("UPDATE users SET roles = $1, updated_at = $2 WHERE username = $3")
+                .bind(to_json(roles)?)
+                .bind(now.timestamp_millis())
+                .bind(username)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn verify_email(
+        &self,
+        username: &str,
+        nonce: &str,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "UPDATE users SET state = $1, email = COALESCE(pending_email, email), \
+             pending_email = NULL, verification_nonce = NULL, updated_at = $2 \
+             WHERE username = $3 AND (state = $4 OR pending_email IS NOT NULL) \
+             AND verification_nonce = $5",
+        )
+        .bind(variant_name(AccountState::Active)?)
+        .bind(now.timestamp_millis())
+        .bind(username)
+        .bind(variant_name(AccountState::Unverified)?)
+        .bind(nonce)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn renew_verification_nonce(
+        &self,
+        username: &str,
+        nonce: &str,
+    ) -> Result<Option<UserModel>, ApiError> {
+        let row = sqlx::query(
+            "UPDATE users SET verification_nonce = $1 WHERE username = $2 AND state = $3 \
+             RETURNING *",
+        )
+        .bind(nonce)
+        .bind(username)
+        .bind(variant_name(AccountState::Unverified)?)
+        .fetch_optional(&self.pool)
+        .await?;
+        self.load(row).await
+    }
+
+    async fn reset_password(
+        &self,
+        id: ObjectId,
+        password: &str,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        let row = sqlx::query(
+            "UPDATE users SET password = $1, state = $2, verification_nonce = NULL, \
+             updated_at = $3 WHERE id = $4 RETURNING *",
+        )
+        .bind(password)
+        .bind(variant_name(AccountState::Active)?)
+        .bind(now.timestamp_millis())
+        .bind(id.to_hex())
+        .fetch_optional(&self.pool)
+        .await?;
+        self.load(row).await
+    }
+
+    async fn update_profile(
+        &self,
+        id: ObjectId,
+        changes: &UserChanges,
+        now: DateTime,
+    ) -> Result<Option<UserModel>, ApiError> {
+        let row = sqlx::query(
+            "UPDATE users SET pending_email = COALESCE($1, pending_email), \
+             verification_nonce = COALESCE($2, verification_nonce), \
+             display_name = CASE WHEN $3 THEN $4 ELSE display_name END, updated_at = $5 \
+             WHERE id = $6 RETURNING *",
+        )
+        .bind(changes.pending_email.as_ref().map(|pending| &pending.email))
+        .bind(
+            changes
+                .pending_email
+                .as_ref()
+                .map(|pending| &pending.verification_nonce),
+        )
+        .bind(changes.display_name.is_some())
+        .bind(changes.display_name.clone().flatten())
+        .bind(now.timestamp_millis())
+        .bind(id.to_hex())
+        .fetch_optional(&self.pool)
+        .await?;
+        self.load(row).await
+    }
+
+    async fn begin_totp(&self, id: ObjectId, totp: &UserTotpModel) -> Result<bool, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE users SET totp_secret = $1, totp_confirmed = $2, totp_last_used_step = $3 \
+             WHERE id = $4 AND (totp_confirmed IS NULL OR totp_confirmed = 0)",
+        )
+        .bind(&totp.secret)
+        .bind(i64::from(totp.confirmed))
+        .bind(totp.last_used_step)
+        .bind(id.to_hex())
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        replace_recovery_codes(&mut tx, id, &totp.recovery_code_hashes).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn confirm_totp(
+        &self,
+        id: ObjectId,
+        secret: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+    ) -> Result<bool, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            "UPDATE users SET totp_confirmed = 1, totp_last_used_step = $1 \
+             WHERE id = $2 AND totp_secret = $3 AND totp_confirmed = 0",
+        )
+        .bind(step)
+        .bind(id.to_hex())
+        .bind(secret)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        replace_recovery_codes(&mut tx, id, recovery_code_hashes).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn use_totp_step(&self, id: ObjectId, step: i64) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_used_step = $1 WHERE id = $2 \
+             AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)",
+        )
+        .bind(step)
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn use_recovery_code(&self, id: ObjectId, code_hash: &str) -> Result<bool, ApiError> {
+        let result =
+            sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2")
+                .bind(id.to_hex())
+                .bind(code_hash)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn clear_totp(&self, id: ObjectId) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "UPDATE users SET totp_secret = NULL, totp_confirmed = NULL, \
+             totp_last_used_step = NULL WHERE id = $1",
+        )
+        .bind(id.to_hex())
+        .execute(&mut *tx)
+        .await?;
+        replace_recovery_codes(&mut tx, id, &[]).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        replace_recovery_codes(&mut tx, id, &[]).await?;
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+pub struct SqlSessionRepository {
+    pool: AnyPool,
+}
+
+impl SqlSessionRepository {
+    pub fn new(pool: AnyPool) -> SqlSessionRepository {
+        SqlSessionRepository { pool }
+    }
+}
+
+fn refresh_token_from_row(row: &AnyRow) -> Result<RefreshTokenModel, ApiError> {
+    Ok(RefreshTokenModel {
+        _id: object_id(row, "id")?,
+        token_hash: row.try_get("token_hash")?,
+        family_id: row.try_get("family_id")?,
+        user_id: object_id(row, "user_id")?,
+        created_at: date(row, "created_at")?,
+        expires_at: date(row, "expires_at")?,
+        used_at: optional_date(row, "used_at")?,
+        revoked: flag(row, "revoked")?,
+    })
+}
+
+#[async_trait]
+impl SessionRepository for SqlSessionRepository {
+    async fn insert_refresh_token(
+        &self,
+        refresh_token: &RefreshTokenModel,
+    ) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO refresh_tokens (id, token_hash, family_id, user_id, created_at, \
+             expires_at, used_at, revoked) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(refresh_token._id.to_hex())
+        .bind(&refresh_token.token_hash)
+        .bind(&refresh_token.family_id)
+        .bind(refresh_token.user_id.to_hex())
+        .bind(refresh_token.created_at.timestamp_millis())
+        .bind(refresh_token.expires_at.timestamp_millis())
+        .bind(
+            refresh_token
+                .used_at
+                .map(|used_at| used_at.timestamp_millis()),
+        )
+        .bind(i64::from(refresh_token.revoked))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshTokenModel>, ApiError> {
+        sqlx::query("SELECT * FROM refresh_tokens WHERE token_hash = $1")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| refresh_token_from_row(&row))
+            .transpose()
+    }
+
+    async fn claim_refresh_token(&self, id: ObjectId, now: DateTime) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET used_at = $1 \
+             WHERE id = $2 AND used_at IS NULL AND revoked = 0",
+        )
+        .bind(now.timestamp_millis())
+        .bind(id.to_hex())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_active(
+        &self,
+        user_id: ObjectId,
+        now: DateTime,
+    ) -> Result<Vec<RefreshTokenModel>, ApiError> {
+        sqlx::query(
+            "SELECT * FROM refresh_tokens WHERE user_id = $1 AND used_at IS NULL \
+             AND revoked = 0 AND expires_at > $2 ORDER BY created_at DESC",
+        )
+        .bind(user_id.to_hex())
+        .bind(now.timestamp_millis())
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(refresh_token_from_row)
+        .collect()
+    }
+
+    async fn revoke_family(&self, user_id: ObjectId, family_id: &str) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET revoked = 1 WHERE family_id = $1 AND user_id = $2",
+        )
+        .bind(family_id)
+        .bind(user_id.to_hex())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_all(&self, user_id: ObjectId) -> Result<Vec<String>, ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let family_ids = sqlx::query_scalar(
+            "SELECT DISTINCT family_id FROM refresh_tokens WHERE user_id = $1 AND revoked = 0",
+        )
+        .bind(user_id.to_hex())
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE refresh_tokens SET revoked = 1 WHERE user_id = $1")
+            .bind(user_id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(family_ids)
+    }
+
+    async fn insert_revoked(&self, revoked: &RevokedTokenModel) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO revoked_tokens (id, jti, session_id, user_id, revoked_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(revoked._id.to_hex())
+        .bind(&revoked.jti)
+        .bind(&revoked.session_id)
+        .bind(revoked.user_id.to_hex())
+        .bind(revoked.revoked_at.timestamp_millis())
+        .bind(revoked.expires_at.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn is_revoked(
+        &self,
+        jti: Option<&str>,
+        session_id: Option<&str>,
+    ) -> Result<bool, ApiError> {
+        if jti.is_none() && session_id.is_none() {
+            return Ok(false);
+        }
+
+        // Comparing with a NULL is never true, so a missing id matches nothing.
+        let revoked =
+            sqlx::query("SELECT id FROM revoked_tokens WHERE jti = $1 OR session_id = $2 LIMIT 1")
+                .bind(jti)
+                .bind(session_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(revoked.is_some())
+    }
+}
+
+pub struct SqlApiKeyRepository {
+    pool: AnyPool,
+}
+
+impl SqlApiKeyRepository {
+    pub fn new(pool: AnyPool) -> SqlApiKeyRepository {
+        SqlApiKeyRepository { pool }
+    }
+}
+
+fn api_key_from_row(row: &AnyRow) -> Result<ApiKeyModel, ApiError> {
+    Ok(ApiKeyModel {
+        _id: object_id(row, "id")?,
+        user_id: object_id(row, "user_id")?,
+        name: row.try_get("name")?,
+        key_hint: row.try_get("key_hint")?,
+        key_hash: row.try_get("key_hash")?,
+        scopes: from_json(row, "scopes")?,
+        created_at: date(row, "created_at")?,
+        last_used_at: optional_date(row, "last_used_at")?,
+        revoked_at: optional_date(row, "revoked_at")?,
+    })
+}
+
+#[async_trait]
+impl ApiKeyRepository for SqlApiKeyRepository {
+    async fn insert(&self, api_key: &ApiKeyModel) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO api_keys (id, user_id, name, key_hint, key_hash, scopes, created_at, \
+             last_used_at, revoked_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(api_key._id.to_hex())
+        .bind(api_key.user_id.to_hex())
+        .bind(&api_key.name)
+        .bind(&api_key.key_hint)
+        .bind(&api_key.key_hash)
+        .bind(to_json(&api_key.scopes)?)
+        .bind(api_key.created_at.timestamp_millis())
+        .bind(api_key.last_used_at.map(|at| at.timestamp_millis()))
+        .bind(api_key.revoked_at.map(|at| at.timestamp_millis()))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn list_active(&self, user_id: ObjectId) -> Result<Vec<ApiKeyModel>, ApiError> {
+        sqlx::query(
+            "SELECT * FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL \
+             ORDER BY created_at DESC",
+        )
+        .bind(user_id.to_hex())
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(api_key_from_row)
+        .collect()
+    }
+
+    async fn revoke(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        now: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "UPDATE api_keys SET revoked_at = $1 \
+             WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL",
+        )
+        .bind(now.timestamp_millis())
+        .bind(id.to_hex())
+        .bind(user_id.to_hex())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        sqlx::query("DELETE FROM api_keys WHERE user_id = $1")
+            .bind(user_id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn authenticate(
+        &self,
+        key_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<ApiKeyModel>, ApiError> {
+        sqlx::query(
+            "UPDATE api_keys SET last_used_at = $1 \
+             WHERE key_hash = $2 AND revoked_at IS NULL RETURNING *",
+        )
+        .bind(now.timestamp_millis())
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| api_key_from_row(&row))
+        .transpose()
+    }
+}
+
+pub struct SqlLoginAttemptRepository {
+    pool: AnyPool,
+}
+
+impl SqlLoginAttemptRepository {
+    pub fn new(pool: AnyPool) -> SqlLoginAttemptRepository {
+        SqlLoginAttemptRepository { pool }
+    }
+}
+
+fn login_attempt_from_row(row: &AnyRow) -> Result<LoginAttemptModel, ApiError> {
+    Ok(LoginAttemptModel {
+        _id: object_id(row, "id")?,
+        kind: from_variant_name(row, "kind")?,
+        subject: row.try_get("subject")?,
+        failures: u32::try_from(row.try_get::<i64, _>("failures")?).map_err(ApiError::internal)?,
+        last_failure_at: date(row, "last_failure_at")?,
+        locked_until: optional_date(row, "locked_until")?,
+        expires_at: date(row, "expires_at")?,
+    })
+}
+
+#[async_trait]
+impl LoginAttemptRepository for SqlLoginAttemptRepository {
+    async fn find_locked(
+        &self,
+        subjects: &[(LockoutKind, &str)],
+        now: DateTime,
+    ) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        let mut locked = Vec::new();
+        for (kind, subject) in subjects {
+            let row = sqlx::query(
+                "SELECT * FROM login_attempts \
+                 WHERE kind = $1 AND subject = $2 AND locked_until > $3 AND expires_at > $3",
+            )
+            .bind(kind.as_str())
+            .bind(*subject)
+            .bind(now.timestamp_millis())
+            .fetch_optional(&self.pool)
+            .await?;
+            if let Some(row) = row {
+                locked.push(login_attempt_from_row(&row)?);
+            }
+        }
+        Ok(locked)
+    }
+
+    /// The cleanup may not have deleted an expired attempt yet, so the upsert starts those over
+    /// itself.
+    async fn record_failure(
+        &self,
+        kind: LockoutKind,
+        subject: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<LoginAttemptModel, ApiError> {
+        let row = sqlx::query(
+            "INSERT INTO login_attempts (id, kind, subject, failures, last_failure_at, \
+             expires_at) VALUES ($1, $2, $3, 1, $4, $5) \
+             ON CONFLICT (kind, subject) DO UPDATE SET \
+             failures = CASE WHEN login_attempts.expires_at <= excluded.last_failure_at THEN 1 \
+             ELSE login_attempts.failures + 1 END, \
+             locked_until = CASE WHEN login_attempts.expires_at <= excluded.last_failure_at \
+             THEN NULL ELSE login_attempts.locked_until END, \
+             last_failure_at = excluded.last_failure_at, expires_at = excluded.expires_at \
+             RETURNING *",
+        )
+        .bind(ObjectId::new().to_hex())
+        .bind(kind.as_str())
+        .bind(subject)
+        .bind(now.timestamp_millis())
+        .bind(expires_at.timestamp_millis())
+        .fetch_one(&self.pool)
+        .await?;
+        login_attempt_from_row(&row)
+    }
+
+    async fn lock(&self, id: ObjectId, locked_until: DateTime) -> Result<(), ApiError> {
+        sqlx::query("UPDATE login_attempts SET locked_until = $1 WHERE id = $2")
+            .bind(locked_until.timestamp_millis())
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, kind: LockoutKind, subject: &str) -> Result<bool, ApiError> {
+        let result = sqlx::query("DELETE FROM login_attempts WHERE kind = $1 AND subject = $2")
+            .bind(kind.as_str())
+            .bind(subject)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list(&self) -> Result<Vec<LoginAttemptModel>, ApiError> {
+        sqlx::query("SELECT * FROM login_attempts ORDER BY last_failure_at DESC")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(login_attempt_from_row)
+            .collect()
+    }
+}
+
+pub struct SqlPasswordResetRepository {
+    pool: AnyPool,
+}
+
+impl SqlPasswordResetRepository {
+    pub fn new(pool: AnyPool) -> SqlPasswordResetRepository {
+        SqlPasswordResetRepository { pool }
+    }
+}
+
+fn password_reset_from_row(row: &AnyRow) -> Result<PasswordResetModel, ApiError> {
+    Ok(PasswordResetModel {
+        _id: object_id(row, "id")?,
+        token_hash: row.try_get("token_hash")?,
+        user_id: object_id(row, "user_id")?,
+        created_at: date(row, "created_at")?,
+        expires_at: date(row, "expires_at")?,
+        used_at: optional_date(row, "used_at")?,
+    })
+}
+
+#[async_trait]
+impl PasswordResetRepository for SqlPasswordResetRepository {
+    async fn replace_unused(&self, reset: &PasswordResetModel) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM password_resets WHERE user_id = $1 AND used_at IS NULL")
+            .bind(reset.user_id.to_hex())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO password_resets (id, token_hash, user_id, created_at, expires_at, \
+             used_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(reset._id.to_hex())
+        .bind(&reset.token_hash)
+        .bind(reset.user_id.to_hex())
+        .bind(reset.created_at.timestamp_millis())
+        .bind(reset.expires_at.timestamp_millis())
+        .bind(reset.used_at.map(|used_at| used_at.timestamp_millis()))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn consume(
+        &self,
+        token_hash: &str,
+        now: DateTime,
+    ) -> Result<Option<PasswordResetModel>, ApiError> {
+        sqlx::query(
+            "UPDATE password_resets SET used_at = $1 \
+             WHERE token_hash = $2 AND used_at IS NULL AND expires_at > $1 RETURNING *",
+        )
+        .bind(now.timestamp_millis())
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| password_reset_from_row(&row))
+        .transpose()
+    }
+
+    async fn delete_all(&self, user_id: ObjectId) -> Result<(), ApiError> {
+        sqlx::query("DELETE FROM password_resets WHERE user_id = $1")
+            .bind(user_id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod user_sql_tests {
+    use super::*;
+    use crate::{user_models::default_roles, user_repository::PendingEmail};
+    use sqlx::any::AnyPoolOptions;
+
+    /// A migrated SQLite database that only lives as long as its single connection.
+    async fn test_pool() -> AnyPool {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn user(username: &str) -> UserModel {
+        UserModel {
+            _id: ObjectId::new(),
+            username: username.to_string(),
+            display_name: None,
+            password: "hash".to_string(),
+            email: format!("{username}@example.com"),
+            roles: default_roles(),
+            state: AccountState::Unverified,
+            verification_nonce: Some("nonce".to_string()),
+            pending_email: None,
+            totp: None,
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn usernames_are_unique() {
+        let users = SqlUserRepository::new(test_pool().await);
+        users.insert(&user("jork")).await.unwrap();
+
+        let err = users.insert(&user("jork")).await.unwrap_err();
+
+        assert!(err.is_duplicate_key());
+        let found = users.find_by_username("jork").await.unwrap().unwrap();
+        assert_eq!(AccountState::Unverified, found.state);
+        assert_eq!(default_roles(), found.roles);
+    }
+
+    #[tokio::test]
+    async fn email_is_verified_once() {
+        let users = SqlUserRepository::new(test_pool().await);
+        users.insert(&user("jork")).await.unwrap();
+
+        assert!(!users
+            .verify_email("jork", "wrong", DateTime::now())
+            .await
+            .unwrap());
+        assert!(users
+            .verify_email("jork", "nonce", DateTime::now())
+            .await
+            .unwrap());
+        assert!(!users
+            .verify_email("jork", "nonce", DateTime::now())
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn changed_email_waits_for_verification() {
+        let users = SqlUserRepository::new(test_pool().await);
+        let jork = UserModel {
+            state: AccountState::Active,
+            verification_nonce: None,
+            ..user("jork")
+        };
+        users.insert(&jork).await.unwrap();
+        let changes = UserChanges {
+            pending_email: Some(PendingEmail {
+                email: "new@example.com".to_string(),
+                verification_nonce: "nonce".to_string(),
+            }),
+            display_name: None,
+        };
+
+        let updated = users
+            .update_profile(jork._id, &changes, DateTime::now())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(jork.email, updated.email);
+        assert_eq!(Some("new@example.com".to_string()), updated.pending_email);
+        assert!(users
+            .verify_email("jork", "nonce", DateTime::now())
+            .await
+            .unwrap());
+        let verified = users.find_by_id(jork._id).await.unwrap().unwrap();
+        assert_eq!("new@example.com", verified.email);
+        assert_eq!(None, verified.pending_email);
+        assert_eq!(AccountState::Active, verified.state);
+    }
+
+    #[tokio::test]
+    async fn totp_is_confirmed_with_recovery_codes() {
+        let users = SqlUserRepository::new(test_pool().await);
+        let jork = user("jork");
+        users.insert(&jork).await.unwrap();
+        let pending = UserTotpModel {
+            secret: "secret".to_string(),
+            confirmed: false,
+            last_used_step: None,
+            recovery_code_hashes: Vec::new(),
+        };
+        let codes = vec!["a".to_string(), "b".to_string()];
+
+        assert!(users.begin_totp(jork._id, &pending).await.unwrap());
+        assert!(users
+            .confirm_totp(jork._id, "secret", 7, &codes)
+            .await
+            .unwrap());
+        assert!(!users.begin_totp(jork._id, &pending).await.unwrap());
+        assert!(!users.use_totp_step(jork._id, 7).await.unwrap());
+        assert!(users.use_recovery_code(jork._id, "a").await.unwrap());
+
+        let totp = users.find_by_id(jork._id).await.unwrap().unwrap().totp;
+        let totp = totp.unwrap();
+        assert!(totp.confirmed);
+        assert_eq!(vec!["b".to_string()], totp.recovery_code_hashes);
+    }
+
+    #[tokio::test]
+    async fn failures_are_counted_per_subject() {
+        let attempts = SqlLoginAttemptRepository::new(test_pool().await);
+        let (now, later) = (DateTime::from_millis(1000), DateTime::from_millis(2000));
+
+        attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        let second = attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        let other = attempts
+            .record_failure(LockoutKind::Ip, "jork", now, later)
+            .await
+            .unwrap();
+
+        assert_eq!(2, second.failures);
+        assert_eq!(1, other.failures);
+    }
+
+    #[tokio::test]
+    async fn expired_failures_are_forgotten() {
+        let pool = test_pool().await;
+        let attempts = SqlLoginAttemptRepository::new(pool.clone());
+        let (now, later) = (DateTime::from_millis(1000), DateTime::from_millis(2000));
+        let attempt = attempts
+            .record_failure(LockoutKind::Username, "jork", now, later)
+            .await
+            .unwrap();
+        attempts.lock(attempt._id, later).await.unwrap();
+
+        let subjects = [(LockoutKind::Username, "jork")];
+        assert!(attempts
+            .find_locked(&subjects, later)
+            .await
+            .unwrap()
+            .is_empty());
+        let restarted = attempts
+            .record_failure(
+                LockoutKind::Username,
+                "jork",
+                later,
+                DateTime::from_millis(3000),
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, restarted.failures);
+        assert_eq!(None, restarted.locked_until);
+
+        assert_eq!(
+            1,
+            delete_expired(&pool, DateTime::from_millis(3000))
+                .await
+                .unwrap()
+        );
+        assert!(attempts.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_token_is_claimed_once() {
+        let sessions = SqlSessionRepository::new(test_pool().await);
+        let refresh_token = RefreshTokenModel {
+            _id: ObjectId::new(),
+            token_hash: "hash".to_string(),
+            family_id: "family".to_string(),
+            user_id: ObjectId::new(),
+            created_at: DateTime::now(),
+            expires_at: DateTime::now(),
+            used_at: None,
+            revoked: false,
+        };
+        sessions.insert_refresh_token(&refresh_token).await.unwrap();
+
+        assert!(sessions
+            .claim_refresh_token(refresh_token._id, DateTime::now())
+            .await
+            .unwrap());
+        assert!(!sessions
+            .claim_refresh_token(refresh_token._id, DateTime::now())
+            .await
+            .unwrap());
+        assert_eq!(
+            vec!["family".to_string()],
+            sessions.revoke_all(refresh_token.user_id).await.unwrap()
+        );
+    }
+}
@@ -1,3 +1,9 @@
+pub mod word_history;
 pub mod word_logic;
+pub mod word_memory;
 pub mod word_models;
+pub mod word_mongo;
 pub mod word_queue;
+pub mod word_repository;
+pub mod word_rotation;
+pub mod word_sql;
@@ -0,0 +1,121 @@
+use bson::oid::ObjectId;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::word_models::WordModel;
+
+pub const HISTORY_PER_PAGE_DEFAULT: u64 = 20;
+pub const HISTORY_PER_PAGE_MAX: u64 = 100;
+
+/// A word that has been the word of the day, kept once the queue moved past it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WotdHistoryModel {
+    pub _id: ObjectId,
+    /// Stored as `YYYY-MM-DD`, so it sorts and compares like the date it is.
+    pub date: NaiveDate,
+    pub word: WordModel,
+    pub featured_at: mongodb::bson::DateTime,
+}
+
+/// Both ends are inclusive, a missing end leaves that side open.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from.map_or(true, |from| from <= date) && self.to.map_or(true, |to| date <= to)
+    }
+}
+
+/// The query string of `/api/wotd/history`. Pages start at 1.
+#[derive(Deserialize, Default, Debug)]
+pub struct DtoHistoryQuery {
+    // Not a flattened `DateRange`, query strings cannot be flattened into numbers.
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+impl DtoHistoryQuery {
+    pub fn range(&self) -> DateRange {
+        DateRange {
+            from: self.from,
+            to: self.to,
+        }
+    }
+
+    /// The page asked for, and how many entries are on it, within bounds. Pages so far out that
+    /// they would skip more entries than an `i64` holds, which databases count skips in, are
+    /// brought back to the last one that does not.
+    pub fn page(&self) -> (u64, u64) {
+        let per_page = self
+            .per_page
+            .unwrap_or(HISTORY_PER_PAGE_DEFAULT)
+            .clamp(1, HISTORY_PER_PAGE_MAX);
+        let last_page = i64::MAX as u64 / per_page;
+        (self.page.unwrap_or(1).clamp(1, last_page), per_page)
+    }
+}
+
+/// A page of the history, newest first.
+#[derive(Serialize, Debug)]
+pub struct DtoHistoryPage {
+    pub page: u64,
+    pub per_page: u64,
+    /// How many entries there are in the whole range.
+    pub total: u64,
+    pub items: Vec<WotdHistoryModel>,
+}
+
+#[cfg(test)]
+mod word_history_tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn range_ends_are_inclusive() {
+        let range = DateRange {
+            from: Some(date("2024-02-01")),
+            to: Some(date("2024-02-14")),
+        };
+
+        assert!(range.contains(date("2024-02-01")));
+        assert!(range.contains(date("2024-02-14")));
+        assert!(!range.contains(date("2024-02-15")));
+        assert!(DateRange::default().contains(date("1999-12-31")));
+    }
+
+    #[test]
+    fn page_is_kept_in_bounds() {
+        let query = DtoHistoryQuery {
+            page: Some(0),
+            per_page: Some(1000),
+            ..DtoHistoryQuery::default()
+        };
+        assert_eq!((1, HISTORY_PER_PAGE_MAX), query.page());
+
+        assert_eq!(
+            (1, HISTORY_PER_PAGE_DEFAULT),
+            DtoHistoryQuery::default().page()
+        );
+    }
+
+    #[test]
+    fn far_pages_skip_no_more_than_an_i64() {
+        let query = DtoHistoryQuery {
+            page: Some(u64::MAX),
+            per_page: Some(7),
+            ..DtoHistoryQuery::default()
+        };
+
+        let (page, per_page) = query.page();
+        assert!(i64::try_from((page - 1) * per_page).is_ok());
+    }
+}
@@ -3,66 +3,630 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use bson::oid::ObjectId;
-use mongodb::Collection;
-use tokio_stream::StreamExt;
+use bson::{oid::ObjectId, DateTime};
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
 
-use crate::word_models::{DtoWotdCreate, WordModel};
-
-pub async fn get_one_word(
-    collection: Collection<WordModel>,
-    word: String,
-) -> Result<Response, StatusCode> {
-    let word_name = word.to_string();
+use crate::{
+    word_history::{DtoHistoryPage, DtoHistoryQuery, WotdHistoryModel},
+    word_models::{DtoWotdCreate, WordModel},
+    word_queue::{DtoQueueEntry, QueueItemWordModel},
+    word_repository::{HistoryRepository, QueueRepository, RotationRepository, WordRepository},
+    word_rotation::{RotationOutcome, RotationRunModel},
+};
 
-    let wotd = collection
-        .find_one(mongodb::bson::doc! { "word": &word_name }, None)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+pub async fn get_one_word(words: &dyn WordRepository, word: String) -> Result<Response, ApiError> {
+    let wotd = words.find_by_word(&word).await?.ok_or(ApiError::NotFound)?;
 
     Ok((StatusCode::OK, Json(Some(wotd))).into_response())
 }
 
-pub async fn get_all_words(collection: Collection<WordModel>) -> Result<Response, StatusCode> {
-    let mut cursor_wotd = collection
-        .find(None, None)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut wotds = Vec::new();
-    while let Some(wotd) = cursor_wotd.next().await {
-        match wotd {
-            Ok(w) => wotds.push(w),
-            Err(err) => {
-                tracing::warn!("error occured during mongo cursor iteration: {err}")
-            }
-        }
-    }
+pub async fn get_all_words(words: &dyn WordRepository) -> Result<Response, ApiError> {
+    let wotds = words.list().await?;
     Ok((StatusCode::OK, Json(wotds)).into_response())
 }
 
+fn new_word(user_id: ObjectId, create_word: DtoWotdCreate) -> WordModel {
+    WordModel {
+        _id: ObjectId::new(),
+        created_by_id: Some(user_id),
+        word: create_word.word,
+        definition: create_word.definition,
+        sentence: create_word.sentence,
+        created_at: chrono::Utc::now().into(),
+        updated_at: chrono::Utc::now().into(),
+    }
+}
+
 pub async fn create_one_word(
-    collection: Collection<WordModel>,
+    words: &dyn WordRepository,
     user_id: ObjectId,
     create_word_dto: DtoWotdCreate,
-) -> Result<Response, StatusCode> {
-    let create_word = create_word_dto;
+) -> Result<Response, ApiError> {
+    words.insert(&new_word(user_id, create_word_dto)).await?;
 
-    let wotd = WordModel {
+    Ok((StatusCode::OK, "wotd added!".to_string()).into_response())
+}
+
+/// Queues a word to become the word of the day, creating it first if it does not exist yet.
+/// Storing the word and queueing it happen at once, so of two people suggesting the same word
+/// at the same time, one gets a `409 Conflict`.
+pub async fn suggest_word(
+    queue: &dyn QueueRepository,
+    user_id: ObjectId,
+    suggestion: DtoWotdCreate,
+) -> Result<Response, ApiError> {
+    let added_at: DateTime = chrono::Utc::now().into();
+    let item = QueueItemWordModel {
         _id: ObjectId::new(),
-        created_by_id: user_id,
-        word: create_word.word.clone(),
-        definition: create_word.definition.clone(),
-        sentence: create_word.sentence.clone(),
-        created_at: chrono::Utc::now().into(),
-        updated_at: chrono::Utc::now().into(),
+        added_at,
+        rank: added_at.timestamp_millis(),
+        suggested_by_id: Some(user_id),
+        word: new_word(user_id, suggestion),
+        pinned_for: None,
     };
 
-    let _result = collection
-        .insert_one(wotd, None)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let queued = queue.enqueue(&item).await.map_err(|err| {
+        if err.is_duplicate_key() {
+            ApiError::Conflict(format!(
+                "{} word has already been suggested, and is in the queue!",
+                item.word.word
+            ))
+        } else {
+            err
+        }
+    })?;
+    if queued.word._id == item.word._id {
+        tracing::info!("queued new word {}", queued.word.word);
+    } else {
+        tracing::info!("queued existing word {}", queued.word.word);
+    }
 
     Ok((StatusCode::OK, "wotd added!".to_string()).into_response())
 }
+
+/// Takes the item for `date` off the queue, the one pinned to it or else the head, and archives it
+/// as the word of the day for `date`. Returns `None` when the queue is empty. An item that cannot
+/// be archived is queued again.
+pub async fn rotate_wotd(
+    queue: &dyn QueueRepository,
+    history: &dyn HistoryRepository,
+    date: NaiveDate,
+) -> Result<Option<QueueItemWordModel>, ApiError> {
+    // Whoever deletes the head features it, a concurrent rotation moves on to the next one.
+    while let Some(wotd) = queue.peek(date).await? {
+        if !queue.delete(wotd._id).await? {
+            continue;
+        }
+
+        let entry = WotdHistoryModel {
+            _id: ObjectId::new(),
+            date,
+            word: wotd.word.clone(),
+            featured_at: chrono::Utc::now().into(),
+        };
+        if let Err(err) = history.insert(&entry).await {
+            // Put it back where it was, so a retry features it instead of the next one.
+            if let Err(requeue_err) = queue.enqueue(&wotd).await {
+                tracing::error!(
+                    "{} could neither be archived nor queued again: {requeue_err}",
+                    wotd.word.word
+                );
+            }
+            return Err(err);
+        }
+
+        tracing::info!("{} is the word of the day for {date}", wotd.word.word);
+        return Ok(Some(wotd));
+    }
+    Ok(None)
+}
+
+/// What the rotation scheduler does every time it fires. Only rotates when `holder` gets the
+/// lease until `lease_until`, and records how it went. Returns `None` when another replica has
+/// the lease.
+pub async fn run_scheduled_rotation(
+    queue: &dyn QueueRepository,
+    history: &dyn HistoryRepository,
+    rotations: &dyn RotationRepository,
+    holder: &str,
+    date: NaiveDate,
+    lease_until: DateTime,
+) -> Result<Option<RotationRunModel>, ApiError> {
+    let started_at = DateTime::now();
+    if !rotations
+        .acquire_lease(holder, started_at, lease_until)
+        .await?
+    {
+        tracing::debug!("another replica rotates the queue for {date}");
+        return Ok(None);
+    }
+
+    let outcome = match rotate_wotd(queue, history, date).await {
+        Ok(Some(wotd)) => RotationOutcome::Rotated {
+            word: wotd.word.word,
+        },
+        Ok(None) => {
+            tracing::warn!("the queue is empty, there is no word of the day for {date}");
+            RotationOutcome::EmptyQueue
+        }
+        Err(err) => {
+            tracing::error!("scheduled rotation for {date} failed: {err}");
+            RotationOutcome::Failed {
+                error: err.to_string(),
+            }
+        }
+    };
+
+    let run = RotationRunModel {
+        _id: ObjectId::new(),
+        holder: holder.to_string(),
+        date,
+        started_at,
+        finished_at: DateTime::now(),
+        outcome,
+    };
+    rotations.record_run(&run).await?;
+    Ok(Some(run))
+}
+
+pub async fn get_last_rotation(rotations: &dyn RotationRepository) -> Result<Response, ApiError> {
+    let run = rotations.last_run().await?.ok_or(ApiError::NotFound)?;
+
+    Ok((StatusCode::OK, Json(run)).into_response())
+}
+
+/// Pins the queued `word` to `date`, a day that has not passed yet on `today`.
+pub async fn pin_word(
+    queue: &dyn QueueRepository,
+    word: &str,
+    date: NaiveDate,
+    today: NaiveDate,
+) -> Result<Response, ApiError> {
+    if date < today {
+        return Err(ApiError::BadRequest(format!("{date} has already passed")));
+    }
+
+    let pinned = queue
+        .pin(word, date)
+        .await
+        .map_err(|err| {
+            if err.is_duplicate_key() {
+                ApiError::Conflict(format!("another word is already pinned to {date}"))
+            } else {
+                err
+            }
+        })?
+        .ok_or(ApiError::NotFound)?;
+
+    tracing::info!("pinned {word} to {date}");
+    Ok((StatusCode::OK, Json(pinned)).into_response())
+}
+
+/// Lets the word pinned to `date` wait its turn like the rest again.
+pub async fn unpin_date(
+    queue: &dyn QueueRepository,
+    date: NaiveDate,
+) -> Result<Response, ApiError> {
+    if !queue.unpin(date).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok((
+        StatusCode::OK,
+        format!("nothing is pinned to {date} anymore"),
+    )
+        .into_response())
+}
+
+/// The gap left between ranks when the queue is renumbered, so most moves only change the rank of
+/// the item that moves.
+pub const RANK_STEP: i64 = 1000;
+
+/// The queue in line, numbered from 1. `suggested_by` is left for the caller to fill in.
+pub async fn list_queue(queue: &dyn QueueRepository) -> Result<Vec<DtoQueueEntry>, ApiError> {
+    Ok(queue
+        .list()
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| DtoQueueEntry {
+            position: i + 1,
+            item,
+            suggested_by: None,
+        })
+        .collect())
+}
+
+pub async fn get_queue_entry(
+    queue: &dyn QueueRepository,
+    id: ObjectId,
+) -> Result<DtoQueueEntry, ApiError> {
+    list_queue(queue)
+        .await?
+        .into_iter()
+        .find(|entry| entry.item._id == id)
+        .ok_or(ApiError::NotFound)
+}
+
+/// Moves the queued item to `position`, counting from 1. Positions past the end move it to the
+/// back. Pinned items keep their place in line too, they still jump ahead on their day.
+pub async fn move_queue_item(
+    queue: &dyn QueueRepository,
+    id: ObjectId,
+    position: usize,
+) -> Result<DtoQueueEntry, ApiError> {
+    let mut items = queue.list().await?;
+    let from = items
+        .iter()
+        .position(|item| item._id == id)
+        .ok_or(ApiError::NotFound)?;
+    let item = items.remove(from);
+    let to = position.clamp(1, items.len() + 1) - 1;
+
+    let before = to.checked_sub(1).map(|i| items[i].rank);
+    let after = items.get(to).map(|next| next.rank);
+    let rank = match (before, after) {
+        (Some(before), Some(after)) if after - before > 1 => Some(before + (after - before) / 2),
+        (Some(_), Some(_)) => None,
+        (None, Some(after)) => after.checked_sub(RANK_STEP),
+        (Some(before), None) => before.checked_add(RANK_STEP),
+        (None, None) => Some(item.rank),
+    };
+
+    match rank {
+        Some(rank) => {
+            if !queue.set_rank(id, rank).await? {
+                return Err(ApiError::NotFound);
+            }
+        }
+        // No room between its neighbours, so everything is spaced out again.
+        None => {
+            items.insert(to, item.clone());
+            let ranks: Vec<_> = items
+                .iter()
+                .enumerate()
+                .filter_map(|(i, queued)| {
+                    let rank = (i as i64 + 1) * RANK_STEP;
+                    (queued.rank != rank).then_some((queued._id, rank))
+                })
+                .collect();
+            queue.set_ranks(&ranks).await?;
+        }
+    }
+
+    tracing::info!("moved {} to position {}", item.word.word, to + 1);
+    get_queue_entry(queue, id).await
+}
+
+/// Takes the item off the queue without featuring it. Its word stays.
+pub async fn remove_queue_item(
+    queue: &dyn QueueRepository,
+    id: ObjectId,
+) -> Result<Response, ApiError> {
+    if !queue.delete(id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    Ok((StatusCode::OK, "removed from the queue".to_string()).into_response())
+}
+
+pub async fn get_history_page(
+    history: &dyn HistoryRepository,
+    query: DtoHistoryQuery,
+) -> Result<Response, ApiError> {
+    let (page, per_page) = query.page();
+    let range = query.range();
+
+    let items = history
+        .list(range, (page - 1).saturating_mul(per_page), per_page)
+        .await?;
+    let total = history.count(range).await?;
+
+    let history_page = DtoHistoryPage {
+        page,
+        per_page,
+        total,
+        items,
+    };
+    Ok((StatusCode::OK, Json(history_page)).into_response())
+}
+
+pub async fn get_wotd_on(
+    history: &dyn HistoryRepository,
+    date: NaiveDate,
+) -> Result<Response, ApiError> {
+    let entry = history
+        .find_by_date(date)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok((StatusCode::OK, Json(entry)).into_response())
+}
+
+/// Removes `user_id` as the creator of every word, including the copies of words in the queue
+/// and the history, for when their account is deleted. The words themselves stay.
+pub async fn anonymise_creator(
+    words: &dyn WordRepository,
+    queue: &dyn QueueRepository,
+    history: &dyn HistoryRepository,
+    user_id: ObjectId,
+) -> Result<(), ApiError> {
+    let anonymised = words.anonymise_creator(user_id).await?;
+    let queued = queue.anonymise_creator(user_id).await?;
+    let archived = history.anonymise_creator(user_id).await?;
+
+    tracing::info!(
+        "anonymised {anonymised} word(s), {queued} queued and {archived} archived word(s) of \
+         {user_id}"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod word_logic_tests {
+    use super::*;
+    use crate::{
+        word_history::DateRange,
+        word_memory::{MemoryHistoryRepository, MemoryQueueRepository, MemoryRotationRepository},
+    };
+    use std::sync::Arc;
+
+    fn suggestion(word: &str) -> DtoWotdCreate {
+        DtoWotdCreate {
+            word: word.to_string(),
+            definition: "a definition".to_string(),
+            sentence: format!("A sentence with {word}."),
+        }
+    }
+
+    #[tokio::test]
+    async fn rotation_archives_the_head() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let history = MemoryHistoryRepository::default();
+        suggest_word(&queue, ObjectId::new(), suggestion("petrichor"))
+            .await
+            .unwrap();
+        let date = "2024-02-14".parse().unwrap();
+
+        let rotated = rotate_wotd(&queue, &history, date).await.unwrap().unwrap();
+
+        assert_eq!("petrichor", rotated.word.word);
+        assert!(queue.peek(date).await.unwrap().is_none());
+        let archived = history.find_by_date(date).await.unwrap().unwrap();
+        assert_eq!(rotated.word._id, archived.word._id);
+        assert_eq!(1, history.count(DateRange::default()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_the_lease_holder_rotates() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let history = MemoryHistoryRepository::default();
+        let rotations = MemoryRotationRepository::default();
+        for word in ["petrichor", "sonder"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        let date = "2024-02-14".parse().unwrap();
+        let lease_until = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+
+        let first = run_scheduled_rotation(&queue, &history, &rotations, "a", date, lease_until)
+            .await
+            .unwrap()
+            .unwrap();
+        let second = run_scheduled_rotation(&queue, &history, &rotations, "b", date, lease_until)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            RotationOutcome::Rotated {
+                word: "petrichor".to_string()
+            },
+            first.outcome
+        );
+        assert!(second.is_none());
+        assert_eq!("sonder", queue.peek(date).await.unwrap().unwrap().word.word);
+        assert_eq!("a", rotations.last_run().await.unwrap().unwrap().holder);
+    }
+
+    #[tokio::test]
+    async fn rotation_features_the_pinned_word_on_its_day() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let history = MemoryHistoryRepository::default();
+        for word in ["petrichor", "valentine"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        let (today, date) = ("2024-02-01".parse().unwrap(), "2024-02-14".parse().unwrap());
+        pin_word(&queue, "valentine", date, today).await.unwrap();
+
+        let rotated = rotate_wotd(&queue, &history, date).await.unwrap().unwrap();
+
+        assert_eq!("valentine", rotated.word.word);
+        let err = pin_word(&queue, "petrichor", today.pred_opt().unwrap(), today)
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::BAD_REQUEST, err.status());
+    }
+
+    #[tokio::test]
+    async fn pinned_word_is_the_word_of_its_day() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let history = MemoryHistoryRepository::default();
+        for word in ["valentine", "sonder"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        let (eve, date) = ("2024-02-13".parse().unwrap(), "2024-02-14".parse().unwrap());
+        pin_word(&queue, "valentine", date, eve).await.unwrap();
+
+        rotate_wotd(&queue, &history, eve).await.unwrap();
+        rotate_wotd(&queue, &history, date).await.unwrap();
+
+        let on_eve = history.find_by_date(eve).await.unwrap().unwrap();
+        assert_eq!("sonder", on_eve.word.word);
+        let on_date = history.find_by_date(date).await.unwrap().unwrap();
+        assert_eq!("valentine", on_date.word.word);
+    }
+
+    /// Archives nothing, as if the database went away halfway through a rotation.
+    struct FailingHistoryRepository;
+
+    #[async_trait::async_trait]
+    impl HistoryRepository for FailingHistoryRepository {
+        async fn insert(&self, _entry: &WotdHistoryModel) -> Result<(), ApiError> {
+            Err(ApiError::internal(anyhow::anyhow!("the history is gone")))
+        }
+
+        async fn find_by_date(
+            &self,
+            _date: NaiveDate,
+        ) -> Result<Option<WotdHistoryModel>, ApiError> {
+            Ok(None)
+        }
+
+        async fn list(
+            &self,
+            _range: DateRange,
+            _skip: u64,
+            _limit: u64,
+        ) -> Result<Vec<WotdHistoryModel>, ApiError> {
+            Ok(Vec::new())
+        }
+
+        async fn count(&self, _range: DateRange) -> Result<u64, ApiError> {
+            Ok(0)
+        }
+
+        async fn anonymise_creator(&self, _user_id: ObjectId) -> Result<u64, ApiError> {
+            Ok(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_archive_keeps_the_word_queued() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        for word in ["petrichor", "sonder"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        // Both may have been suggested within the same millisecond.
+        for (rank, item) in queue.list().await.unwrap().into_iter().enumerate() {
+            queue.set_rank(item._id, rank as i64).await.unwrap();
+        }
+        let date = "2024-02-14".parse().unwrap();
+
+        let err = rotate_wotd(&queue, &FailingHistoryRepository, date)
+            .await
+            .unwrap_err();
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, err.status());
+        assert_eq!(
+            "petrichor",
+            queue.peek(date).await.unwrap().unwrap().word.word
+        );
+        assert_eq!(2, queue.list().await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn empty_queue_does_not_rotate() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let history = MemoryHistoryRepository::default();
+
+        let rotated = rotate_wotd(&queue, &history, "2024-02-14".parse().unwrap())
+            .await
+            .unwrap();
+
+        assert!(rotated.is_none());
+        assert_eq!(0, history.count(DateRange::default()).await.unwrap());
+    }
+
+    async fn queued_words(queue: &dyn QueueRepository) -> Vec<String> {
+        list_queue(queue)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.item.word.word)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn moved_items_take_their_new_position() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        for word in ["petrichor", "sonder", "valentine"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        let valentine = queue.find_by_word("valentine").await.unwrap().unwrap();
+
+        let moved = move_queue_item(&queue, valentine._id, 1).await.unwrap();
+        assert_eq!(1, moved.position);
+        assert_eq!(
+            vec!["valentine", "petrichor", "sonder"],
+            queued_words(&queue).await
+        );
+
+        let moved = move_queue_item(&queue, valentine._id, 2).await.unwrap();
+        assert_eq!(2, moved.position);
+        assert_eq!(
+            vec!["petrichor", "valentine", "sonder"],
+            queued_words(&queue).await
+        );
+
+        let moved = move_queue_item(&queue, valentine._id, 99).await.unwrap();
+        assert_eq!(3, moved.position);
+        assert_eq!(
+            vec!["petrichor", "sonder", "valentine"],
+            queued_words(&queue).await
+        );
+    }
+
+    #[tokio::test]
+    async fn crowded_ranks_are_spaced_out() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        for word in ["petrichor", "sonder", "valentine"] {
+            suggest_word(&queue, ObjectId::new(), suggestion(word))
+                .await
+                .unwrap();
+        }
+        for (rank, entry) in list_queue(&queue).await.unwrap().into_iter().enumerate() {
+            queue.set_rank(entry.item._id, rank as i64).await.unwrap();
+        }
+        let valentine = queue.find_by_word("valentine").await.unwrap().unwrap();
+
+        move_queue_item(&queue, valentine._id, 2).await.unwrap();
+
+        let ranks: Vec<_> = queue.list().await.unwrap().iter().map(|i| i.rank).collect();
+        assert_eq!(vec![RANK_STEP, 2 * RANK_STEP, 3 * RANK_STEP], ranks);
+        assert_eq!(
+            vec!["petrichor", "valentine", "sonder"],
+            queued_words(&queue).await
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_items_leave_the_queue() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        let suggester = ObjectId::new();
+        suggest_word(&queue, suggester, suggestion("petrichor"))
+            .await
+            .unwrap();
+        let entry = list_queue(&queue).await.unwrap().remove(0);
+        assert_eq!(Some(suggester), entry.item.suggested_by_id);
+
+        remove_queue_item(&queue, entry.item._id).await.unwrap();
+
+        assert!(queued_words(&queue).await.is_empty());
+        let err = remove_queue_item(&queue, entry.item._id).await.unwrap_err();
+        assert_eq!(StatusCode::NOT_FOUND, err.status());
+        let err = move_queue_item(&queue, entry.item._id, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::NOT_FOUND, err.status());
+    }
+}
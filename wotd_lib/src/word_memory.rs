@@ -0,0 +1,453 @@
+use std::{
+    cmp::Reverse,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
+
+use crate::{
+    word_history::{DateRange, WotdHistoryModel},
+    word_models::WordModel,
+    word_queue::QueueItemWordModel,
+    word_repository::{HistoryRepository, QueueRepository, RotationRepository, WordRepository},
+    word_rotation::{RotationLeaseModel, RotationRunModel, ROTATION_LEASE},
+};
+
+// The in-memory counterpart of `word_mongo`, see `user_lib::user_memory`.
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Default)]
+pub struct MemoryWordRepository {
+    words: Mutex<Vec<WordModel>>,
+}
+
+#[async_trait]
+impl WordRepository for MemoryWordRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<WordModel>, ApiError> {
+        Ok(lock(&self.words)
+            .iter()
+            .find(|model| model.word == word)
+            .cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<WordModel>, ApiError> {
+        Ok(lock(&self.words).clone())
+    }
+
+    async fn insert(&self, word: &WordModel) -> Result<(), ApiError> {
+        let mut words = lock(&self.words);
+        if words.iter().any(|taken| taken.word == word.word) {
+            return Err(ApiError::Duplicate);
+        }
+        words.push(word.clone());
+        Ok(())
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let mut modified = 0;
+        for word in lock(&self.words).iter_mut() {
+            if word.created_by_id == Some(user_id) {
+                word.created_by_id = None;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+}
+
+pub struct MemoryQueueRepository {
+    /// Where suggested words are stored before they are queued.
+    words: Arc<MemoryWordRepository>,
+    items: Mutex<Vec<QueueItemWordModel>>,
+}
+
+impl MemoryQueueRepository {
+    pub fn new(words: Arc<MemoryWordRepository>) -> MemoryQueueRepository {
+        MemoryQueueRepository {
+            words,
+            items: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl QueueRepository for MemoryQueueRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<QueueItemWordModel>, ApiError> {
+        Ok(lock(&self.items)
+            .iter()
+            .find(|item| item.word.word == word)
+            .cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<QueueItemWordModel>, ApiError> {
+        let mut items = lock(&self.items).clone();
+        items.sort_by_key(|item| (item.rank, item.added_at));
+        Ok(items)
+    }
+
+    /// Holds both locks throughout, so concurrent suggestions of a word cannot both queue it.
+    async fn enqueue(&self, item: &QueueItemWordModel) -> Result<QueueItemWordModel, ApiError> {
+        let mut words = lock(&self.words.words);
+        let mut items = lock(&self.items);
+        if items.iter().any(|taken| {
+            taken.word.word == item.word.word
+                || (item.pinned_for.is_some() && taken.pinned_for == item.pinned_for)
+        }) {
+            return Err(ApiError::Duplicate);
+        }
+
+        let word = match words.iter().find(|word| word.word == item.word.word) {
+            Some(word) => word.clone(),
+            None => {
+                words.push(item.word.clone());
+                item.word.clone()
+            }
+        };
+        let queued = QueueItemWordModel {
+            word,
+            ..item.clone()
+        };
+        items.push(queued.clone());
+        Ok(queued)
+    }
+
+    async fn peek(&self, date: NaiveDate) -> Result<Option<QueueItemWordModel>, ApiError> {
+        let items = lock(&self.items);
+        if let Some(pinned) = items.iter().find(|item| item.pinned_for == Some(date)) {
+            return Ok(Some(pinned.clone()));
+        }
+
+        Ok(items
+            .iter()
+            .filter(|item| item.pinned_for.map_or(true, |pinned_for| pinned_for < date))
+            .min_by_key(|item| (item.rank, item.added_at))
+            .cloned())
+    }
+
+    async fn pin(
+        &self,
+        word: &str,
+        date: NaiveDate,
+    ) -> Result<Option<QueueItemWordModel>, ApiError> {
+        let mut items = lock(&self.items);
+        if items
+            .iter()
+            .any(|item| item.pinned_for == Some(date) && item.word.word != word)
+        {
+            return Err(ApiError::Duplicate);
+        }
+
+        Ok(items
+            .iter_mut()
+            .find(|item| item.word.word == word)
+            .map(|item| {
+                item.pinned_for = Some(date);
+                item.clone()
+            }))
+    }
+
+    async fn unpin(&self, date: NaiveDate) -> Result<bool, ApiError> {
+        let mut items = lock(&self.items);
+        match items.iter_mut().find(|item| item.pinned_for == Some(date)) {
+            Some(item) => {
+                item.pinned_for = None;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_rank(&self, id: ObjectId, rank: i64) -> Result<bool, ApiError> {
+        let mut items = lock(&self.items);
+        match items.iter_mut().find(|item| item._id == id) {
+            Some(item) => {
+                item.rank = rank;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn set_ranks(&self, ranks: &[(ObjectId, i64)]) -> Result<(), ApiError> {
+        let mut items = lock(&self.items);
+        for (id, rank) in ranks {
+            if let Some(item) = items.iter_mut().find(|item| item._id == *id) {
+                item.rank = *rank;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let mut items = lock(&self.items);
+        let before = items.len();
+        items.retain(|item| item._id != id);
+        Ok(items.len() < before)
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let mut modified = 0;
+        for item in lock(&self.items).iter_mut() {
+            if item.word.created_by_id == Some(user_id) {
+                item.word.created_by_id = None;
+                modified += 1;
+            }
+            if item.suggested_by_id == Some(user_id) {
+                item.suggested_by_id = None;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryHistoryRepository {
+    entries: Mutex<Vec<WotdHistoryModel>>,
+}
+
+impl MemoryHistoryRepository {
+    /// The entries in `range`, newest first.
+    fn in_range(&self, range: DateRange) -> Vec<WotdHistoryModel> {
+        let mut entries: Vec<_> = lock(&self.entries)
+            .iter()
+            .filter(|entry| range.contains(entry.date))
+            .cloned()
+            .collect();
+        entries.sort_by_key(|entry| Reverse((entry.date, entry.featured_at)));
+        entries
+    }
+}
+
+#[async_trait]
+impl HistoryRepository for MemoryHistoryRepository {
+    async fn insert(&self, entry: &WotdHistoryModel) -> Result<(), ApiError> {
+        lock(&self.entries).push(entry.clone());
+        Ok(())
+    }
+
+    async fn find_by_date(&self, date: NaiveDate) -> Result<Option<WotdHistoryModel>, ApiError> {
+        Ok(lock(&self.entries)
+            .iter()
+            .filter(|entry| entry.date == date)
+            .max_by_key(|entry| entry.featured_at)
+            .cloned())
+    }
+
+    async fn list(
+        &self,
+        range: DateRange,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<WotdHistoryModel>, ApiError> {
+        Ok(self
+            .in_range(range)
+            .into_iter()
+            .skip(usize::try_from(skip).unwrap_or(usize::MAX))
+            .take(usize::try_from(limit).unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    async fn count(&self, range: DateRange) -> Result<u64, ApiError> {
+        Ok(self.in_range(range).len() as u64)
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let mut modified = 0;
+        for entry in lock(&self.entries).iter_mut() {
+            if entry.word.created_by_id == Some(user_id) {
+                entry.word.created_by_id = None;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRotationRepository {
+    lease: Mutex<Option<RotationLeaseModel>>,
+    runs: Mutex<Vec<RotationRunModel>>,
+}
+
+#[async_trait]
+impl RotationRepository for MemoryRotationRepository {
+    async fn acquire_lease(
+        &self,
+        holder: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<bool, ApiError> {
+        let mut lease = lock(&self.lease);
+        if lease
+            .as_ref()
+            .is_some_and(|held| held.holder != holder && held.expires_at > now)
+        {
+            return Ok(false);
+        }
+
+        *lease = Some(RotationLeaseModel {
+            _id: ROTATION_LEASE.to_string(),
+            holder: holder.to_string(),
+            expires_at,
+        });
+        Ok(true)
+    }
+
+    async fn record_run(&self, run: &RotationRunModel) -> Result<(), ApiError> {
+        lock(&self.runs).push(run.clone());
+        Ok(())
+    }
+
+    async fn last_run(&self) -> Result<Option<RotationRunModel>, ApiError> {
+        Ok(lock(&self.runs)
+            .iter()
+            .max_by_key(|run| run.started_at)
+            .cloned())
+    }
+}
+
+#[cfg(test)]
+mod word_memory_tests {
+    use super::*;
+    use bson::DateTime;
+
+    fn word(word: &str) -> WordModel {
+        WordModel {
+            _id: ObjectId::new(),
+            created_by_id: None,
+            word: word.to_string(),
+            definition: "a definition".to_string(),
+            sentence: format!("A sentence with {word}."),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn item(name: &str, added_at: i64) -> QueueItemWordModel {
+        QueueItemWordModel {
+            _id: ObjectId::new(),
+            word: word(name),
+            added_at: DateTime::from_millis(added_at),
+            rank: added_at,
+            pinned_for: None,
+            suggested_by_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_words_are_unique() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        queue.enqueue(&item("petrichor", 1)).await.unwrap();
+
+        let err = queue.enqueue(&item("petrichor", 2)).await.unwrap_err();
+
+        assert!(err.is_duplicate_key());
+    }
+
+    #[tokio::test]
+    async fn enqueue_stores_new_words_and_reuses_existing_ones() {
+        let words = Arc::new(MemoryWordRepository::default());
+        let queue = MemoryQueueRepository::new(words.clone());
+        let sonder = word("sonder");
+        words.insert(&sonder).await.unwrap();
+
+        let queued = queue.enqueue(&item("sonder", 1)).await.unwrap();
+        queue.enqueue(&item("petrichor", 2)).await.unwrap();
+
+        assert_eq!(sonder._id, queued.word._id);
+        assert!(words.find_by_word("petrichor").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn history_pages_are_newest_first() {
+        let history = MemoryHistoryRepository::default();
+        for (name, date) in [("sonder", "2024-02-13"), ("petrichor", "2024-02-14")] {
+            let entry = WotdHistoryModel {
+                _id: ObjectId::new(),
+                date: date.parse().unwrap(),
+                word: word(name),
+                featured_at: DateTime::now(),
+            };
+            history.insert(&entry).await.unwrap();
+        }
+
+        let first = history.list(DateRange::default(), 0, 1).await.unwrap();
+        let second = history.list(DateRange::default(), 1, 1).await.unwrap();
+
+        assert_eq!("petrichor", first[0].word.word);
+        assert_eq!("sonder", second[0].word.word);
+        assert_eq!(2, history.count(DateRange::default()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn peek_is_the_oldest_item() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        queue.enqueue(&item("sonder", 20)).await.unwrap();
+        queue.enqueue(&item("petrichor", 10)).await.unwrap();
+
+        let head = queue.peek(date("2024-02-14")).await.unwrap().unwrap();
+        assert_eq!("petrichor", head.word.word);
+
+        assert!(queue.delete(head._id).await.unwrap());
+        let head = queue.peek(date("2024-02-14")).await.unwrap().unwrap();
+        assert_eq!("sonder", head.word.word);
+    }
+
+    #[tokio::test]
+    async fn pinned_item_waits_for_its_day() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        queue.enqueue(&item("valentine", 10)).await.unwrap();
+        queue.enqueue(&item("sonder", 20)).await.unwrap();
+
+        let pinned = queue.pin("valentine", date("2024-02-14")).await.unwrap();
+
+        assert!(pinned.is_some());
+        let peek = |day| queue.peek(date(day));
+        assert_eq!(
+            "sonder",
+            peek("2024-02-13").await.unwrap().unwrap().word.word
+        );
+        assert_eq!(
+            "valentine",
+            peek("2024-02-14").await.unwrap().unwrap().word.word
+        );
+        assert_eq!(
+            "valentine",
+            peek("2024-02-15").await.unwrap().unwrap().word.word
+        );
+        let err = queue.pin("sonder", date("2024-02-14")).await.unwrap_err();
+        assert!(err.is_duplicate_key());
+    }
+
+    #[tokio::test]
+    async fn rank_decides_the_order() {
+        let queue = MemoryQueueRepository::new(Arc::default());
+        queue.enqueue(&item("sonder", 10)).await.unwrap();
+        let petrichor = queue.enqueue(&item("petrichor", 20)).await.unwrap();
+
+        assert!(queue.set_rank(petrichor._id, 5).await.unwrap());
+
+        let words: Vec<_> = queue
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.word.word)
+            .collect();
+        assert_eq!(vec!["petrichor", "sonder"], words);
+        let head = queue.peek(date("2024-02-14")).await.unwrap().unwrap();
+        assert_eq!("petrichor", head.word.word);
+        assert!(!queue.set_rank(ObjectId::new(), 1).await.unwrap());
+    }
+}
@@ -1,15 +1,58 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
 
 // What do we need to represent with a struct?
 // - MongoDB models
 // - Create API DTOs
 
+pub const WORD_MAX_CHARS: u64 = 64;
+pub const DEFINITION_MAX_CHARS: u64 = 1000;
+pub const SENTENCE_MAX_CHARS: u64 = 500;
+
+/// Words are letters, optionally joined by spaces, hyphens or apostrophes, like "mother-in-law".
+pub fn validate_word(word: &str) -> Result<(), ValidationError> {
+    let starts_with_letter = word.chars().next().is_some_and(char::is_alphabetic);
+    if starts_with_letter
+        && word
+            .chars()
+            .all(|c| c.is_alphabetic() || matches!(c, ' ' | '-' | '\''))
+    {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("word_chars");
+        err.message = Some(
+            "must start with a letter and only contain letters, spaces, hyphens and apostrophes"
+                .into(),
+        );
+        Err(err)
+    }
+}
+
+/// The example sentence has to actually use the word, in any case.
+fn validate_sentence_uses_word(wotd: &DtoWotdCreate) -> Result<(), ValidationError> {
+    if wotd
+        .sentence
+        .to_lowercase()
+        .contains(&wotd.word.to_lowercase())
+    {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("sentence_uses_word");
+        err.message = Some("the sentence must contain the word".into());
+        Err(err)
+    }
+}
+
 /// What is required, and that cannot be generated by the system to create a new WOTD.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_sentence_uses_word", skip_on_field_errors = true))]
 pub struct DtoWotdCreate {
+    #[validate(length(min = 1, max = "WORD_MAX_CHARS"), custom = "validate_word")]
     pub word: String,
+    #[validate(length(min = 1, max = "DEFINITION_MAX_CHARS"))]
     pub definition: String,
+    #[validate(length(min = 1, max = "SENTENCE_MAX_CHARS"))]
     pub sentence: String,
 }
 
@@ -17,7 +60,8 @@ pub struct DtoWotdCreate {
 #[derive(Serialize, Deserialize)]
 pub struct DtoWotd {
     pub _id: ObjectId,
-    pub created_by_id: ObjectId,
+    /// `None` once the user that created the word has deleted their account.
+    pub created_by_id: Option<ObjectId>,
     pub word: String,
     pub definition: String,
     pub sentence: String,
@@ -29,7 +73,8 @@ pub struct DtoWotd {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct WordModel {
     pub _id: ObjectId,
-    pub created_by_id: ObjectId,
+    /// `None` once the user that created the word has deleted their account.
+    pub created_by_id: Option<ObjectId>,
     pub word: String,
     pub definition: String,
     pub sentence: String,
@@ -50,3 +95,45 @@ impl From<WordModel> for DtoWotd {
         }
     }
 }
+
+#[cfg(test)]
+mod word_model_tests {
+    use super::*;
+
+    fn wotd(word: &str, sentence: &str) -> DtoWotdCreate {
+        DtoWotdCreate {
+            word: word.to_string(),
+            definition: "a definition".to_string(),
+            sentence: sentence.to_string(),
+        }
+    }
+
+    #[test]
+    fn words_with_joiners_are_valid() {
+        assert!(wotd("mother-in-law", "My Mother-in-law visited.")
+            .validate()
+            .is_ok());
+        assert!(wotd("o'clock", "It is five o'clock.").validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_words_are_rejected() {
+        let errors = wotd("<script>", "<script>").validate().unwrap_err();
+        assert_eq!("word_chars", errors.field_errors()["word"][0].code);
+
+        let errors = wotd("", "").validate().unwrap_err();
+        assert_eq!("length", errors.field_errors()["word"][0].code);
+    }
+
+    #[test]
+    fn sentence_must_use_word() {
+        let errors = wotd("petrichor", "It smelled nice after the rain.")
+            .validate()
+            .unwrap_err();
+
+        assert_eq!(
+            "sentence_uses_word",
+            errors.field_errors()["__all__"][0].code
+        );
+    }
+}
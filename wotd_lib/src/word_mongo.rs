@@ -0,0 +1,481 @@
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId, DateTime, Document};
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
+use mongodb::{
+    error::{ErrorKind, TRANSIENT_TRANSACTION_ERROR},
+    options::{
+        FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument, UpdateOptions,
+    },
+    ClientSession, Collection,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    word_history::{DateRange, WotdHistoryModel},
+    word_models::WordModel,
+    word_queue::QueueItemWordModel,
+    word_repository::{HistoryRepository, QueueRepository, RotationRepository, WordRepository},
+    word_rotation::{RotationLeaseModel, RotationRunModel, ROTATION_LEASE},
+};
+
+pub struct MongoWordRepository {
+    collection: Collection<WordModel>,
+}
+
+impl MongoWordRepository {
+    pub fn new(collection: Collection<WordModel>) -> MongoWordRepository {
+        MongoWordRepository { collection }
+    }
+}
+
+#[async_trait]
+impl WordRepository for MongoWordRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<WordModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one(doc! { "word": word }, None)
+            .await?)
+    }
+
+    async fn list(&self) -> Result<Vec<WordModel>, ApiError> {
+        let mut cursor = self.collection.find(None, None).await?;
+
+        let mut words = Vec::new();
+        while let Some(word) = cursor.next().await {
+            match word {
+                Ok(w) => words.push(w),
+                Err(err) => {
+                    tracing::warn!("error occured during mongo cursor iteration: {err}")
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    async fn insert(&self, word: &WordModel) -> Result<(), ApiError> {
+        self.collection.insert_one(word, None).await?;
+        Ok(())
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let result = self
+            .collection
+            .update_many(
+                doc! { "created_by_id": user_id },
+                doc! { "$set": { "created_by_id": null } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+}
+
+/// How many times a suggestion is tried when its transaction keeps conflicting with others.
+const ENQUEUE_ATTEMPTS: usize = 3;
+
+pub struct MongoQueueRepository {
+    /// Where suggested words are stored before they are queued.
+    words: Collection<WordModel>,
+    collection: Collection<QueueItemWordModel>,
+}
+
+impl MongoQueueRepository {
+    pub fn new(
+        words: Collection<WordModel>,
+        collection: Collection<QueueItemWordModel>,
+    ) -> MongoQueueRepository {
+        MongoQueueRepository { words, collection }
+    }
+
+    /// Runs [`MongoQueueRepository::enqueue_with_session`] in a transaction, again from the start
+    /// when the server says it conflicted with another one, like a suggestion of the same word.
+    async fn enqueue_in_transaction(
+        &self,
+        session: &mut ClientSession,
+        item: &QueueItemWordModel,
+    ) -> Result<QueueItemWordModel, ApiError> {
+        let mut attempt = 1;
+        loop {
+            let result = match self.enqueue_with_session(session, item).await {
+                Ok(queued) => session.commit_transaction().await.map(|()| queued),
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(queued) => return Ok(queued),
+                Err(err) => {
+                    // Fails when the commit was the problem, there is nothing to abort then.
+                    let _ = session.abort_transaction().await;
+                    if attempt == ENQUEUE_ATTEMPTS
+                        || !err.contains_label(TRANSIENT_TRANSACTION_ERROR)
+                    {
+                        return Err(err.into());
+                    }
+                    tracing::debug!("retrying suggestion of {}: {err}", item.word.word);
+                    attempt += 1;
+                    session.start_transaction(None).await?;
+                }
+            }
+        }
+    }
+
+    async fn enqueue_with_session(
+        &self,
+        session: &mut ClientSession,
+        item: &QueueItemWordModel,
+    ) -> mongodb::error::Result<QueueItemWordModel> {
+        let word = match self
+            .words
+            .find_one_with_session(doc! { "word": &item.word.word }, None, session)
+            .await?
+        {
+            Some(word) => word,
+            None => {
+                self.words
+                    .insert_one_with_session(&item.word, None, session)
+                    .await?;
+                item.word.clone()
+            }
+        };
+
+        let queued = QueueItemWordModel {
+            word,
+            ..item.clone()
+        };
+        self.collection
+            .insert_one_with_session(&queued, None, session)
+            .await?;
+        Ok(queued)
+    }
+
+    /// For standalone servers, which have no transactions. Upserting the word makes concurrent
+    /// suggestions agree on it, and the unique index on the queue lets only one of them queue it.
+    async fn enqueue_with_upsert(
+        &self,
+        item: &QueueItemWordModel,
+    ) -> Result<QueueItemWordModel, ApiError> {
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let word = self
+            .words
+            .find_one_and_update(
+                doc! { "word": &item.word.word },
+                doc! { "$setOnInsert": bson::to_document(&item.word).map_err(ApiError::internal)? },
+                options,
+            )
+            .await?
+            .ok_or_else(|| ApiError::internal(anyhow::anyhow!("upsert returned no document")))?;
+
+        let queued = QueueItemWordModel {
+            word,
+            ..item.clone()
+        };
+        self.collection.insert_one(&queued, None).await?;
+        Ok(queued)
+    }
+}
+
+#[async_trait]
+impl QueueRepository for MongoQueueRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<QueueItemWordModel>, ApiError> {
+        Ok(self
+            .collection
+            .find_one(doc! { "word.word": word }, None)
+            .await?)
+    }
+
+    async fn list(&self) -> Result<Vec<QueueItemWordModel>, ApiError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "rank": 1, "added_at": 1 })
+            .build();
+        let mut cursor = self.collection.find(None, options).await?;
+
+        let mut items = Vec::new();
+        while let Some(item) = cursor.next().await {
+            match item {
+                Ok(i) => items.push(i),
+                Err(err) => {
+                    tracing::warn!("error occured during mongo cursor iteration: {err}")
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// In a transaction where the deployment supports them, with upserts otherwise.
+    async fn enqueue(&self, item: &QueueItemWordModel) -> Result<QueueItemWordModel, ApiError> {
+        let mut session = self.collection.client().start_session(None).await?;
+        match session.start_transaction(None).await {
+            Ok(()) => self.enqueue_in_transaction(&mut session, item).await,
+            Err(err) if matches!(*err.kind, ErrorKind::Transaction { .. }) => {
+                tracing::debug!("queueing {} without a transaction: {err}", item.word.word);
+                self.enqueue_with_upsert(item).await
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn peek(&self, date: NaiveDate) -> Result<Option<QueueItemWordModel>, ApiError> {
+        let date = date.to_string();
+        if let Some(pinned) = self
+            .collection
+            .find_one(doc! { "pinned_for": &date }, None)
+            .await?
+        {
+            return Ok(Some(pinned));
+        }
+
+        let options = FindOneOptions::builder()
+            .sort(doc! { "rank": 1, "added_at": 1 })
+            .build();
+        Ok(self
+            .collection
+            .find_one(
+                doc! { "$or": [{ "pinned_for": null }, { "pinned_for": { "$lt": &date } }] },
+                options,
+            )
+            .await?)
+    }
+
+    async fn pin(
+        &self,
+        word: &str,
+        date: NaiveDate,
+    ) -> Result<Option<QueueItemWordModel>, ApiError> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        Ok(self
+            .collection
+            .find_one_and_update(
+                doc! { "word.word": word },
+                doc! { "$set": { "pinned_for": date.to_string() } },
+                options,
+            )
+            .await?)
+    }
+
+    async fn unpin(&self, date: NaiveDate) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(
+                doc! { "pinned_for": date.to_string() },
+                doc! { "$set": { "pinned_for": null } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count > 0)
+    }
+
+    async fn set_rank(&self, id: ObjectId, rank: i64) -> Result<bool, ApiError> {
+        let result = self
+            .collection
+            .update_one(doc! { "_id": id }, doc! { "$set": { "rank": rank } }, None)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    /// In a transaction where the deployment supports them. Standalone servers take one write
+    /// per item, so a reader may catch the queue half renumbered there.
+    async fn set_ranks(&self, ranks: &[(ObjectId, i64)]) -> Result<(), ApiError> {
+        let mut session = self.collection.client().start_session(None).await?;
+        let in_transaction = match session.start_transaction(None).await {
+            Ok(()) => true,
+            Err(err) if matches!(*err.kind, ErrorKind::Transaction { .. }) => {
+                tracing::debug!("renumbering the queue without a transaction: {err}");
+                false
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // Dropping the session aborts the transaction when one of these fails.
+        for (id, rank) in ranks {
+            self.collection
+                .update_one_with_session(
+                    doc! { "_id": id },
+                    doc! { "$set": { "rank": rank } },
+                    None,
+                    &mut session,
+                )
+                .await?;
+        }
+        if in_transaction {
+            session.commit_transaction().await?;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let result = self.collection.delete_one(doc! { "_id": id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let created = self
+            .collection
+            .update_many(
+                doc! { "word.created_by_id": user_id },
+                doc! { "$set": { "word.created_by_id": null } },
+                None,
+            )
+            .await?;
+        let suggested = self
+            .collection
+            .update_many(
+                doc! { "suggested_by_id": user_id },
+                doc! { "$set": { "suggested_by_id": null } },
+                None,
+            )
+            .await?;
+        Ok(created.modified_count + suggested.modified_count)
+    }
+}
+
+pub struct MongoHistoryRepository {
+    collection: Collection<WotdHistoryModel>,
+}
+
+impl MongoHistoryRepository {
+    pub fn new(collection: Collection<WotdHistoryModel>) -> MongoHistoryRepository {
+        MongoHistoryRepository { collection }
+    }
+}
+
+/// Dates are stored as `YYYY-MM-DD` strings, which compare like the dates.
+fn range_filter(range: DateRange) -> Document {
+    let mut date = Document::new();
+    if let Some(from) = range.from {
+        date.insert("$gte", from.to_string());
+    }
+    if let Some(to) = range.to {
+        date.insert("$lte", to.to_string());
+    }
+
+    if date.is_empty() {
+        doc! {}
+    } else {
+        doc! { "date": date }
+    }
+}
+
+#[async_trait]
+impl HistoryRepository for MongoHistoryRepository {
+    async fn insert(&self, entry: &WotdHistoryModel) -> Result<(), ApiError> {
+        self.collection.insert_one(entry, None).await?;
+        Ok(())
+    }
+
+    async fn find_by_date(&self, date: NaiveDate) -> Result<Option<WotdHistoryModel>, ApiError> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "featured_at": -1 })
+            .build();
+        Ok(self
+            .collection
+            .find_one(doc! { "date": date.to_string() }, options)
+            .await?)
+    }
+
+    async fn list(
+        &self,
+        range: DateRange,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<WotdHistoryModel>, ApiError> {
+        let options = FindOptions::builder()
+            .sort(doc! { "date": -1, "featured_at": -1 })
+            .skip(skip)
+            .limit(i64::try_from(limit).map_err(ApiError::internal)?)
+            .build();
+        let mut cursor = self.collection.find(range_filter(range), options).await?;
+
+        let mut entries = Vec::new();
+        while let Some(entry) = cursor.next().await {
+            match entry {
+                Ok(e) => entries.push(e),
+                Err(err) => {
+                    tracing::warn!("error occured during mongo cursor iteration: {err}")
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn count(&self, range: DateRange) -> Result<u64, ApiError> {
+        Ok(self
+            .collection
+            .count_documents(range_filter(range), None)
+            .await?)
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let result = self
+            .collection
+            .update_many(
+                doc! { "word.created_by_id": user_id },
+                doc! { "$set": { "word.created_by_id": null } },
+                None,
+            )
+            .await?;
+        Ok(result.modified_count)
+    }
+}
+
+pub struct MongoRotationRepository {
+    leases: Collection<RotationLeaseModel>,
+    runs: Collection<RotationRunModel>,
+}
+
+impl MongoRotationRepository {
+    pub fn new(
+        leases: Collection<RotationLeaseModel>,
+        runs: Collection<RotationRunModel>,
+    ) -> MongoRotationRepository {
+        MongoRotationRepository { leases, runs }
+    }
+}
+
+#[async_trait]
+impl RotationRepository for MongoRotationRepository {
+    /// When another holder has the lease, the filter misses it and the upsert breaks the unique
+    /// `_id` instead.
+    async fn acquire_lease(
+        &self,
+        holder: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<bool, ApiError> {
+        let options = UpdateOptions::builder().upsert(true).build();
+        let result = self
+            .leases
+            .update_one(
+                doc! {
+                    "_id": ROTATION_LEASE,
+                    "$or": [{ "expires_at": { "$lte": now } }, { "holder": holder }],
+                },
+                doc! { "$set": { "holder": holder, "expires_at": expires_at } },
+                options,
+            )
+            .await;
+
+        match result.map_err(ApiError::from) {
+            Ok(_) => Ok(true),
+            Err(err) if err.is_duplicate_key() => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn record_run(&self, run: &RotationRunModel) -> Result<(), ApiError> {
+        self.runs.insert_one(run, None).await?;
+        Ok(())
+    }
+
+    async fn last_run(&self) -> Result<Option<RotationRunModel>, ApiError> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "started_at": -1 })
+            .build();
+        Ok(self.runs.find_one(doc! {}, options).await?)
+    }
+}
@@ -1,7 +1,9 @@
 use bson::oid::ObjectId;
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-use crate::word_models::{DtoWotdCreate, WordModel};
+use crate::word_models::{validate_word, DtoWotdCreate, WordModel, WORD_MAX_CHARS};
 
 /// Dto to be used to suggest a word to be added to the Queue.
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,9 +26,46 @@ pub struct DtoQueueItemWord {
 }
 
 /// The final product of user that will go into Database.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct QueueItemWordModel {
     pub _id: ObjectId,
     pub word: WordModel,
     pub added_at: mongodb::bson::DateTime,
+    /// Where the item is in line, lowest first. Starts out as `added_at` in milliseconds, so the
+    /// queue is first come, first served until a moderator moves something.
+    #[serde(default)]
+    pub rank: i64,
+    /// `None` once the user that suggested the word has deleted their account.
+    #[serde(default)]
+    pub suggested_by_id: Option<ObjectId>,
+    /// The day this item has to be the word of the day, instead of waiting its turn. At most one
+    /// item is pinned to a day.
+    #[serde(default)]
+    pub pinned_for: Option<NaiveDate>,
+}
+
+/// Dto to pin a queued word to a day.
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct DtoQueuePin {
+    #[validate(length(min = 1, max = "WORD_MAX_CHARS"), custom = "validate_word")]
+    pub word: String,
+}
+
+/// A queued word as moderators see it.
+#[derive(Serialize, Debug)]
+pub struct DtoQueueEntry {
+    /// Where the item is in line, starting at 1.
+    pub position: usize,
+    #[serde(flatten)]
+    pub item: QueueItemWordModel,
+    /// The username of whoever suggested the word, if their account still exists.
+    pub suggested_by: Option<String>,
+}
+
+/// Dto to move a queued word to another place in line.
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct DtoQueueMove {
+    /// Starting at 1, past the end of the queue moves it to the back.
+    #[validate(range(min = 1))]
+    pub position: usize,
 }
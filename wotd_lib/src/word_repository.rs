@@ -0,0 +1,113 @@
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
+
+use crate::{
+    word_history::{DateRange, WotdHistoryModel},
+    word_models::WordModel,
+    word_queue::QueueItemWordModel,
+    word_rotation::RotationRunModel,
+};
+
+// Like the repositories of `user_lib`, these are all the handlers and `word_logic` know about
+// storage.
+
+#[async_trait]
+pub trait WordRepository: Send + Sync {
+    async fn find_by_word(&self, word: &str) -> Result<Option<WordModel>, ApiError>;
+
+    async fn list(&self) -> Result<Vec<WordModel>, ApiError>;
+
+    /// A word that already exists is a `409 Conflict`.
+    async fn insert(&self, word: &WordModel) -> Result<(), ApiError>;
+
+    /// Clears `created_by_id` on every word created by `user_id`, returning how many there were.
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError>;
+}
+
+/// The words waiting to become the word of the day, in order of their `rank`.
+#[async_trait]
+pub trait QueueRepository: Send + Sync {
+    async fn find_by_word(&self, word: &str) -> Result<Option<QueueItemWordModel>, ApiError>;
+
+    /// Every item in line, items with the same rank in the order they were queued.
+    async fn list(&self) -> Result<Vec<QueueItemWordModel>, ApiError>;
+
+    /// Queues `item` in one go. Its word is stored first, unless a word with the same spelling
+    /// already exists, then that one is queued instead. Returns the item as it was queued.
+    ///
+    /// A word that is already queued is a `409 Conflict`, and leaves nothing behind.
+    async fn enqueue(&self, item: &QueueItemWordModel) -> Result<QueueItemWordModel, ApiError>;
+
+    /// The item to feature on `date`: the one pinned to it, otherwise the first in line. Items
+    /// pinned to later days wait for their day, items pinned to earlier days that were never
+    /// featured take their turn like the rest.
+    async fn peek(&self, date: NaiveDate) -> Result<Option<QueueItemWordModel>, ApiError>;
+
+    /// Pins the queued `word` to `date`, returning the item or `None` when the word is not queued.
+    /// A day that already has another word pinned to it is a `409 Conflict`.
+    async fn pin(
+        &self,
+        word: &str,
+        date: NaiveDate,
+    ) -> Result<Option<QueueItemWordModel>, ApiError>;
+
+    /// Returns whether a word was pinned to `date`. It stays queued.
+    async fn unpin(&self, date: NaiveDate) -> Result<bool, ApiError>;
+
+    /// Returns whether the item is queued.
+    async fn set_rank(&self, id: ObjectId, rank: i64) -> Result<bool, ApiError>;
+
+    /// Sets the rank of every listed item at once, so nobody sees the queue half renumbered.
+    /// Items that are no longer queued are skipped.
+    async fn set_ranks(&self, ranks: &[(ObjectId, i64)]) -> Result<(), ApiError>;
+
+    /// Returns whether the item was still queued.
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError>;
+
+    /// Clears `user_id` as the suggester of every item, and as the creator of every queued copy
+    /// of a word they created, returning how many of those it cleared.
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError>;
+}
+
+/// Every word of the day there has been.
+#[async_trait]
+pub trait HistoryRepository: Send + Sync {
+    async fn insert(&self, entry: &WotdHistoryModel) -> Result<(), ApiError>;
+
+    /// The word featured last on `date`, when the queue rotated more than once that day.
+    async fn find_by_date(&self, date: NaiveDate) -> Result<Option<WotdHistoryModel>, ApiError>;
+
+    /// Newest first, skipping the first `skip` entries in `range`.
+    async fn list(
+        &self,
+        range: DateRange,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<WotdHistoryModel>, ApiError>;
+
+    async fn count(&self, range: DateRange) -> Result<u64, ApiError>;
+
+    /// Clears the creator of every archived copy of a word created by `user_id`, returning how
+    /// many there were.
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError>;
+}
+
+/// What the rotation scheduler keeps, shared by every replica.
+#[async_trait]
+pub trait RotationRepository: Send + Sync {
+    /// Gives the rotation lease to `holder` until `expires_at`, unless another holder has it
+    /// past `now`. Returns whether `holder` has it now.
+    async fn acquire_lease(
+        &self,
+        holder: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<bool, ApiError>;
+
+    async fn record_run(&self, run: &RotationRunModel) -> Result<(), ApiError>;
+
+    /// The run that started last.
+    async fn last_run(&self) -> Result<Option<RotationRunModel>, ApiError>;
+}
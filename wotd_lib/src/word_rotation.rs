@@ -0,0 +1,43 @@
+use bson::oid::ObjectId;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// The lease every replica's scheduler competes for before rotating the queue.
+pub const ROTATION_LEASE: &str = "wotd_rotation";
+
+/// Who may rotate the queue until when. Only one holder has it at a time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RotationLeaseModel {
+    /// The name of the lease, [`ROTATION_LEASE`].
+    pub _id: String,
+    pub holder: String,
+    pub expires_at: mongodb::bson::DateTime,
+}
+
+/// How a scheduled rotation went.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum RotationOutcome {
+    Rotated {
+        word: String,
+    },
+    /// There was nothing in the queue to rotate.
+    EmptyQueue,
+    Failed {
+        error: String,
+    },
+}
+
+/// A scheduled rotation, kept so admins can see whether the scheduler is doing its job.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RotationRunModel {
+    pub _id: ObjectId,
+    /// The replica that held the lease.
+    pub holder: String,
+    /// The day the rotation was for.
+    pub date: NaiveDate,
+    pub started_at: mongodb::bson::DateTime,
+    pub finished_at: mongodb::bson::DateTime,
+    #[serde(flatten)]
+    pub outcome: RotationOutcome,
+}
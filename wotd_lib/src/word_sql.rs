@@ -0,0 +1,690 @@
+use async_trait::async_trait;
+use bson::{oid::ObjectId, DateTime};
+use chrono::NaiveDate;
+use error_lib::api_error::ApiError;
+use sqlx::{
+    any::{AnyArguments, AnyRow},
+    query::Query,
+    Any, AnyPool, Row, TypeInfo, ValueRef,
+};
+
+use crate::{
+    word_history::{DateRange, WotdHistoryModel},
+    word_models::WordModel,
+    word_queue::QueueItemWordModel,
+    word_repository::{HistoryRepository, QueueRepository, RotationRepository, WordRepository},
+    word_rotation::{RotationRunModel, ROTATION_LEASE},
+};
+
+// The relational counterpart of `word_mongo`, see `user_lib::user_sql`.
+
+/// Joins every queue item with its word, the columns of `words` keep their names.
+const QUEUE_SELECT: &str = "SELECT queue_words.id AS queue_id, queue_words.added_at, \
+                            queue_words.rank, queue_words.suggested_by_id, \
+                            queue_words.pinned_for, words.* \
+                            FROM queue_words JOIN words ON words.id = queue_words.word_id";
+
+/// Joins every history entry with its word, like [`QUEUE_SELECT`].
+const HISTORY_SELECT: &str = "SELECT wotd_history.id AS history_id, wotd_history.date, \
+                              wotd_history.featured_at, words.* \
+                              FROM wotd_history JOIN words ON words.id = wotd_history.word_id";
+
+/// Both ends of `range` as `$1` and `$2`, which can be NULL.
+const HISTORY_RANGE: &str = "($1 IS NULL OR wotd_history.date >= $1) \
+                             AND ($2 IS NULL OR wotd_history.date <= $2)";
+
+const INSERT_WORD: &str = "INSERT INTO words (id, created_by_id, word, definition, sentence, \
+                           created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)";
+
+/// Binds every column of `word` to `sql`, an [`INSERT_WORD`].
+fn insert_word<'q>(sql: &'q str, word: &'q WordModel) -> Query<'q, Any, AnyArguments<'q>> {
+    sqlx::query(sql)
+        .bind(word._id.to_hex())
+        .bind(word.created_by_id.map(|id| id.to_hex()))
+        .bind(&word.word)
+        .bind(&word.definition)
+        .bind(&word.sentence)
+        .bind(word.created_at.timestamp_millis())
+        .bind(word.updated_at.timestamp_millis())
+}
+
+fn object_id(row: &AnyRow, column: &str) -> Result<ObjectId, ApiError> {
+    ObjectId::parse_str(row.try_get::<String, _>(column)?).map_err(ApiError::internal)
+}
+
+fn date(row: &AnyRow, column: &str) -> Result<DateTime, ApiError> {
+    Ok(DateTime::from_millis(row.try_get(column)?))
+}
+
+fn word_from_row(row: &AnyRow) -> Result<WordModel, ApiError> {
+    // The Any driver cannot decode a NULL into an `Option`, see `user_sql::optional`.
+    let created_by_id = match row.try_get_raw("created_by_id")?.type_info().name() {
+        "NULL" => None,
+        _ => Some(object_id(row, "created_by_id")?),
+    };
+
+    Ok(WordModel {
+        _id: object_id(row, "id")?,
+        created_by_id,
+        word: row.try_get("word")?,
+        definition: row.try_get("definition")?,
+        sentence: row.try_get("sentence")?,
+        created_at: date(row, "created_at")?,
+        updated_at: date(row, "updated_at")?,
+    })
+}
+
+fn queue_item_from_row(row: &AnyRow) -> Result<QueueItemWordModel, ApiError> {
+    let pinned_for = match row.try_get_raw("pinned_for")?.type_info().name() {
+        "NULL" => None,
+        _ => Some(
+            row.try_get::<String, _>("pinned_for")?
+                .parse()
+                .map_err(ApiError::internal)?,
+        ),
+    };
+
+    let suggested_by_id = match row.try_get_raw("suggested_by_id")?.type_info().name() {
+        "NULL" => None,
+        _ => Some(object_id(row, "suggested_by_id")?),
+    };
+
+    Ok(QueueItemWordModel {
+        _id: object_id(row, "queue_id")?,
+        word: word_from_row(row)?,
+        added_at: date(row, "added_at")?,
+        rank: row.try_get("rank")?,
+        suggested_by_id,
+        pinned_for,
+    })
+}
+
+fn history_entry_from_row(row: &AnyRow) -> Result<WotdHistoryModel, ApiError> {
+    Ok(WotdHistoryModel {
+        _id: object_id(row, "history_id")?,
+        date: row
+            .try_get::<String, _>("date")?
+            .parse()
+            .map_err(ApiError::internal)?,
+        word: word_from_row(row)?,
+        featured_at: date(row, "featured_at")?,
+    })
+}
+
+pub struct SqlWordRepository {
+    pool: AnyPool,
+}
+
+impl SqlWordRepository {
+    pub fn new(pool: AnyPool) -> SqlWordRepository {
+        SqlWordRepository { pool }
+    }
+}
+
+#[async_trait]
+impl WordRepository for SqlWordRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<WordModel>, ApiError> {
+        sqlx::query("SELECT * FROM words WHERE word = $1")
+            .bind(word)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| word_from_row(&row))
+            .transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<WordModel>, ApiError> {
+        sqlx::query("SELECT * FROM words ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(word_from_row)
+            .collect()
+    }
+
+    async fn insert(&self, word: &WordModel) -> Result<(), ApiError> {
+        insert_word(INSERT_WORD, word).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let result = sqlx::query("UPDATE words SET created_by_id = NULL WHERE created_by_id = $1")
+            .bind(user_id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+pub struct SqlQueueRepository {
+    pool: AnyPool,
+}
+
+impl SqlQueueRepository {
+    pub fn new(pool: AnyPool) -> SqlQueueRepository {
+        SqlQueueRepository { pool }
+    }
+}
+
+#[async_trait]
+impl QueueRepository for SqlQueueRepository {
+    async fn find_by_word(&self, word: &str) -> Result<Option<QueueItemWordModel>, ApiError> {
+        sqlx::query(&format!("{QUEUE_SELECT} WHERE words.word = $1"))
+            .bind(word)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| queue_item_from_row(&row))
+            .transpose()
+    }
+
+    async fn list(&self) -> Result<Vec<QueueItemWordModel>, ApiError> {
+        sqlx::query(&format!(
+            "{QUEUE_SELECT} ORDER BY queue_words.rank, queue_words.added_at"
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(queue_item_from_row)
+        .collect()
+    }
+
+    /// In a transaction. A concurrent suggestion of the same word waits for this one on the
+    /// unique keys, then finds the word stored and queued.
+    async fn enqueue(&self, item: &QueueItemWordModel) -> Result<QueueItemWordModel, ApiError> {
+        let mut tx = self.pool.begin().await?;
+
+        let insert = format!("{INSERT_WORD} ON CONFLICT (word) DO NOTHING");
+        insert_word(&insert, &item.word).execute(&mut *tx).await?;
+        let row = sqlx::query("SELECT * FROM words WHERE word = $1")
+            .bind(&item.word.word)
+            .fetch_one(&mut *tx)
+            .await?;
+        let queued = QueueItemWordModel {
+            word: word_from_row(&row)?,
+            ..item.clone()
+        };
+
+        sqlx::query(
+            "INSERT INTO queue_words (id, word_id, added_at, rank, suggested_by_id, pinned_for) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(queued._id.to_hex())
+        .bind(queued.word._id.to_hex())
+        .bind(queued.added_at.timestamp_millis())
+        .bind(queued.rank)
+        .bind(queued.suggested_by_id.map(|id| id.to_hex()))
+        .bind(queued.pinned_for.map(|date| date.to_string()))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(queued)
+    }
+
+    /// The item pinned to `date` sorts before all others.
+    async fn peek(&self, date: NaiveDate) -> Result<Option<QueueItemWordModel>, ApiError> {
+        sqlx::query(&format!(
+            "{QUEUE_SELECT} \
+             WHERE queue_words.pinned_for IS NULL OR queue_words.pinned_for <= $1 \
+             ORDER BY CASE WHEN queue_words.pinned_for = $1 THEN 0 ELSE 1 END, \
+             queue_words.rank, queue_words.added_at LIMIT 1"
+        ))
+        .bind(date.to_string())
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| queue_item_from_row(&row))
+        .transpose()
+    }
+
+    async fn pin(
+        &self,
+        word: &str,
+        date: NaiveDate,
+    ) -> Result<Option<QueueItemWordModel>, ApiError> {
+        let result = sqlx::query(
+            "UPDATE queue_words SET pinned_for = $1 \
+             WHERE word_id = (SELECT id FROM words WHERE word = $2)",
+        )
+        .bind(date.to_string())
+        .bind(word)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.find_by_word(word).await
+    }
+
+    async fn unpin(&self, date: NaiveDate) -> Result<bool, ApiError> {
+        let result = sqlx::query("UPDATE queue_words SET pinned_for = NULL WHERE pinned_for = $1")
+            .bind(date.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_rank(&self, id: ObjectId, rank: i64) -> Result<bool, ApiError> {
+        let result = sqlx::query("UPDATE queue_words SET rank = $1 WHERE id = $2")
+            .bind(rank)
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// In a transaction.
+    async fn set_ranks(&self, ranks: &[(ObjectId, i64)]) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        for (id, rank) in ranks {
+            sqlx::query("UPDATE queue_words SET rank = $1 WHERE id = $2")
+                .bind(rank)
+                .bind(id.to_hex())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<bool, ApiError> {
+        let result = sqlx::query("DELETE FROM queue_words WHERE id = $1")
+            .bind(id.to_hex())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Only the suggester, queued words are the rows of `words`, so there is no copy left to
+    /// anonymise once [`SqlWordRepository::anonymise_creator`] ran.
+    async fn anonymise_creator(&self, user_id: ObjectId) -> Result<u64, ApiError> {
+        let result =
+            sqlx::query("UPDATE queue_words SET suggested_by_id = NULL WHERE suggested_by_id = $1")
+                .bind(user_id.to_hex())
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+pub struct SqlHistoryRepository {
+    pool: AnyPool,
+}
+
+impl SqlHistoryRepository {
+    pub fn new(pool: AnyPool) -> SqlHistoryRepository {
+        SqlHistoryRepository { pool }
+    }
+}
+
+#[async_trait]
+impl HistoryRepository for SqlHistoryRepository {
+    /// Like the queue, the entry only refers to its word, which has to be stored already.
+    async fn insert(&self, entry: &WotdHistoryModel) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO wotd_history (id, date, word_id, featured_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(entry._id.to_hex())
+        .bind(entry.date.to_string())
+        .bind(entry.word._id.to_hex())
+        .bind(entry.featured_at.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_by_date(&self, date: NaiveDate) -> Result<Option<WotdHistoryModel>, ApiError> {
+        sqlx::query(&format!(
+            "{HISTORY_SELECT} WHERE wotd_history.date = $1 \
+             ORDER BY wotd_history.featured_at DESC LIMIT 1"
+        ))
+        .bind(date.to_string())
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| history_entry_from_row(&row))
+        .transpose()
+    }
+
+    async fn list(
+        &self,
+        range: DateRange,
+        skip: u64,
+        limit: u64,
+    ) -> Result<Vec<WotdHistoryModel>, ApiError> {
+        sqlx::query(&format!(
+            "{HISTORY_SELECT} WHERE {HISTORY_RANGE} \
+             ORDER BY wotd_history.date DESC, wotd_history.featured_at DESC LIMIT $3 OFFSET $4"
+        ))
+        .bind(range.from.map(|from| from.to_string()))
+        .bind(range.to.map(|to| to.to_string()))
+        .bind(i64::try_from(limit).map_err(ApiError::internal)?)
+        .bind(i64::try_from(skip).map_err(ApiError::internal)?)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(history_entry_from_row)
+        .collect()
+    }
+
+    async fn count(&self, range: DateRange) -> Result<u64, ApiError> {
+        let count: i64 = sqlx::query(&format!(
+            "SELECT COUNT(*) AS count FROM wotd_history WHERE {HISTORY_RANGE}"
+        ))
+        .bind(range.from.map(|from| from.to_string()))
+        .bind(range.to.map(|to| to.to_string()))
+        .fetch_one(&self.pool)
+        .await?
+        .try_get("count")?;
+        u64::try_from(count).map_err(ApiError::internal)
+    }
+
+    /// Nothing to do, entries refer to the rows of `words` like the queue does, see
+    /// [`SqlQueueRepository::anonymise_creator`].
+    async fn anonymise_creator(&self, _user_id: ObjectId) -> Result<u64, ApiError> {
+        Ok(0)
+    }
+}
+
+pub struct SqlRotationRepository {
+    pool: AnyPool,
+}
+
+impl SqlRotationRepository {
+    pub fn new(pool: AnyPool) -> SqlRotationRepository {
+        SqlRotationRepository { pool }
+    }
+}
+
+#[async_trait]
+impl RotationRepository for SqlRotationRepository {
+    /// The upsert leaves the row alone when another holder has the lease, so nothing changes.
+    async fn acquire_lease(
+        &self,
+        holder: &str,
+        now: DateTime,
+        expires_at: DateTime,
+    ) -> Result<bool, ApiError> {
+        let result = sqlx::query(
+            "INSERT INTO rotation_leases (name, holder, expires_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (name) DO UPDATE \
+             SET holder = excluded.holder, expires_at = excluded.expires_at \
+             WHERE rotation_leases.expires_at <= $4 OR rotation_leases.holder = $2",
+        )
+        .bind(ROTATION_LEASE)
+        .bind(holder)
+        .bind(expires_at.timestamp_millis())
+        .bind(now.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_run(&self, run: &RotationRunModel) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO rotation_runs (id, holder, date, started_at, finished_at, outcome) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(run._id.to_hex())
+        .bind(&run.holder)
+        .bind(run.date.to_string())
+        .bind(run.started_at.timestamp_millis())
+        .bind(run.finished_at.timestamp_millis())
+        .bind(serde_json::to_string(&run.outcome).map_err(ApiError::internal)?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn last_run(&self) -> Result<Option<RotationRunModel>, ApiError> {
+        let Some(row) = sqlx::query("SELECT * FROM rotation_runs ORDER BY started_at DESC LIMIT 1")
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(RotationRunModel {
+            _id: object_id(&row, "id")?,
+            holder: row.try_get("holder")?,
+            date: row
+                .try_get::<String, _>("date")?
+                .parse()
+                .map_err(ApiError::internal)?,
+            started_at: date(&row, "started_at")?,
+            finished_at: date(&row, "finished_at")?,
+            outcome: serde_json::from_str(&row.try_get::<String, _>("outcome")?)
+                .map_err(ApiError::internal)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod word_sql_tests {
+    use super::*;
+    use sqlx::any::AnyPoolOptions;
+
+    async fn test_pool() -> AnyPool {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn word(word: &str) -> WordModel {
+        WordModel {
+            _id: ObjectId::new(),
+            created_by_id: Some(ObjectId::new()),
+            word: word.to_string(),
+            definition: "a definition".to_string(),
+            sentence: format!("A sentence with {word}."),
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    fn item(word: WordModel, added_at: i64) -> QueueItemWordModel {
+        QueueItemWordModel {
+            _id: ObjectId::new(),
+            word,
+            added_at: DateTime::from_millis(added_at),
+            rank: added_at,
+            suggested_by_id: Some(ObjectId::new()),
+            pinned_for: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn words_and_queued_words_are_unique() {
+        let pool = test_pool().await;
+        let words = SqlWordRepository::new(pool.clone());
+        let queue = SqlQueueRepository::new(pool);
+        let petrichor = word("petrichor");
+        words.insert(&petrichor).await.unwrap();
+
+        assert!(words
+            .insert(&word("petrichor"))
+            .await
+            .unwrap_err()
+            .is_duplicate_key());
+        let queued = queue.enqueue(&item(word("petrichor"), 1)).await.unwrap();
+        assert_eq!(petrichor._id, queued.word._id);
+        assert!(queue
+            .enqueue(&item(word("petrichor"), 2))
+            .await
+            .unwrap_err()
+            .is_duplicate_key());
+    }
+
+    #[tokio::test]
+    async fn failed_enqueue_leaves_nothing_behind() {
+        let pool = test_pool().await;
+        let words = SqlWordRepository::new(pool.clone());
+        let queue = SqlQueueRepository::new(pool);
+        let duplicate = item(word("sonder"), 1);
+        queue.enqueue(&duplicate).await.unwrap();
+
+        let err = queue
+            .enqueue(&QueueItemWordModel {
+                word: word("petrichor"),
+                ..duplicate
+            })
+            .await
+            .unwrap_err();
+
+        assert!(err.is_duplicate_key());
+        assert!(words.find_by_word("petrichor").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn peek_is_the_oldest_item() {
+        let queue = SqlQueueRepository::new(test_pool().await);
+        for (name, added_at) in [("sonder", 20), ("petrichor", 10)] {
+            queue.enqueue(&item(word(name), added_at)).await.unwrap();
+        }
+
+        let today = "2024-02-14".parse().unwrap();
+        let head = queue.peek(today).await.unwrap().unwrap();
+        assert_eq!("petrichor", head.word.word);
+
+        assert!(queue.delete(head._id).await.unwrap());
+        assert_eq!(
+            "sonder",
+            queue.peek(today).await.unwrap().unwrap().word.word
+        );
+    }
+
+    #[tokio::test]
+    async fn pinned_item_waits_for_its_day() {
+        let queue = SqlQueueRepository::new(test_pool().await);
+        queue.enqueue(&item(word("valentine"), 10)).await.unwrap();
+        queue.enqueue(&item(word("sonder"), 20)).await.unwrap();
+        let valentines_day = "2024-02-14".parse().unwrap();
+
+        let pinned = queue.pin("valentine", valentines_day).await.unwrap();
+
+        assert_eq!(Some(valentines_day), pinned.unwrap().pinned_for);
+        let day_before = queue.peek("2024-02-13".parse().unwrap()).await.unwrap();
+        assert_eq!("sonder", day_before.unwrap().word.word);
+        let on_the_day = queue.peek(valentines_day).await.unwrap();
+        assert_eq!("valentine", on_the_day.unwrap().word.word);
+        let err = queue.pin("sonder", valentines_day).await.unwrap_err();
+        assert!(err.is_duplicate_key());
+        assert!(queue.unpin(valentines_day).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn history_is_listed_newest_first_within_range() {
+        let pool = test_pool().await;
+        let queue = SqlQueueRepository::new(pool.clone());
+        let history = SqlHistoryRepository::new(pool);
+        for (name, date) in [
+            ("sonder", "2024-02-13"),
+            ("valentine", "2024-02-14"),
+            ("petrichor", "2024-02-15"),
+        ] {
+            let queued = queue.enqueue(&item(word(name), 1)).await.unwrap();
+            let entry = WotdHistoryModel {
+                _id: ObjectId::new(),
+                date: date.parse().unwrap(),
+                word: queued.word,
+                featured_at: DateTime::now(),
+            };
+            history.insert(&entry).await.unwrap();
+        }
+        let range = DateRange {
+            from: Some("2024-02-14".parse().unwrap()),
+            to: None,
+        };
+
+        let listed = history.list(range, 0, 1).await.unwrap();
+
+        assert_eq!(2, history.count(range).await.unwrap());
+        assert_eq!("petrichor", listed[0].word.word);
+        let found = history
+            .find_by_date("2024-02-14".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("valentine", found.word.word);
+    }
+
+    #[tokio::test]
+    async fn lease_is_held_by_one_holder_until_it_expires() {
+        let rotations = SqlRotationRepository::new(test_pool().await);
+        let (now, later) = (DateTime::from_millis(1000), DateTime::from_millis(2000));
+
+        assert!(rotations.acquire_lease("a", now, later).await.unwrap());
+        assert!(!rotations.acquire_lease("b", now, later).await.unwrap());
+        assert!(rotations.acquire_lease("a", now, later).await.unwrap());
+        assert!(rotations
+            .acquire_lease("b", later, DateTime::from_millis(3000))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn anonymised_words_have_no_creator() {
+        let words = SqlWordRepository::new(test_pool().await);
+        let petrichor = word("petrichor");
+        words.insert(&petrichor).await.unwrap();
+
+        let anonymised = words
+            .anonymise_creator(petrichor.created_by_id.unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(1, anonymised);
+        let found = words.find_by_word("petrichor").await.unwrap().unwrap();
+        assert_eq!(None, found.created_by_id);
+    }
+
+    #[tokio::test]
+    async fn rank_decides_the_order() {
+        let queue = SqlQueueRepository::new(test_pool().await);
+        queue.enqueue(&item(word("sonder"), 10)).await.unwrap();
+        let petrichor = queue.enqueue(&item(word("petrichor"), 20)).await.unwrap();
+
+        assert!(queue.set_rank(petrichor._id, 5).await.unwrap());
+
+        let listed = queue.list().await.unwrap();
+        assert_eq!("petrichor", listed[0].word.word);
+        assert_eq!(5, listed[0].rank);
+        assert_eq!(petrichor.suggested_by_id, listed[0].suggested_by_id);
+        let head = queue.peek("2024-02-14".parse().unwrap()).await.unwrap();
+        assert_eq!("petrichor", head.unwrap().word.word);
+        assert!(!queue.set_rank(ObjectId::new(), 1).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ranks_are_set_together() {
+        let queue = SqlQueueRepository::new(test_pool().await);
+        let sonder = queue.enqueue(&item(word("sonder"), 10)).await.unwrap();
+        let petrichor = queue.enqueue(&item(word("petrichor"), 20)).await.unwrap();
+
+        queue
+            .set_ranks(&[
+                (sonder._id, 2000),
+                (petrichor._id, 1000),
+                (ObjectId::new(), 3000),
+            ])
+            .await
+            .unwrap();
+
+        let listed: Vec<_> = queue
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.word.word, item.rank))
+            .collect();
+        assert_eq!(
+            vec![
+                ("petrichor".to_string(), 1000),
+                ("sonder".to_string(), 2000)
+            ],
+            listed
+        );
+    }
+}